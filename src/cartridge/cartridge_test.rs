@@ -0,0 +1,453 @@
+use crate::cartridge::Cartridge;
+use crate::nes::memory::{MappedMemory, Memory};
+use crate::ppu::vram::mirrored_nametables;
+use crate::rom::{CHR_ROM_SIZE, MirrorType, PRG_ROM_SIZE, RomFile};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Builds an iNES ROM with the given mapper number and bank counts. PRG ROM is
+// filled with the number of each 8 KB chunk, and CHR ROM with the number of
+// each 1 KB chunk, so that tests can tell which bank is mapped in.
+fn new_rom(mapper: u8, prg_banks: u8, chr_banks: u8, flags_6: u8) -> RomFile {
+    let mut rom = vec![
+        0x4e,
+        0x45,
+        0x53,
+        0x1a,
+        prg_banks,
+        chr_banks,
+        (mapper << 4) | flags_6,
+        mapper & 0xf0,
+    ];
+    rom.resize(16, 0x00);
+    for chunk in 0..(prg_banks as usize * PRG_ROM_SIZE / 0x2000) {
+        rom.extend_from_slice(&[chunk as u8; 0x2000]);
+    }
+    for chunk in 0..(chr_banks as usize * CHR_ROM_SIZE / 0x0400) {
+        rom.extend_from_slice(&[chunk as u8; 0x0400]);
+    }
+    RomFile::new_from_buffer("test".to_string(), &rom).unwrap()
+}
+
+#[test]
+fn test_unsupported_mapper() {
+    let rom = new_rom(0xff, 1, 1, 0x00);
+    let error = Cartridge::new(&rom).err().unwrap();
+    assert_eq!(error.to_string(), "Unsupported mapper #255");
+}
+
+#[test]
+fn test_nrom_128() {
+    let mut cartridge = Cartridge::new(&new_rom(0, 1, 1, 0x00)).unwrap();
+
+    // 16 KB of PRG ROM is mirrored into both halves.
+    assert_eq!(cartridge.read(0x8000), 0x00);
+    assert_eq!(cartridge.read(0xbfff), 0x01);
+    assert_eq!(cartridge.read(0xc000), 0x00);
+    assert_eq!(cartridge.read(0xffff), 0x01);
+
+    // PRG ROM can't be written to.
+    cartridge.store(0x8000, 0x12);
+    assert_eq!(cartridge.read(0x8000), 0x00);
+
+    // PRG RAM can.
+    cartridge.store(0x6000, 0x34);
+    cartridge.store(0x7fff, 0x56);
+    assert_eq!(cartridge.read(0x6000), 0x34);
+    assert_eq!(cartridge.read(0x7fff), 0x56);
+}
+
+#[test]
+fn test_battery_save() {
+    let mut cartridge = Cartridge::new(&new_rom(0, 1, 1, 0x02)).unwrap();
+    assert_eq!(cartridge.take_save(), None);
+
+    // Only saved again after a change.
+    cartridge.store(0x6001, 0x12);
+    let save = cartridge.take_save().unwrap();
+    assert_eq!(save.len(), 0x2000);
+    assert_eq!(save[1], 0x12);
+    assert_eq!(cartridge.take_save(), None);
+
+    let mut cartridge = Cartridge::new(&new_rom(0, 1, 1, 0x02)).unwrap();
+    cartridge.load_save(&save);
+    assert_eq!(cartridge.read(0x6001), 0x12);
+    assert_eq!(cartridge.take_save(), None);
+}
+
+#[test]
+fn test_no_battery_save() {
+    let mut cartridge = Cartridge::new(&new_rom(0, 1, 1, 0x00)).unwrap();
+    cartridge.store(0x6000, 0x12);
+    assert_eq!(cartridge.take_save(), None);
+
+    // Boards without PRG RAM have nothing to save either.
+    let mut cartridge = Cartridge::new(&new_rom(2, 2, 0, 0x02)).unwrap();
+    cartridge.store(0x6000, 0x12);
+    assert_eq!(cartridge.take_save(), None);
+}
+
+#[test]
+fn test_nrom_256() {
+    let mut cartridge = Cartridge::new(&new_rom(0, 2, 1, 0x01)).unwrap();
+
+    // 32 KB of PRG ROM fills the whole range.
+    assert_eq!(cartridge.read(0x8000), 0x00);
+    assert_eq!(cartridge.read(0xbfff), 0x01);
+    assert_eq!(cartridge.read(0xc000), 0x02);
+    assert_eq!(cartridge.read(0xffff), 0x03);
+
+    // CHR ROM is visible to the PPU, but can't be written to.
+    assert_eq!(cartridge.fetch_chr(0x1fff), 0x07);
+    cartridge.store_chr(0x1fff, 0x12);
+    assert_eq!(cartridge.fetch_chr(0x1fff), 0x07);
+
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::Vertical)
+    );
+}
+
+// Writes "value" to an MMC1 register, one bit at a time.
+fn write_mmc1(cartridge: &mut Cartridge, address: u16, value: u8) {
+    for bit in 0..5 {
+        cartridge.store(address, (value >> bit) & 0x01);
+    }
+}
+
+#[test]
+fn test_mmc1_shift_register() {
+    let mut cartridge = Cartridge::new(&new_rom(1, 8, 2, 0x00)).unwrap();
+
+    // The register is only written on the fifth write.
+    for bit in [1, 1, 0, 0] {
+        cartridge.store(0x8000, bit);
+    }
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::SingleScreenLower)
+    );
+    cartridge.store(0x8000, 0x00);
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::Horizontal)
+    );
+
+    // Writing with bit 7 set starts over.
+    cartridge.store(0x8000, 0x01);
+    cartridge.store(0x8000, 0x80);
+    write_mmc1(&mut cartridge, 0x8000, 0x02);
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::Vertical)
+    );
+    write_mmc1(&mut cartridge, 0x9fff, 0x01);
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::SingleScreenUpper)
+    );
+}
+
+#[test]
+fn test_mmc1_prg_banks() {
+    let mut cartridge = Cartridge::new(&new_rom(1, 8, 2, 0x00)).unwrap();
+
+    // On power up, the last bank is fixed at $C000.
+    assert_eq!(cartridge.read(0x8000), 0x00);
+    assert_eq!(cartridge.read(0xc000), 0x0e);
+    write_mmc1(&mut cartridge, 0xe000, 0x03);
+    assert_eq!(cartridge.read(0x8000), 0x06);
+    assert_eq!(cartridge.read(0xc000), 0x0e);
+
+    // Mode 2 fixes the first bank at $8000 and switches $C000.
+    write_mmc1(&mut cartridge, 0x8000, 0x08);
+    assert_eq!(cartridge.read(0x8000), 0x00);
+    assert_eq!(cartridge.read(0xc000), 0x06);
+
+    // 32 KB mode ignores the low bit.
+    write_mmc1(&mut cartridge, 0x8000, 0x00);
+    assert_eq!(cartridge.read(0x8000), 0x04);
+    assert_eq!(cartridge.read(0xc000), 0x06);
+
+    // A reset puts it back in mode 3.
+    cartridge.store(0x8000, 0x80);
+    assert_eq!(cartridge.read(0x8000), 0x06);
+    assert_eq!(cartridge.read(0xc000), 0x0e);
+}
+
+#[test]
+fn test_mmc1_chr_banks() {
+    let mut cartridge = Cartridge::new(&new_rom(1, 2, 4, 0x00)).unwrap();
+
+    // 8 KB mode ignores the low bit of CHR bank 0, and all of CHR bank 1.
+    write_mmc1(&mut cartridge, 0xa000, 0x03);
+    write_mmc1(&mut cartridge, 0xc000, 0x05);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x08);
+    assert_eq!(cartridge.fetch_chr(0x1000), 0x0c);
+
+    // 4 KB mode switches both halves separately.
+    write_mmc1(&mut cartridge, 0x8000, 0x1c);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x0c);
+    assert_eq!(cartridge.fetch_chr(0x1000), 0x14);
+}
+
+#[test]
+fn test_mmc1_prg_ram() {
+    let mut cartridge = Cartridge::new(&new_rom(1, 2, 1, 0x00)).unwrap();
+
+    cartridge.store(0x6000, 0x12);
+    assert_eq!(cartridge.read(0x6000), 0x12);
+
+    // Disabling PRG RAM blocks reads and writes.
+    write_mmc1(&mut cartridge, 0xe000, 0x10);
+    cartridge.store(0x6000, 0x34);
+    assert_eq!(cartridge.read(0x6000), 0x00);
+    write_mmc1(&mut cartridge, 0xe000, 0x00);
+    assert_eq!(cartridge.read(0x6000), 0x12);
+}
+
+#[test]
+fn test_mmc3_banks() {
+    let mut cartridge = Cartridge::new(&new_rom(4, 4, 2, 0x00)).unwrap();
+
+    // PRG mode 0: R6 at $8000, second-last bank at $C000.
+    cartridge.store(0x8000, 0x06);
+    cartridge.store(0x8001, 0x01);
+    cartridge.store(0x8000, 0x07);
+    cartridge.store(0x8001, 0x03);
+    assert_eq!(cartridge.read(0x8000), 0x01);
+    assert_eq!(cartridge.read(0xa000), 0x03);
+    assert_eq!(cartridge.read(0xc000), 0x06);
+    assert_eq!(cartridge.read(0xe000), 0x07);
+
+    // PRG mode 1 swaps $8000 and $C000.
+    cartridge.store(0x8000, 0x40);
+    assert_eq!(cartridge.read(0x8000), 0x06);
+    assert_eq!(cartridge.read(0xc000), 0x01);
+    assert_eq!(cartridge.read(0xe000), 0x07);
+
+    // 2 KB banks ignore the low bit.
+    cartridge.store(0x8000, 0x00);
+    cartridge.store(0x8001, 0x05);
+    cartridge.store(0x8000, 0x05);
+    cartridge.store(0x8001, 0x0a);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x04);
+    assert_eq!(cartridge.fetch_chr(0x0400), 0x05);
+    assert_eq!(cartridge.fetch_chr(0x1c00), 0x0a);
+
+    // CHR inversion swaps the halves.
+    cartridge.store(0x8000, 0x80);
+    assert_eq!(cartridge.fetch_chr(0x1000), 0x04);
+    assert_eq!(cartridge.fetch_chr(0x0c00), 0x0a);
+}
+
+#[test]
+fn test_mmc3_mirroring_and_prg_ram() {
+    let mut cartridge = Cartridge::new(&new_rom(4, 2, 1, 0x00)).unwrap();
+
+    cartridge.store(0xa000, 0x00);
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::Vertical)
+    );
+    cartridge.store(0xa000, 0x01);
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::Horizontal)
+    );
+
+    cartridge.store(0x6000, 0x12);
+    assert_eq!(cartridge.read(0x6000), 0x12);
+
+    // Write protected.
+    cartridge.store(0xa001, 0xc0);
+    cartridge.store(0x6000, 0x34);
+    assert_eq!(cartridge.read(0x6000), 0x12);
+
+    // Disabled.
+    cartridge.store(0xa001, 0x00);
+    assert_eq!(cartridge.read(0x6000), 0x00);
+}
+
+// Simulates the pattern fetches the PPU makes for one scanline, with the
+// background at $0000 and sprites at $1000.
+fn mmc3_scanline(cartridge: &mut Cartridge) {
+    cartridge.notify_ppu_address(0x0000);
+    cartridge.notify_ppu_address(0x1000);
+}
+
+#[test]
+fn test_mmc3_irq() {
+    let mut cartridge = Cartridge::new(&new_rom(4, 2, 1, 0x00)).unwrap();
+
+    // Reload with 2, and enable IRQs.
+    cartridge.store(0xc000, 0x02);
+    cartridge.store(0xc001, 0x00);
+    cartridge.store(0xe001, 0x00);
+
+    // Reload on the first scanline, then count down.
+    mmc3_scanline(&mut cartridge);
+    assert!(!cartridge.irq());
+    mmc3_scanline(&mut cartridge);
+    assert!(!cartridge.irq());
+    mmc3_scanline(&mut cartridge);
+    assert!(cartridge.irq());
+
+    // A12 staying high doesn't clock the counter.
+    cartridge.notify_ppu_address(0x1000);
+    cartridge.notify_ppu_address(0x1008);
+
+    // Acknowledge and disable.
+    cartridge.store(0xe000, 0x00);
+    assert!(!cartridge.irq());
+
+    // The counter reloads when it hits 0, but with IRQs disabled nothing
+    // happens.
+    for _ in 0..3 {
+        mmc3_scanline(&mut cartridge);
+    }
+    assert!(!cartridge.irq());
+}
+
+#[test]
+fn test_uxrom() {
+    let mut cartridge = Cartridge::new(&new_rom(2, 8, 0, 0x00)).unwrap();
+
+    // The last bank is fixed at $C000.
+    assert_eq!(cartridge.read(0x8000), 0x00);
+    assert_eq!(cartridge.read(0xc000), 0x0e);
+
+    // No bus conflicts, even though the ROM byte here is 0.
+    cartridge.store(0x8000, 0x05);
+    assert_eq!(cartridge.read(0x8000), 0x0a);
+    assert_eq!(cartridge.read(0xbfff), 0x0b);
+    assert_eq!(cartridge.read(0xffff), 0x0f);
+}
+
+#[test]
+fn test_uxrom_submapper_bus_conflicts() {
+    // NES 2.0 submapper 2 marks a board with bus conflicts. The ROM byte at
+    // $C000 is 0x0e, so bit 0 gets lost.
+    let mut rom = new_rom(2, 8, 0, 0x00);
+    rom.submapper = 2;
+    let mut cartridge = Cartridge::new(&rom).unwrap();
+    cartridge.store(0xc000, 0x07);
+    assert_eq!(cartridge.read(0x8000), 0x0c);
+}
+
+#[test]
+fn test_cnrom_bus_conflicts() {
+    let mut cartridge = Cartridge::new(&new_rom(3, 2, 4, 0x00)).unwrap();
+
+    // The latched value is ANDed with the ROM byte, which is 3 at $E000.
+    cartridge.store(0xe000, 0x02);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x10);
+    cartridge.store(0xe000, 0x03);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x18);
+
+    // At $A000 the ROM byte is 1, so bit 1 gets lost.
+    cartridge.store(0xa000, 0x03);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x08);
+}
+
+#[test]
+fn test_axrom() {
+    let mut cartridge = Cartridge::new(&new_rom(7, 8, 0, 0x00)).unwrap();
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::SingleScreenLower)
+    );
+    assert_eq!(cartridge.read(0xfffc), 0x03);
+
+    cartridge.store(0x8000, 0x13);
+    assert_eq!(
+        cartridge.nametables(),
+        mirrored_nametables(MirrorType::SingleScreenUpper)
+    );
+    assert_eq!(cartridge.read(0x8000), 0x0c);
+    assert_eq!(cartridge.read(0xffff), 0x0f);
+}
+
+#[test]
+fn test_color_dreams_and_gxrom() {
+    // Both have bus conflicts, so make sure the ROM byte written over has all
+    // bits set.
+    let mut rom = new_rom(11, 8, 4, 0x00);
+    rom.prg_rom_data[1][0x3fff] = 0xff;
+    let mut cartridge = Cartridge::new(&rom).unwrap();
+    cartridge.store(0xffff, 0x31);
+    assert_eq!(cartridge.read(0x8000), 0x04);
+    assert_eq!(cartridge.fetch_chr(0x0400), 0x19);
+
+    let mut rom = new_rom(66, 8, 4, 0x00);
+    rom.prg_rom_data[1][0x3fff] = 0xff;
+    let mut cartridge = Cartridge::new(&rom).unwrap();
+    cartridge.store(0xffff, 0x13);
+    assert_eq!(cartridge.read(0x8000), 0x04);
+    assert_eq!(cartridge.fetch_chr(0x0400), 0x19);
+}
+
+#[test]
+fn test_mapper_34() {
+    // BNROM has CHR RAM.
+    let mut cartridge = Cartridge::new(&new_rom(34, 8, 0, 0x00)).unwrap();
+    cartridge.store(0xffff, 0x01);
+    assert_eq!(cartridge.read(0x8000), 0x04);
+
+    // NINA-001 has CHR ROM, and registers at $7FFD-$7FFF.
+    let mut cartridge = Cartridge::new(&new_rom(34, 4, 8, 0x00)).unwrap();
+    cartridge.store(0x7ffd, 0x01);
+    cartridge.store(0x7ffe, 0x03);
+    cartridge.store(0x7fff, 0x0f);
+    assert_eq!(cartridge.read(0x8000), 0x04);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x0c);
+    assert_eq!(cartridge.fetch_chr(0x1000), 0x3c);
+    assert_eq!(cartridge.read(0x7fff), 0x0f);
+}
+
+#[test]
+fn test_chr_ram() {
+    // No CHR ROM means 8 KB of CHR RAM.
+    let mut cartridge = Cartridge::new(&new_rom(2, 2, 0, 0x00)).unwrap();
+    assert_eq!(cartridge.store_chr(0x0000, 0x12), 0x00);
+    assert_eq!(cartridge.store_chr(0x1fff, 0x34), 0x00);
+    assert_eq!(cartridge.fetch_chr(0x0000), 0x12);
+    assert_eq!(cartridge.fetch_chr(0x1fff), 0x34);
+
+    // CHR ROM stays read-only.
+    let mut cartridge = Cartridge::new(&new_rom(2, 2, 1, 0x00)).unwrap();
+    assert_eq!(cartridge.store_chr(0x0400, 0x12), 0x01);
+    assert_eq!(cartridge.fetch_chr(0x0400), 0x01);
+
+    // NES 2.0 headers can declare CHR RAM smaller than a bank, which then
+    // repeats.
+    for mapper in [1, 4] {
+        let mut rom = new_rom(mapper, 2, 0, 0x00);
+        rom.chr_ram_size = 0x80;
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.store_chr(0x1fff, 0x12), 0x00);
+        assert_eq!(cartridge.fetch_chr(0x007f), 0x12);
+    }
+}
+
+#[test]
+fn test_open_bus() {
+    let cartridge = Cartridge::new(&new_rom(1, 2, 1, 0x00)).unwrap();
+    let cartridge = Rc::new(RefCell::new(cartridge));
+    let mut memory = MappedMemory::new();
+    memory.add_mapping(cartridge.clone(), 0x4020.., 0x4020..);
+
+    // Nothing on the board drives the bus at $4020-$5FFF.
+    memory.store(0x6000, 0x12);
+    assert_eq!(memory.read(0x5000), 0x12);
+    assert_eq!(memory.peek(0x4020), 0x12);
+    assert_eq!(memory.read(0x6000), 0x12);
+
+    // Nor in $6000-$7FFF with PRG RAM disabled.
+    write_mmc1(&mut cartridge.borrow_mut(), 0xe000, 0x10);
+    assert_eq!(memory.read(0x8000), 0x00);
+    assert_eq!(memory.read(0x6000), 0x00);
+    memory.store(0x6000, 0x34);
+    assert_eq!(memory.read(0x6000), 0x34);
+    assert_eq!(memory.peek(0x7fff), 0x34);
+}
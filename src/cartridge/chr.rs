@@ -0,0 +1,58 @@
+use crate::rom::RomFile;
+
+// Boards without CHR ROM have this much CHR RAM, unless the header says
+// otherwise.
+pub const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+
+// The memory a cartridge maps into the PPU's pattern tables. This is either
+// CHR ROM from the ROM file, or CHR RAM for boards without any CHR ROM, where
+// the game uploads its tiles at runtime.
+pub struct Chr {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    pub fn new(rom: &RomFile) -> Chr {
+        if rom.chr_rom_data.is_empty() {
+            Chr::new_ram(match rom.chr_ram_size + rom.chr_nvram_size {
+                0 => DEFAULT_CHR_RAM_SIZE,
+                size => size,
+            })
+        } else {
+            Chr {
+                data: rom.chr_rom_data.concat(),
+                writable: false,
+            }
+        }
+    }
+
+    // Builds "size" bytes of CHR RAM.
+    pub fn new_ram(size: usize) -> Chr {
+        Chr {
+            data: vec![0x00; size],
+            writable: true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    // Fetches the byte at "index", wrapping around if the index is past the
+    // end of CHR memory.
+    pub fn fetch(&self, index: usize) -> u8 {
+        self.data[index % self.data.len()]
+    }
+
+    // Stores a byte at "index" if this is CHR RAM. Writes to CHR ROM are
+    // ignored. Returns the previous value.
+    pub fn store(&mut self, index: usize, value: u8) -> u8 {
+        let index = index % self.data.len();
+        let old_value = self.data[index];
+        if self.writable {
+            self.data[index] = value;
+        }
+        old_value
+    }
+}
@@ -0,0 +1,208 @@
+use crate::cartridge::Mapper;
+use crate::cartridge::chr::Chr;
+use crate::cartridge::prg_ram::PrgRam;
+use crate::rom::{MirrorType, RomFile};
+
+const PRG_BANK_SIZE_16K: usize = 0x4000;
+const PRG_BANK_SIZE_32K: usize = 0x8000;
+const CHR_BANK_SIZE_4K: usize = 0x1000;
+const CHR_BANK_SIZE_8K: usize = 0x2000;
+
+// The boards handled by "Discrete". They're all made from off-the-shelf
+// logic chips rather than a custom mapper chip, and most are nothing more
+// than a latch that is written through the PRG ROM address range.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Board {
+    // Mapper 2: 16 KB switchable PRG bank at $8000, last bank fixed at
+    // $C000.
+    UxRom,
+    // Mapper 3: 8 KB switchable CHR bank.
+    CnRom,
+    // Mapper 7: 32 KB switchable PRG bank, and single-screen mirroring with
+    // the nametable page selected by bit 4.
+    AxRom,
+    // Mapper 11: 32 KB PRG bank in bits 0-1, 8 KB CHR bank in bits 4-7.
+    ColorDreams,
+    // Mapper 34, with CHR RAM: 32 KB switchable PRG bank.
+    BnRom,
+    // Mapper 34, with CHR ROM: 32 KB PRG bank and two 4 KB CHR banks,
+    // selected through registers at $7FFD-$7FFF.
+    Nina001,
+    // Mapper 66: 32 KB PRG bank in bits 4-5, 8 KB CHR bank in bits 0-1.
+    GxRom,
+}
+
+// Discrete logic mappers (2, 3, 7, 11, 34 and 66).
+//
+// Boards that latch writes to ROM space suffer from bus conflicts: the ROM
+// drives the data bus at the same time as the CPU, so the value that ends up
+// latched is the CPU's value ANDed with the byte in ROM at that address.
+// Games avoid this by writing to a location that holds the same value.
+//
+// See http://wiki.nesdev.com/w/index.php/Bus_conflict for more details.
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+    mirror_type: MirrorType,
+    bus_conflicts: bool,
+
+    prg_bank: usize,
+    // The CHR bank at $0000, and for NINA-001 the 4 KB bank at $1000.
+    chr_banks: [usize; 2],
+}
+
+impl Discrete {
+    pub fn new(rom: &RomFile, board: Board) -> Discrete {
+        let mirror_type = match board {
+            Board::AxRom => MirrorType::SingleScreenLower,
+            _ => rom.mirror_type,
+        };
+
+        // UxROM and AxROM both come in variants with and without bus
+        // conflicts. Games written for the conflict-free boards may not
+        // avoid them, so unless an NES 2.0 submapper says which variant it
+        // is, conflicts are only emulated for boards that always have them.
+        let bus_conflicts = match (board, rom.submapper) {
+            (Board::UxRom | Board::CnRom | Board::AxRom, 1) => false,
+            (Board::UxRom | Board::CnRom | Board::AxRom, 2) => true,
+            _ => matches!(
+                board,
+                Board::CnRom | Board::ColorDreams | Board::BnRom | Board::GxRom
+            ),
+        };
+
+        Discrete {
+            board,
+            prg_rom: rom.prg_rom_data.concat(),
+            prg_ram: PrgRam::new(rom),
+            chr: Chr::new(rom),
+            mirror_type,
+            bus_conflicts,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    // Maps a CPU address in $8000-$FFFF into PRG ROM.
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let offset = address as usize - 0x8000;
+        let index = match self.board {
+            Board::UxRom => {
+                let bank_count = self.prg_rom.len() / PRG_BANK_SIZE_16K;
+                let bank = if address >= 0xc000 {
+                    bank_count.saturating_sub(1)
+                } else {
+                    self.prg_bank
+                };
+                bank * PRG_BANK_SIZE_16K + (offset & (PRG_BANK_SIZE_16K - 1))
+            }
+            Board::CnRom => offset,
+            _ => self.prg_bank * PRG_BANK_SIZE_32K + offset,
+        };
+        index % self.prg_rom.len()
+    }
+
+    // Maps a PPU address in $0000-$1FFF into CHR memory.
+    fn chr_index(&self, address: u16) -> usize {
+        let index = match self.board {
+            Board::Nina001 => {
+                let bank = self.chr_banks[usize::from(address >> 12)];
+                bank * CHR_BANK_SIZE_4K
+                    + (address as usize & (CHR_BANK_SIZE_4K - 1))
+            }
+            _ => self.chr_banks[0] * CHR_BANK_SIZE_8K + address as usize,
+        };
+        index % self.chr.len()
+    }
+
+    // Latches a value written to $8000-$FFFF.
+    fn write_latch(&mut self, value: u8) {
+        let value = usize::from(value);
+        match self.board {
+            Board::UxRom => self.prg_bank = value,
+            Board::CnRom => self.chr_banks[0] = value,
+            Board::AxRom => {
+                self.prg_bank = value & 0x07;
+                self.mirror_type = if value & 0x10 == 0 {
+                    MirrorType::SingleScreenLower
+                } else {
+                    MirrorType::SingleScreenUpper
+                };
+            }
+            Board::ColorDreams => {
+                self.prg_bank = value & 0x03;
+                self.chr_banks[0] = value >> 4;
+            }
+            Board::BnRom => self.prg_bank = value,
+            Board::Nina001 => (),
+            Board::GxRom => {
+                self.prg_bank = (value >> 4) & 0x03;
+                self.chr_banks[0] = value & 0x03;
+            }
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.board == Board::Nina001 => {
+                Some(self.prg_ram.fetch(address as usize - 0x6000))
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn store_prg(&mut self, address: u16, value: u8) -> u8 {
+        let old_value = self.peek_prg(address).unwrap_or(0x00);
+        match address {
+            // NINA-001's registers sit on top of the end of PRG RAM, so the
+            // write goes to both.
+            0x6000..=0x7fff if self.board == Board::Nina001 => {
+                self.prg_ram.store(address as usize - 0x6000, value);
+                let value = usize::from(value);
+                match address {
+                    0x7ffd => self.prg_bank = value & 0x01,
+                    0x7ffe => self.chr_banks[0] = value & 0x0f,
+                    0x7fff => self.chr_banks[1] = value & 0x0f,
+                    _ => (),
+                }
+            }
+            0x8000..=0xffff if self.board != Board::Nina001 => {
+                let value = if self.bus_conflicts {
+                    value & old_value
+                } else {
+                    value
+                };
+                self.write_latch(value);
+            }
+            _ => (),
+        }
+        old_value
+    }
+
+    fn fetch_chr(&self, address: u16) -> u8 {
+        self.chr.fetch(self.chr_index(address))
+    }
+
+    fn store_chr(&mut self, address: u16, value: u8) -> u8 {
+        let index = self.chr_index(address);
+        self.chr.store(index, value)
+    }
+
+    fn mirror_type(&self) -> MirrorType {
+        self.mirror_type
+    }
+
+    // Only NINA-001 has PRG RAM.
+    fn prg_ram(&mut self) -> Option<&mut PrgRam> {
+        if self.board == Board::Nina001 {
+            Some(&mut self.prg_ram)
+        } else {
+            None
+        }
+    }
+}
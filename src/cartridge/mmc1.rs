@@ -0,0 +1,193 @@
+use crate::cartridge::Mapper;
+use crate::cartridge::chr::Chr;
+use crate::cartridge::prg_ram::PrgRam;
+use crate::rom::{MirrorType, RomFile};
+
+// Writes with bit 7 set reset the shift register.
+const SHIFT_RESET: u8 = 0b1000_0000;
+
+// Control: Nametable mirroring (0 = one-screen lower, 1 = one-screen upper,
+// 2 = vertical, 3 = horizontal).
+const CONTROL_MIRRORING: u8 = 0b0_0011;
+// Control: PRG ROM bank mode (0/1 = switch 32 KB at $8000, 2 = fix first bank
+// at $8000 and switch 16 KB at $C000, 3 = fix last bank at $C000 and switch
+// 16 KB at $8000).
+const CONTROL_PRG_MODE: u8 = 0b0_1100;
+// Control: CHR ROM bank mode (0 = switch 8 KB, 1 = switch two 4 KB banks).
+const CONTROL_CHR_MODE: u8 = 0b1_0000;
+
+// PRG bank: Select 16 KB PRG ROM bank.
+const PRG_BANK_SELECT: u8 = 0b0_1111;
+// PRG bank: PRG RAM chip enable (0 = enabled, 1 = disabled).
+const PRG_BANK_RAM_DISABLE: u8 = 0b1_0000;
+
+// On boards with 512 KB of PRG ROM (SUROM), bit 4 of the CHR bank registers
+// selects which 256 KB half of PRG ROM the PRG bank register applies to.
+const CHR_BANK_PRG_HALF: u8 = 0b1_0000;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_HALF_SIZE: usize = 0x40000;
+
+// MMC1 (mapper 1), used by SxROM boards.
+//
+// The CPU writes to the mapper's registers one bit at a time, through a 5-bit
+// shift register mapped to $8000-$FFFF. On the fifth write, the collected
+// value is copied into the register selected by bits 13-14 of the address:
+//
+// $8000-$9FFF: Control (mirroring and bank modes).
+// $A000-$BFFF: CHR bank 0.
+// $C000-$DFFF: CHR bank 1.
+// $E000-$FFFF: PRG bank and PRG RAM enable.
+//
+// See http://wiki.nesdev.com/w/index.php/MMC1 for more details.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+
+    // Bits written so far, shifted in from the top. Once the 1 that starts
+    // out in bit 4 reaches bit 0, the fifth bit is being written.
+    shift: u8,
+
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(rom: &RomFile) -> Mmc1 {
+        Mmc1 {
+            prg_rom: rom.prg_rom_data.concat(),
+            prg_ram: PrgRam::new(rom),
+            chr: Chr::new(rom),
+            shift: 0b1_0000,
+            // PRG mode 3 on power up, so that the reset vector is in the
+            // fixed last bank.
+            control: CONTROL_PRG_MODE,
+            chr_bank_0: 0x00,
+            chr_bank_1: 0x00,
+            prg_bank: 0x00,
+        }
+    }
+
+    // Writes one bit into the shift register, and copies the result into
+    // the register selected by "address" on the fifth write.
+    fn write_shift(&mut self, address: u16, value: u8) {
+        if value & SHIFT_RESET != 0 {
+            self.shift = 0b1_0000;
+            self.control |= CONTROL_PRG_MODE;
+            return;
+        }
+
+        let done = self.shift & 0x01 != 0;
+        self.shift = (self.shift >> 1) | ((value & 0x01) << 4);
+        if done {
+            let register = self.shift;
+            match address {
+                0x8000..=0x9fff => self.control = register,
+                0xa000..=0xbfff => self.chr_bank_0 = register,
+                0xc000..=0xdfff => self.chr_bank_1 = register,
+                _ => self.prg_bank = register,
+            }
+            self.shift = 0b1_0000;
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & PRG_BANK_RAM_DISABLE == 0
+    }
+
+    // Maps a CPU address in $8000-$FFFF into PRG ROM.
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = usize::from(self.prg_bank & PRG_BANK_SELECT);
+        let offset = address as usize & (PRG_BANK_SIZE - 1);
+        let high = address >= 0xc000;
+
+        let bank = match (self.control & CONTROL_PRG_MODE) >> 2 {
+            // 32 KB mode ignores the low bit of the bank number.
+            0 | 1 => (bank & !0x01) | usize::from(high),
+            2 if high => bank,
+            2 => 0,
+            _ if high => 0x0f,
+            _ => bank,
+        };
+
+        // The bank number only reaches 256 KB, SUROM boards pick which half
+        // with the CHR bank register.
+        let half = if self.prg_rom.len() > PRG_HALF_SIZE
+            && self.chr_bank_0 & CHR_BANK_PRG_HALF != 0
+        {
+            PRG_HALF_SIZE
+        } else {
+            0
+        };
+
+        half + (bank % bank_count) * PRG_BANK_SIZE + offset
+    }
+
+    // Maps a PPU address in $0000-$1FFF into CHR memory.
+    fn chr_index(&self, address: u16) -> usize {
+        let offset = address as usize & (CHR_BANK_SIZE - 1);
+        let bank = if self.control & CONTROL_CHR_MODE == 0 {
+            // 8 KB mode ignores the low bit of the bank number.
+            usize::from(self.chr_bank_0 & !0x01) | usize::from(address >> 12)
+        } else if address < 0x1000 {
+            usize::from(self.chr_bank_0)
+        } else {
+            usize::from(self.chr_bank_1)
+        };
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % bank_count) * CHR_BANK_SIZE + offset
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                Some(self.prg_ram.fetch(address as usize - 0x6000))
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn store_prg(&mut self, address: u16, value: u8) -> u8 {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                self.prg_ram.store(address as usize - 0x6000, value)
+            }
+            0x8000..=0xffff => {
+                let old_value = self.prg_rom[self.prg_rom_index(address)];
+                self.write_shift(address, value);
+                old_value
+            }
+            _ => 0x00,
+        }
+    }
+
+    fn fetch_chr(&self, address: u16) -> u8 {
+        self.chr.fetch(self.chr_index(address))
+    }
+
+    fn store_chr(&mut self, address: u16, value: u8) -> u8 {
+        let index = self.chr_index(address);
+        self.chr.store(index, value)
+    }
+
+    fn mirror_type(&self) -> MirrorType {
+        match self.control & CONTROL_MIRRORING {
+            0 => MirrorType::SingleScreenLower,
+            1 => MirrorType::SingleScreenUpper,
+            2 => MirrorType::Vertical,
+            _ => MirrorType::Horizontal,
+        }
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut PrgRam> {
+        Some(&mut self.prg_ram)
+    }
+}
@@ -0,0 +1,232 @@
+use crate::cartridge::Mapper;
+use crate::cartridge::chr::Chr;
+use crate::cartridge::prg_ram::PrgRam;
+use crate::rom::{MirrorType, RomFile};
+
+// Bank select: Which bank register the next bank data write goes to.
+const SELECT_REGISTER: u8 = 0b0000_0111;
+// Bank select: PRG ROM bank mode (0 = R6 at $8000 and the second-last bank
+// at $C000, 1 = the other way around).
+const SELECT_PRG_MODE: u8 = 0b0100_0000;
+// Bank select: CHR A12 inversion (0 = 2 KB banks at $0000, 1 = 2 KB banks at
+// $1000).
+const SELECT_CHR_INVERSION: u8 = 0b1000_0000;
+
+// PRG RAM protect: Deny writes to PRG RAM.
+const PRG_RAM_WRITE_PROTECT: u8 = 0b0100_0000;
+// PRG RAM protect: Enable PRG RAM.
+const PRG_RAM_ENABLE: u8 = 0b1000_0000;
+
+// PPU address line A12, which the scanline counter watches.
+const PPU_A12: u16 = 0x1000;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// MMC3 (mapper 4), used by TxROM boards.
+//
+// CPU $6000-$7FFF: 8 KB PRG RAM, with write protection.
+// CPU $8000-$9FFF: 8 KB switchable PRG ROM bank, or the second-last bank.
+// CPU $A000-$BFFF: 8 KB switchable PRG ROM bank.
+// CPU $C000-$DFFF: The second-last bank, or an 8 KB switchable bank.
+// CPU $E000-$FFFF: 8 KB PRG ROM bank, fixed to the last bank.
+// PPU $0000-$1FFF: Two 2 KB and four 1 KB switchable CHR banks, with the two
+//                  halves of the pattern tables optionally swapped.
+//
+// Registers are mapped in pairs of even and odd addresses across
+// $8000-$FFFF: bank select/data, mirroring/PRG RAM protect, IRQ latch/reload
+// and IRQ disable/enable.
+//
+// The scanline counter is clocked by rising edges of PPU address line A12.
+// With backgrounds using $0000 and sprites using $1000, this happens once per
+// scanline, when the PPU starts fetching sprite patterns.
+//
+// See http://wiki.nesdev.com/w/index.php/MMC3 for more details.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+
+    bank_select: u8,
+    // R0-R7: R0 and R1 are 2 KB CHR banks, R2-R5 are 1 KB CHR banks, and R6
+    // and R7 are 8 KB PRG banks.
+    bank_registers: [u8; 8],
+    mirror_type: MirrorType,
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    // Level of A12 on the last pattern table access.
+    last_a12: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: &RomFile) -> Mmc3 {
+        Mmc3 {
+            prg_rom: rom.prg_rom_data.concat(),
+            prg_ram: PrgRam::new(rom),
+            chr: Chr::new(rom),
+            bank_select: 0x00,
+            bank_registers: [0x00, 0x02, 0x04, 0x05, 0x06, 0x07, 0x00, 0x01],
+            mirror_type: rom.mirror_type,
+            prg_ram_protect: PRG_RAM_ENABLE,
+            irq_latch: 0x00,
+            irq_counter: 0x00,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            last_a12: false,
+        }
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        self.prg_ram_protect & PRG_RAM_ENABLE != 0
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_readable()
+            && self.prg_ram_protect & PRG_RAM_WRITE_PROTECT == 0
+    }
+
+    // Maps a CPU address in $8000-$FFFF into PRG ROM.
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let second_last = bank_count.saturating_sub(2);
+        let swapped = self.bank_select & SELECT_PRG_MODE != 0;
+
+        let bank = match (address >> 13) & 0x03 {
+            0 if swapped => second_last,
+            0 => usize::from(self.bank_registers[6]),
+            1 => usize::from(self.bank_registers[7]),
+            2 if swapped => usize::from(self.bank_registers[6]),
+            2 => second_last,
+            _ => bank_count - 1,
+        };
+        (bank % bank_count) * PRG_BANK_SIZE
+            + (address as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    // Maps a PPU address in $0000-$1FFF into CHR memory.
+    fn chr_index(&self, address: u16) -> usize {
+        // With inversion, the 2 KB banks are in the upper half instead.
+        let address = if self.bank_select & SELECT_CHR_INVERSION != 0 {
+            address ^ 0x1000
+        } else {
+            address
+        };
+
+        let bank = match address >> 10 {
+            0 => self.bank_registers[0] & 0xfe,
+            1 => self.bank_registers[0] | 0x01,
+            2 => self.bank_registers[1] & 0xfe,
+            3 => self.bank_registers[1] | 0x01,
+            slot => self.bank_registers[slot as usize - 2],
+        };
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (usize::from(bank) % bank_count) * CHR_BANK_SIZE
+            + (address as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let even = address & 0x01 == 0;
+        match address {
+            0x8000..=0x9fff if even => self.bank_select = value,
+            0x8000..=0x9fff => {
+                let register = self.bank_select & SELECT_REGISTER;
+                self.bank_registers[register as usize] = value;
+            }
+            0xa000..=0xbfff if even => {
+                // Boards with four-screen VRAM ignore the mirroring register.
+                if self.mirror_type != MirrorType::Both {
+                    self.mirror_type = if value & 0x01 == 0 {
+                        MirrorType::Vertical
+                    } else {
+                        MirrorType::Horizontal
+                    };
+                }
+            }
+            0xa000..=0xbfff => self.prg_ram_protect = value,
+            0xc000..=0xdfff if even => self.irq_latch = value,
+            0xc000..=0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            _ if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    // Clocks the scanline counter, raising an IRQ when it hits 0.
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_readable() => {
+                Some(self.prg_ram.fetch(address as usize - 0x6000))
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn store_prg(&mut self, address: u16, value: u8) -> u8 {
+        let old_value = self.peek_prg(address).unwrap_or(0x00);
+        match address {
+            0x6000..=0x7fff if self.prg_ram_writable() => {
+                self.prg_ram.store(address as usize - 0x6000, value);
+            }
+            0x8000..=0xffff => self.write_register(address, value),
+            _ => (),
+        }
+        old_value
+    }
+
+    fn fetch_chr(&self, address: u16) -> u8 {
+        self.chr.fetch(self.chr_index(address))
+    }
+
+    fn store_chr(&mut self, address: u16, value: u8) -> u8 {
+        let index = self.chr_index(address);
+        self.chr.store(index, value)
+    }
+
+    fn mirror_type(&self) -> MirrorType {
+        self.mirror_type
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut PrgRam> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn notify_ppu_address(&mut self, address: u16) {
+        let a12 = address & PPU_A12 != 0;
+        if a12 && !self.last_a12 {
+            self.clock_irq_counter();
+        }
+        self.last_a12 = a12;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
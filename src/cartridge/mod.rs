@@ -0,0 +1,235 @@
+pub mod chr;
+pub mod discrete;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod prg_ram;
+
+// Tests for cartridges and mappers.
+#[cfg(test)]
+mod cartridge_test;
+
+use crate::cartridge::discrete::{Board, Discrete};
+use crate::cartridge::mmc1::Mmc1;
+use crate::cartridge::mmc3::Mmc3;
+use crate::cartridge::nrom::Nrom;
+use crate::cartridge::prg_ram::PrgRam;
+use crate::nes::memory::Memory;
+use crate::ppu::vram::{NAMETABLE_SIZE, NametableSource, mirrored_nametables};
+use crate::rom::{MirrorType, RomFile};
+use std::io::{Error, ErrorKind, Result};
+
+// The circuitry on a cartridge board that maps the PRG and CHR data into the
+// CPU and PPU address spaces. Most mappers also allow swapping banks of that
+// data in and out at runtime.
+//
+// See http://wiki.nesdev.com/w/index.php/Mapper for more details.
+pub trait Mapper {
+    // Reads a byte from CPU address space ($4020-$FFFF), the way the CPU
+    // does. Returns None where nothing on the board drives the data bus,
+    // which leaves open bus. Default implementation has no side effects, and
+    // just peeks.
+    fn read_prg(&mut self, address: u16) -> Option<u8> {
+        self.peek_prg(address)
+    }
+
+    // Looks at a byte in CPU address space ($4020-$FFFF) without any side
+    // effects, or returns None like read_prg().
+    fn peek_prg(&self, address: u16) -> Option<u8>;
+
+    // Stores a byte into CPU address space ($4020-$FFFF). Returns the
+    // previous value.
+    fn store_prg(&mut self, address: u16, value: u8) -> u8;
+
+    // Fetches a byte from the PPU pattern tables ($0000-$1FFF).
+    fn fetch_chr(&self, address: u16) -> u8;
+
+    // Stores a byte into the PPU pattern tables ($0000-$1FFF). Returns the
+    // previous value.
+    fn store_chr(&mut self, address: u16, value: u8) -> u8;
+
+    // How the nametables are currently mirrored.
+    fn mirror_type(&self) -> MirrorType;
+
+    // Where each of the four nametables ($2000, $2400, $2800 and $2C00) is
+    // currently mapped. Default implementation follows mirror_type().
+    fn nametables(&self) -> [NametableSource; 4] {
+        mirrored_nametables(self.mirror_type())
+    }
+
+    // Number of 1 KB pages of nametable RAM the board provides, for mappers
+    // that map nametables to NametableSource::Cartridge. Four-screen boards
+    // always get at least 2. Default implementation has none.
+    fn nametable_ram_pages(&self) -> usize {
+        0
+    }
+
+    // Called with the address of every PPU access to the pattern tables, in
+    // the order the PPU makes them. Mappers with scanline counters use this
+    // to watch PPU address line A12. Default implementation is a no-op.
+    fn notify_ppu_address(&mut self, _address: u16) {}
+
+    // Whether the mapper is asserting the CPU's IRQ line. Default
+    // implementation never does.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Called after every CPU instruction with the number of cycles it took,
+    // for mappers with timers. Default implementation is a no-op.
+    fn clock_cpu(&mut self, _cycles: u32) {}
+
+    // The board's PRG RAM, for saving and restoring it. Default
+    // implementation has none.
+    fn prg_ram(&mut self) -> Option<&mut PrgRam> {
+        None
+    }
+}
+
+// A cartridge, made up of its ROM data and the mapper that makes that data
+// visible to the CPU and PPU. The cartridge is shared between the CPU's
+// memory map, where it is mapped to $4020-$FFFF, and the PPU.
+pub struct Cartridge {
+    mapper: Box<dyn Mapper>,
+
+    // Extra nametable RAM on the board, such as on four-screen boards, where
+    // the nametables at $2800 and $2C00 are mapped to it.
+    nametable_ram: Vec<u8>,
+}
+
+impl Cartridge {
+    // Builds a cartridge with the mapper declared in the ROM header.
+    pub fn new(rom: &RomFile) -> Result<Cartridge> {
+        let mapper: Box<dyn Mapper> = match rom.mapper {
+            0 => Box::new(Nrom::new(rom)),
+            1 => Box::new(Mmc1::new(rom)),
+            2 => Box::new(Discrete::new(rom, Board::UxRom)),
+            3 => Box::new(Discrete::new(rom, Board::CnRom)),
+            4 => Box::new(Mmc3::new(rom)),
+            7 => Box::new(Discrete::new(rom, Board::AxRom)),
+            11 => Box::new(Discrete::new(rom, Board::ColorDreams)),
+            // Mapper 34 covers two unrelated boards, told apart by the NES 2.0
+            // submapper, or else by whether they have more than 8 KB of CHR
+            // ROM.
+            34 if rom.submapper == 1 => {
+                Box::new(Discrete::new(rom, Board::Nina001))
+            }
+            34 if rom.submapper == 2 => {
+                Box::new(Discrete::new(rom, Board::BnRom))
+            }
+            34 if rom.chr_rom_data.len() > 1 => {
+                Box::new(Discrete::new(rom, Board::Nina001))
+            }
+            34 => Box::new(Discrete::new(rom, Board::BnRom)),
+            66 => Box::new(Discrete::new(rom, Board::GxRom)),
+            number => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Unsupported mapper #{}", number),
+                ));
+            }
+        };
+        Ok(Cartridge::with_mapper(mapper, rom.mirror_type))
+    }
+
+    // Builds a cartridge around an existing mapper.
+    pub fn with_mapper(
+        mapper: Box<dyn Mapper>,
+        mirror_type: MirrorType,
+    ) -> Cartridge {
+        let mut pages = mapper.nametable_ram_pages();
+        if mirror_type == MirrorType::Both {
+            pages = pages.max(2);
+        }
+        Cartridge {
+            mapper,
+            nametable_ram: vec![0x00; pages * NAMETABLE_SIZE],
+        }
+    }
+
+    // Fetches a byte from the pattern tables, for the PPU.
+    pub fn fetch_chr(&self, address: u16) -> u8 {
+        self.mapper.fetch_chr(address)
+    }
+
+    // Stores a byte into the pattern tables, for the PPU.
+    pub fn store_chr(&mut self, address: u16, value: u8) -> u8 {
+        self.mapper.store_chr(address, value)
+    }
+
+    pub fn nametables(&self) -> [NametableSource; 4] {
+        self.mapper.nametables()
+    }
+
+    // Fetches a byte from a page of cartridge nametable RAM, for the PPU.
+    pub fn fetch_nametable(&self, page: usize, offset: u16) -> u8 {
+        self.nametable_ram[page * NAMETABLE_SIZE + offset as usize]
+    }
+
+    // Stores a byte into a page of cartridge nametable RAM, for the PPU.
+    pub fn store_nametable(
+        &mut self,
+        page: usize,
+        offset: u16,
+        value: u8,
+    ) -> u8 {
+        let index = page * NAMETABLE_SIZE + offset as usize;
+        let old_value = self.nametable_ram[index];
+        self.nametable_ram[index] = value;
+        old_value
+    }
+
+    // Lets the mapper see a pattern table address on the PPU's bus.
+    pub fn notify_ppu_address(&mut self, address: u16) {
+        self.mapper.notify_ppu_address(address)
+    }
+
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    pub fn clock_cpu(&mut self, cycles: u32) {
+        self.mapper.clock_cpu(cycles)
+    }
+
+    // Restores battery-backed PRG RAM from a save.
+    pub fn load_save(&mut self, save: &[u8]) {
+        if let Some(prg_ram) = self.mapper.prg_ram() {
+            prg_ram.load(save);
+        }
+    }
+
+    // The contents of battery-backed PRG RAM, if it has changed since the
+    // last save.
+    pub fn take_save(&mut self) -> Option<Vec<u8>> {
+        self.mapper
+            .prg_ram()
+            .and_then(|prg_ram| prg_ram.take_save())
+            .map(|save| save.to_vec())
+    }
+}
+
+// This is how the cartridge is accessed by the CPU. Outside of
+// MappedMemory, which fills in open bus, addresses the board doesn't drive
+// read as 0.
+impl Memory for Cartridge {
+    fn read(&mut self, address: u16) -> u8 {
+        self.read_bus(address).unwrap_or(0x00)
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.peek_bus(address).unwrap_or(0x00)
+    }
+
+    fn read_bus(&mut self, address: u16) -> Option<u8> {
+        self.mapper.read_prg(address)
+    }
+
+    fn peek_bus(&self, address: u16) -> Option<u8> {
+        self.mapper.peek_prg(address)
+    }
+
+    fn store(&mut self, address: u16, value: u8) -> u8 {
+        self.mapper.store_prg(address, value)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::cartridge::Mapper;
+use crate::cartridge::chr::Chr;
+use crate::cartridge::prg_ram::PrgRam;
+use crate::rom::{MirrorType, RomFile};
+
+// NROM (mapper 0), the simplest board with no bank switching at all.
+//
+// CPU $6000-$7FFF: PRG RAM (Family Basic only, but harmless to provide).
+// CPU $8000-$BFFF: First 16 KB of PRG ROM.
+// CPU $C000-$FFFF: Last 16 KB of PRG ROM, or a mirror of $8000-$BFFF for
+//                  NROM-128 boards.
+// PPU $0000-$1FFF: 8 KB of CHR ROM or CHR RAM.
+//
+// See http://wiki.nesdev.com/w/index.php/NROM for more details.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+    mirror_type: MirrorType,
+}
+
+impl Nrom {
+    pub fn new(rom: &RomFile) -> Nrom {
+        Nrom {
+            prg_rom: rom.prg_rom_data.concat(),
+            prg_ram: PrgRam::new(rom),
+            chr: Chr::new(rom),
+            mirror_type: rom.mirror_type,
+        }
+    }
+
+    // Maps a CPU address in $8000-$FFFF into PRG ROM. 16 KB ROMs end up
+    // mirrored into both halves.
+    fn prg_rom_index(&self, address: u16) -> usize {
+        (address as usize - 0x8000) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Nrom {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff => {
+                Some(self.prg_ram.fetch(address as usize - 0x6000))
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn store_prg(&mut self, address: u16, value: u8) -> u8 {
+        match address {
+            0x6000..=0x7fff => {
+                self.prg_ram.store(address as usize - 0x6000, value)
+            }
+            // PRG ROM can't be written to.
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_index(address)],
+            _ => 0x00,
+        }
+    }
+
+    fn fetch_chr(&self, address: u16) -> u8 {
+        self.chr.fetch(address as usize)
+    }
+
+    fn store_chr(&mut self, address: u16, value: u8) -> u8 {
+        self.chr.store(address as usize, value)
+    }
+
+    fn mirror_type(&self) -> MirrorType {
+        self.mirror_type
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut PrgRam> {
+        Some(&mut self.prg_ram)
+    }
+}
@@ -0,0 +1,60 @@
+use crate::rom::{PRG_RAM_SIZE, RomFile};
+
+// Work RAM on a cartridge board, mapped into the CPU at $6000-$7FFF. Boards
+// with a battery keep it powered while the console is off, which is how most
+// games with saves keep them, so its contents get saved to a file.
+pub struct PrgRam {
+    data: Vec<u8>,
+    battery: bool,
+    // Whether the RAM has changed since it was last saved.
+    dirty: bool,
+}
+
+impl PrgRam {
+    // Sized from the header, but never less than 8 KB, since plenty of iNES
+    // headers leave it out.
+    pub fn new(rom: &RomFile) -> PrgRam {
+        PrgRam {
+            data: vec![
+                0x00;
+                (rom.prg_ram_size + rom.prg_nvram_size).max(PRG_RAM_SIZE)
+            ],
+            battery: rom.battery,
+            dirty: false,
+        }
+    }
+
+    // Fetches the byte at "index", wrapping around if the index is past the
+    // end of PRG RAM.
+    pub fn fetch(&self, index: usize) -> u8 {
+        self.data[index % self.data.len()]
+    }
+
+    // Stores a byte at "index", wrapping around like fetch(). Returns the
+    // previous value.
+    pub fn store(&mut self, index: usize, value: u8) -> u8 {
+        let index = index % self.data.len();
+        let old_value = self.data[index];
+        self.data[index] = value;
+        self.dirty |= old_value != value;
+        old_value
+    }
+
+    // Restores the contents from a save. Saves of a different size fill in
+    // as much as they can.
+    pub fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.data.len());
+        self.data[..size].copy_from_slice(&save[..size]);
+        self.dirty = false;
+    }
+
+    // The contents to save, if the RAM is battery-backed and has changed
+    // since the last time.
+    pub fn take_save(&mut self) -> Option<&[u8]> {
+        if !self.battery || !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        Some(&self.data)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::cpu::opcode::Opcode;
 use crate::cpu::opcode::Opcode::*;
 #[allow(unused_imports)]
 use crate::cpu::{
@@ -17,11 +18,7 @@ use crate::cpu::{
 use crate::nes::memory::BasicMemory;
 
 fn new_cpu() -> Cpu {
-    Cpu::new(
-        Box::new(BasicMemory::with_default_size()),
-        Option::None,
-        Option::None,
-    )
+    Cpu::new(Box::new(BasicMemory::with_default_size()), Option::None)
 }
 
 #[test]
@@ -271,7 +268,7 @@ fn test_asl() {
                 "Bad flag: {:08b}",
                 cpu.registers.p.0
             );
-            let shift_result = cpu.memory.fetch(*addr);
+            let shift_result = cpu.memory.read(*addr);
             assert!(
                 shift_result == (asl_result.0 << 1),
                 "Bad shift result {:#04x} in memory {:#06x}",
@@ -703,7 +700,7 @@ fn test_dec() {
         // Execute and make sure value was incremented.
         cpu.execute();
         assert!(
-            val - 1 == cpu.memory.fetch(*addr as u16),
+            val - 1 == cpu.memory.read(*addr as u16),
             "Bad value loaded from addr {:#06x}",
             addr
         );
@@ -877,7 +874,7 @@ fn test_inc() {
         // Execute and make sure value was incremented.
         cpu.execute();
         assert!(
-            val + 1 == cpu.memory.fetch(*addr as u16),
+            val + 1 == cpu.memory.read(*addr as u16),
             "Bad value loaded from addr {:#06x}",
             addr
         );
@@ -1175,7 +1172,7 @@ fn test_lsr() {
                 "Bad flag: {:08b}",
                 cpu.registers.p.0
             );
-            let shift_result = cpu.memory.fetch(*addr);
+            let shift_result = cpu.memory.read(*addr);
             assert!(
                 shift_result == (lsr_result.0 >> 1),
                 "Bad shift result {:#04x} in memory {:#06x}",
@@ -1412,7 +1409,7 @@ fn test_rol() {
                 cpu.registers.p.0,
                 *addr
             );
-            let value = cpu.memory.fetch(*addr);
+            let value = cpu.memory.read(*addr);
             assert!(
                 value == (rol_result.2),
                 "Bad rotate result {:#04x} in address {:#06x}",
@@ -1502,7 +1499,7 @@ fn test_ror() {
                 cpu.registers.p.0,
                 *addr
             );
-            let value = cpu.memory.fetch(*addr);
+            let value = cpu.memory.read(*addr);
             assert!(
                 value == (ror_result.2),
                 "Bad rotate result {:#04x} in address {:#06x}",
@@ -1654,7 +1651,7 @@ fn test_sta() {
     for addr in addresses.iter() {
         cpu.execute();
         assert!(
-            val == cpu.memory.fetch(*addr),
+            val == cpu.memory.read(*addr),
             "Bad value at addr {:#06x}",
             addr
         );
@@ -1696,7 +1693,7 @@ fn test_stx() {
     for addr in addresses.iter() {
         cpu.execute();
         assert!(
-            val == cpu.memory.fetch(*addr),
+            val == cpu.memory.read(*addr),
             "Bad value at addr {:#06x}",
             addr
         );
@@ -1738,7 +1735,7 @@ fn test_sty() {
     for addr in addresses.iter() {
         cpu.execute();
         assert!(
-            val == cpu.memory.fetch(*addr),
+            val == cpu.memory.read(*addr),
             "Bad value at addr {:#06x}",
             addr
         );
@@ -1781,3 +1778,193 @@ fn test_subroutine() {
         );
     }
 }
+
+#[test]
+fn test_unofficial_immediate() {
+    let mut cpu = new_cpu();
+
+    // Opcode, A, X, operand, carry in, and the expected A, X and flags.
+    let results = [
+        (
+            _ANC_Imm_1,
+            0xf0,
+            0x00,
+            0x8f,
+            false,
+            0x80,
+            0x00,
+            N_FLAG | C_FLAG,
+        ),
+        (_ANC_Imm_2, 0x0f, 0x00, 0xf0, false, 0x00, 0x00, Z_FLAG),
+        (_ALR_Imm, 0xff, 0x00, 0x03, false, 0x01, 0x00, C_FLAG),
+        (_ALR_Imm, 0x80, 0x00, 0x81, true, 0x40, 0x00, 0),
+        (
+            _ARR_Imm,
+            0xff,
+            0x00,
+            0xff,
+            true,
+            0xff,
+            0x00,
+            N_FLAG | C_FLAG,
+        ),
+        (_ARR_Imm, 0xff, 0x00, 0xc0, false, 0x60, 0x00, C_FLAG),
+        (_ARR_Imm, 0xff, 0x00, 0x40, false, 0x20, 0x00, V_FLAG),
+        (_ARR_Imm, 0xff, 0x00, 0x01, true, 0x80, 0x00, N_FLAG),
+        (_SBX_Imm, 0xf3, 0x3f, 0x03, false, 0xf3, 0x30, C_FLAG),
+        (_SBX_Imm, 0xf3, 0x3f, 0x34, false, 0xf3, 0xff, N_FLAG),
+        (
+            _SBX_Imm,
+            0x0f,
+            0xf0,
+            0x00,
+            false,
+            0x0f,
+            0x00,
+            Z_FLAG | C_FLAG,
+        ),
+        (_XAA_Imm, 0x01, 0x3f, 0xf7, false, 0x27, 0x3f, 0),
+        (_LXA_Imm, 0x01, 0x00, 0x8f, false, 0x8f, 0x8f, N_FLAG),
+    ];
+
+    for &(opcode, a, x, operand, carry, result_a, result_x, flags) in
+        results.iter()
+    {
+        cpu.reset();
+        cpu.registers.a = a;
+        cpu.registers.x = x;
+        cpu.registers.p.set_c(carry);
+        cpu.memory.store_bytes(0x0000, &[opcode as u8, operand]);
+        assert!(cpu.execute() == 2, "Bad cycle count for {}", opcode);
+        assert!(
+            cpu.registers.a == result_a,
+            "Bad A {:#04x} after {}",
+            cpu.registers.a,
+            opcode
+        );
+        assert!(
+            cpu.registers.x == result_x,
+            "Bad X {:#04x} after {}",
+            cpu.registers.x,
+            opcode
+        );
+        assert!(
+            cpu.registers.p.0 == I_FLAG | flags,
+            "Bad flags after {}: {:08b}",
+            opcode,
+            cpu.registers.p.0
+        );
+    }
+}
+
+#[test]
+fn test_unofficial_stores() {
+    let mut cpu = new_cpu();
+    cpu.registers.a = 0xff;
+    cpu.registers.x = 0xf3;
+    cpu.registers.y = 0x10;
+    cpu.memory.store_bytes(0x0080, &[0xf0, 0x11]);
+
+    cpu.memory.store_bytes(
+        0x0000,
+        &[
+            // Store X & 0x12 to 0x1200
+            _SHX_Abs_Y as u8,
+            0xf0,
+            0x11,
+            // Store Y & 0x13 to 0x1020
+            _SHY_Abs_X as u8,
+            0x2d,
+            0x12,
+            // Store A & X & 0x12 to 0x1200
+            _AHX_Ind_Y as u8,
+            0x80,
+            // Store A & X & 0x13 to 0x1234
+            _AHX_Abs_Y as u8,
+            0x24,
+            0x12,
+            // Store A & X & 0x11 to 0x1030, and set SP to A & X
+            _TAS_Abs_Y as u8,
+            0x20,
+            0x10,
+        ],
+    );
+
+    let stores = [(0x1200, 0x12, 5), (0x1020, 0x10, 5), (0x1200, 0x12, 6)];
+    for &(address, value, cycles) in stores.iter() {
+        cpu.memory.store(address, 0x00);
+        assert!(cpu.execute() == cycles, "Bad cycle count");
+        let stored = cpu.memory.read(address);
+        assert!(
+            stored == value,
+            "Bad value {:#04x} at addr {:#06x}",
+            stored,
+            address
+        );
+    }
+
+    cpu.execute();
+    assert!(cpu.memory.read(0x1234) == 0x13, "Bad value stored by AHX");
+
+    cpu.execute();
+    assert!(cpu.memory.read(0x1030) == 0x11, "Bad value stored by TAS");
+    assert!(cpu.registers.sp == 0xf3, "Bad stack pointer after TAS");
+
+    // LAS loads memory & SP into A, X and SP, with an extra cycle when
+    // crossing a page.
+    cpu.memory.store(0x1300, 0x5e);
+    cpu.memory
+        .store_bytes(0x000e, &[_LAS_Abs_Y as u8, 0xf0, 0x12]);
+    assert!(cpu.execute() == 5, "Bad cycle count for LAS");
+    assert!(
+        cpu.registers.a == 0x52
+            && cpu.registers.x == 0x52
+            && cpu.registers.sp == 0x52,
+        "Bad registers after LAS"
+    );
+    assert!(cpu.registers.p.0 == I_FLAG, "Bad flags after LAS");
+}
+
+#[test]
+fn test_jam() {
+    let mut cpu = new_cpu();
+
+    for opcode in [
+        0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+    ] {
+        cpu.reset();
+        cpu.memory.store_bytes(0xfffc, &[0x00, 0x80]);
+        cpu.memory
+            .store_bytes(0x0000, &[opcode, LDA_Imm as u8, 0x12]);
+        assert!(
+            Opcode::try_from(opcode).unwrap().to_string() == "*JAM",
+            "Bad decoding of {:#04x}",
+            opcode
+        );
+
+        cpu.execute();
+        assert!(cpu.halted, "CPU not halted by {:#04x}", opcode);
+
+        // Nothing runs, not even interrupts, but time passes.
+        cpu.nmi = true;
+        for _ in 0..10 {
+            assert!(cpu.execute() == 1, "Bad cycle count while halted");
+        }
+        assert!(
+            cpu.registers.a == 0x00
+                && cpu.registers.pc == 0x0001
+                && cpu.registers.sp == 0xfd,
+            "CPU ran while halted by {:#04x}",
+            opcode
+        );
+
+        // Until it's reset.
+        cpu.reset = true;
+        cpu.execute();
+        assert!(
+            !cpu.halted && cpu.registers.pc == 0x8000,
+            "CPU not reset after {:#04x}",
+            opcode
+        );
+    }
+}
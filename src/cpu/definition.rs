@@ -321,5 +321,37 @@ pub fn lookup_instruction_definition(opcode: Opcode) -> InstructionDefinition {
         _RRA_Zero_X => def(2, 6),
         _RRA_Ind_X => def(2, 8),
         _RRA_Ind_Y => def(2, 8),
+
+        // And with accumulator, copy N to Carry
+        _ANC_Imm_1 | _ANC_Imm_2 => def(2, 2),
+
+        // And with accumulator, Logical shift Right
+        _ALR_Imm => def(2, 2),
+
+        // And with accumulator, Rotate Right
+        _ARR_Imm => def(2, 2),
+
+        // Subtract from bitwise and of accumulator and X register, into X
+        _SBX_Imm => def(2, 2),
+
+        // Load Accumulator, X register and Stack pointer
+        _LAS_Abs_Y => def(3, 4),
+
+        // Unstable: transfer X register to Accumulator, And
+        _XAA_Imm => def(2, 2),
+
+        // Unstable: Load Accumulator and X register
+        _LXA_Imm => def(2, 2),
+
+        // Unstable: Store bitwise and of a register and the address's high byte
+        _SHY_Abs_X => def(3, 5),
+        _SHX_Abs_Y => def(3, 5),
+        _AHX_Abs_Y => def(3, 5),
+        _AHX_Ind_Y => def(2, 6),
+        _TAS_Abs_Y => def(3, 5),
+
+        // Locks up the CPU until it's reset
+        _JAM_1 | _JAM_2 | _JAM_3 | _JAM_4 | _JAM_5 | _JAM_6 | _JAM_7
+        | _JAM_8 | _JAM_9 | _JAM_10 | _JAM_11 | _JAM_12 => def(1, 2),
     }
 }
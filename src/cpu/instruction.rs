@@ -35,16 +35,16 @@ impl Instruction {
         pc: u16,
         cpu: &mut Cpu,
     ) -> (Instruction, InstructionDefinition) {
-        let raw_opcode = cpu.memory.fetch(pc);
+        let raw_opcode = cpu.memory.read_opcode(pc, cpu.cycles);
         let opcode = opcode::decode(raw_opcode);
         let def = lookup_instruction_definition(opcode);
         let arg1 = if def.len > 1 {
-            cpu.memory.fetch(pc + 1)
+            cpu.memory.read(pc + 1)
         } else {
             0
         };
         let arg2 = if def.len > 2 {
-            cpu.memory.fetch(pc + 2)
+            cpu.memory.read(pc + 2)
         } else {
             0
         };
@@ -165,7 +165,7 @@ impl Instruction {
     fn indirect_address(&self, cpu: &mut Cpu) -> u16 {
         cpu.frame_log.decoded_args.push('(');
         let address = self.absolute_address(cpu);
-        let result = cpu.memory.fetch_u16_wrap_msb(address);
+        let result = cpu.memory.read_u16_wrap_msb(address);
         cpu.frame_log
             .decoded_args
             .push_str(format!(") = {:04X}", result).as_str());
@@ -177,7 +177,7 @@ impl Instruction {
     // THAT address.
     fn indirect_address_x(&self, cpu: &mut Cpu) -> u16 {
         let address = self.zero_page_address_x(cpu);
-        let result = cpu.memory.fetch_u16_wrap_msb(address);
+        let result = cpu.memory.read_u16_wrap_msb(address);
         cpu.frame_log.decoded_args = format!(
             "(${:02X},X) @ {:02X} = {:04X}",
             self.arg1(),
@@ -192,7 +192,7 @@ impl Instruction {
     // boundary was crossed.
     fn indirect_address_y(&self, cpu: &mut Cpu) -> (u16, PageCross) {
         let address = self.zero_page_address(cpu);
-        let intermediate = cpu.memory.fetch_u16_wrap_msb(address);
+        let intermediate = cpu.memory.read_u16_wrap_msb(address);
         let result = intermediate.wrapping_add(u16::from(cpu.registers.y));
         cpu.frame_log.decoded_args = format!(
             "(${:02X}),Y = {:04X} @ {:04X}",
@@ -946,13 +946,13 @@ impl Instruction {
             }
             _NOP_Abs => {
                 let address = self.absolute_address(cpu);
-                let value = cpu.memory.fetch(address);
+                let value = cpu.memory.read(address);
                 cpu.decode_operand_value(value);
             }
             _NOP_Abs_X_1 | _NOP_Abs_X_2 | _NOP_Abs_X_3 | _NOP_Abs_X_4
             | _NOP_Abs_X_5 | _NOP_Abs_X_6 => {
                 let (address, page_cross) = self.absolute_address_x(cpu);
-                let value = cpu.memory.fetch(address);
+                let value = cpu.memory.read(address);
                 cpu.decode_operand_value(value);
                 if page_cross != PageCross::Same {
                     cycles += 1;
@@ -960,13 +960,13 @@ impl Instruction {
             }
             _NOP_Zero_1 | _NOP_Zero_2 | _NOP_Zero_3 => {
                 let address = self.zero_page_address(cpu);
-                let value = cpu.memory.fetch(address);
+                let value = cpu.memory.read(address);
                 cpu.decode_operand_value(value);
             }
             _NOP_Zero_X_1 | _NOP_Zero_X_2 | _NOP_Zero_X_3 | _NOP_Zero_X_4
             | _NOP_Zero_X_5 | _NOP_Zero_X_6 => {
                 let address = self.zero_page_address_x(cpu);
-                let value = cpu.memory.fetch(address);
+                let value = cpu.memory.read(address);
                 cpu.decode_operand_value(value);
             }
 
@@ -1205,6 +1205,83 @@ impl Instruction {
                 let (address, _) = self.indirect_address_y(cpu);
                 cpu._rra(address);
             }
+
+            // And with accumulator, copy N to Carry
+            _ANC_Imm_1 | _ANC_Imm_2 => {
+                let value = self.immediate_value(cpu);
+                cpu._anc(value);
+            }
+
+            // And with accumulator, Logical shift Right
+            _ALR_Imm => {
+                let value = self.immediate_value(cpu);
+                cpu._alr(value);
+            }
+
+            // And with accumulator, Rotate Right
+            _ARR_Imm => {
+                let value = self.immediate_value(cpu);
+                cpu._arr(value);
+            }
+
+            // Subtract from bitwise and of accumulator and X register, into X
+            _SBX_Imm => {
+                let value = self.immediate_value(cpu);
+                cpu._sbx(value);
+            }
+
+            // Load Accumulator, X register and Stack pointer
+            _LAS_Abs_Y => {
+                let (address, page_cross) = self.absolute_address_y(cpu);
+                cpu._las(address);
+                if page_cross != PageCross::Same {
+                    cycles += 1;
+                }
+            }
+
+            // Unstable: transfer X register to Accumulator, And
+            _XAA_Imm => {
+                let value = self.immediate_value(cpu);
+                cpu._xaa(value);
+            }
+
+            // Unstable: Load Accumulator and X register
+            _LXA_Imm => {
+                let value = self.immediate_value(cpu);
+                cpu._lxa(value);
+            }
+
+            // Unstable: Store bitwise and of a register and the address's high
+            // byte. These need the address before indexing, too.
+            _SHY_Abs_X => {
+                let (address, _) = self.absolute_address_x(cpu);
+                let base = address.wrapping_sub(u16::from(cpu.registers.x));
+                cpu._shy(base, address);
+            }
+            _SHX_Abs_Y => {
+                let (address, _) = self.absolute_address_y(cpu);
+                let base = address.wrapping_sub(u16::from(cpu.registers.y));
+                cpu._shx(base, address);
+            }
+            _AHX_Abs_Y => {
+                let (address, _) = self.absolute_address_y(cpu);
+                let base = address.wrapping_sub(u16::from(cpu.registers.y));
+                cpu._ahx(base, address);
+            }
+            _AHX_Ind_Y => {
+                let (address, _) = self.indirect_address_y(cpu);
+                let base = address.wrapping_sub(u16::from(cpu.registers.y));
+                cpu._ahx(base, address);
+            }
+            _TAS_Abs_Y => {
+                let (address, _) = self.absolute_address_y(cpu);
+                let base = address.wrapping_sub(u16::from(cpu.registers.y));
+                cpu._tas(base, address);
+            }
+
+            // Locks up the CPU until it's reset
+            _JAM_1 | _JAM_2 | _JAM_3 | _JAM_4 | _JAM_5 | _JAM_6 | _JAM_7
+            | _JAM_8 | _JAM_9 | _JAM_10 | _JAM_11 | _JAM_12 => cpu._jam(),
         }
 
         cycles
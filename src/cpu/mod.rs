@@ -1,7 +1,6 @@
 use crate::cpu::instruction::{BranchTaken, Instruction};
 use crate::nes::memory::Memory;
 use crate::utils::arithmetic::{concat_bytes, is_negative};
-use std::fs::File;
 
 pub mod definition;
 pub mod instruction;
@@ -61,6 +60,10 @@ pub const NMI_VECTOR: u16 = 0xfffa;
 pub const RESET_VECTOR: u16 = 0xfffc;
 pub const IRQ_VECTOR: u16 = 0xfffe;
 
+// XAA and LXA OR the accumulator with a constant that varies from chip to
+// chip (and with temperature). This is a common value on the 2A03.
+const UNSTABLE_CONSTANT: u8 = 0xee;
+
 impl Status {
     // Constructs a new Status object, with only the I flag set.
     pub fn new() -> Status {
@@ -235,20 +238,22 @@ pub struct Cpu {
     pub irq: bool,
     pub nmi: bool,
     pub reset: bool,
+    // Set by the JAM instructions, which lock up the CPU until it's reset.
+    pub halted: bool,
     pub frame_log: Log,
-    mem_dump_pc: Option<u16>,
+    // CPU cycles since power on.
+    pub cycles: u64,
 }
 
 impl Cpu {
     pub fn new(
-        memory: Box<dyn Memory>,
+        mut memory: Box<dyn Memory>,
         program_counter: Option<u16>,
-        mem_dump_counter: Option<u16>,
     ) -> Cpu {
         // Get the PC from the RESET vector pointer.
         let pc = match program_counter {
             Some(pc) => pc,
-            None => memory.fetch_u16(RESET_VECTOR),
+            None => memory.read_u16(RESET_VECTOR),
         };
 
         Cpu {
@@ -257,10 +262,11 @@ impl Cpu {
             irq: false,
             nmi: false,
             reset: false,
+            halted: false,
             frame_log: Log {
                 ..Default::default()
             },
-            mem_dump_pc: mem_dump_counter,
+            cycles: 0,
         }
     }
 
@@ -274,6 +280,7 @@ impl Cpu {
         self.irq = false;
         self.nmi = false;
         self.reset = false;
+        self.halted = false;
     }
 
     pub fn decode_operand_value(&mut self, operand: u8) {
@@ -286,26 +293,27 @@ impl Cpu {
         self.frame_log.decoded_args.push('A');
     }
 
-    pub fn mem_dump(&self) {
-        if let Ok(mut file) = File::create("mem-dump.bin") {
-            self.memory.dump(&mut file).ok();
-        }
-    }
-
     // Executes the instruction at PC and returns the number of cycles taken.
     pub fn execute(&mut self) -> u32 {
+        // A halted CPU doesn't fetch anything or take IRQs or NMIs, but the
+        // rest of the console keeps running, so time passes a cycle at a
+        // time. Only a reset gets it going again.
+        if self.halted {
+            if self.reset {
+                self.reset = false;
+                self.halted = false;
+                self.handle_reset();
+            }
+            self.cycles += 1;
+            return 1;
+        }
+
         self.frame_log = Log {
             pc: self.registers.pc,
             registers: self.registers.log(),
             ..Default::default()
         };
 
-        // Dump memory, if option was passed in.
-        match self.mem_dump_pc {
-            Some(pc) if pc == self.registers.pc => self.mem_dump(),
-            _ => {}
-        }
-
         let instruction_location = self.registers.pc;
         let (instr, definition) =
             Instruction::parse(instruction_location, self);
@@ -317,8 +325,11 @@ impl Cpu {
         let cycles = instr.execute(self, instruction_location);
 
         // Check interrupts.
-        self.check_interrupts();
+        if !self.halted {
+            self.check_interrupts();
+        }
 
+        self.cycles += u64::from(cycles);
         u32::from(cycles)
     }
 
@@ -349,7 +360,7 @@ impl Cpu {
         self.registers.p.set_i(true);
 
         // Fetch memory from IRQ vector.
-        let vector = self.memory.fetch_u16(IRQ_VECTOR);
+        let vector = self.memory.read_u16(IRQ_VECTOR);
         self.registers.pc = vector;
     }
 
@@ -365,7 +376,7 @@ impl Cpu {
         self.registers.p.set_i(true);
 
         // Fetch memory from NMI vector.
-        let vector = self.memory.fetch_u16(NMI_VECTOR);
+        let vector = self.memory.read_u16(NMI_VECTOR);
         self.registers.pc = vector;
     }
 
@@ -375,7 +386,7 @@ impl Cpu {
     // the stack pointer was decremented 3 times, which is why the stack pointer
     // on startup is set to 0xfd (0x00 - 3).
     fn handle_reset(&mut self) {
-        let vector = self.memory.fetch_u16(RESET_VECTOR);
+        let vector = self.memory.read_u16(RESET_VECTOR);
         self.registers.pc = vector;
     }
 
@@ -535,7 +546,7 @@ impl Cpu {
     // Pull a value off of the stack, and increment stack pointer.
     pub fn pull(&mut self) -> u8 {
         self.registers.sp += 1;
-        self.memory.fetch(concat_bytes(0x01, self.registers.sp))
+        self.memory.read(concat_bytes(0x01, self.registers.sp))
     }
 
     pub fn pull_u16(&mut self) -> u16 {
@@ -606,7 +617,7 @@ impl Cpu {
     //         V    Overflow Flag       Set if sign bit is incorrect
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn adc(&mut self, address: u16) {
-        let arg = self.memory.fetch(address);
+        let arg = self.memory.read(address);
         self.adc_value(arg);
         self.decode_operand_value(arg);
     }
@@ -651,7 +662,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn and(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         self.and_value(value);
         self.decode_operand_value(value);
     }
@@ -677,7 +688,7 @@ impl Cpu {
     //         V    Overflow Flag       Set if sign bit is incorrect
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn sbc(&mut self, address: u16) {
-        let arg = self.memory.fetch(address);
+        let arg = self.memory.read(address);
         self.sbc_value(arg);
         self.decode_operand_value(arg);
     }
@@ -697,7 +708,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn rol(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let rotated_value = self.rotate_l(value);
         self.memory.store(address, rotated_value);
         self.decode_operand_value(value);
@@ -736,7 +747,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn ror(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let rotated_value = self.rotate_r(value);
         self.memory.store(address, rotated_value);
         self.decode_operand_value(value);
@@ -776,7 +787,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn asl(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let shifted_value = self.shift_l(value);
         self.memory.store(address, shifted_value);
         self.decode_operand_value(value);
@@ -814,7 +825,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn lsr(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let shifted_value = self.shift_r(value);
         self.memory.store(address, shifted_value);
         self.decode_operand_value(value);
@@ -854,7 +865,7 @@ impl Cpu {
     //         V    Overflow Flag       Set to bit 6 of value
     //         N    Negative Flag       Set to bit 7 of value
     pub fn bit(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let zero_test = self.registers.a & value;
         self.set_z_flag(zero_test);
         self.registers.p.set_v(value & V_FLAG == V_FLAG);
@@ -873,7 +884,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn inc(&mut self, address: u16) {
-        let old_value = self.memory.fetch(address);
+        let old_value = self.memory.read(address);
         let value = old_value.wrapping_add(1);
         self.memory.store(address, value);
         self.set_z_flag(value);
@@ -892,7 +903,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn dec(&mut self, address: u16) {
-        let old_value = self.memory.fetch(address);
+        let old_value = self.memory.read(address);
         let value = old_value.wrapping_sub(1);
         self.memory.store(address, value);
         self.set_z_flag(value);
@@ -911,7 +922,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn eor(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         self.eor_value(value);
         self.decode_operand_value(value);
     }
@@ -946,7 +957,7 @@ impl Cpu {
 
     // Compare with Accumulator.
     pub fn cmp(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let register = self.registers.a;
         self.compare(register, value);
         self.decode_operand_value(value);
@@ -959,7 +970,7 @@ impl Cpu {
 
     // Compare with X register.
     pub fn cpx(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let register = self.registers.x;
         self.compare(register, value);
         self.decode_operand_value(value);
@@ -972,7 +983,7 @@ impl Cpu {
 
     // Compare with Y register.
     pub fn cpy(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         let register = self.registers.y;
         self.compare(register, value);
         self.decode_operand_value(value);
@@ -1022,7 +1033,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of A is set
     pub fn lda(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         self.lda_value(value);
         self.decode_operand_value(value);
     }
@@ -1045,7 +1056,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of X is set
     pub fn ldx(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         self.ldx_value(value);
         self.decode_operand_value(value);
     }
@@ -1067,7 +1078,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of Y is set
     pub fn ldy(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         self.ldy_value(value);
         self.decode_operand_value(value);
     }
@@ -1089,7 +1100,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of the result is set
     pub fn ora(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         self.ora_value(value);
         self.decode_operand_value(value);
     }
@@ -1316,7 +1327,7 @@ impl Cpu {
         self.registers.p.set_i(true);
 
         // Fetch memory from IRQ vector.
-        let vector = self.memory.fetch_u16(IRQ_VECTOR);
+        let vector = self.memory.read_u16(IRQ_VECTOR);
         self.registers.pc = vector;
     }
 
@@ -1422,7 +1433,7 @@ impl Cpu {
     //         V    Overflow Flag       Not affected
     //         N    Negative Flag       Set if bit 7 of X is set
     pub fn _lax(&mut self, address: u16) {
-        let value = self.memory.fetch(address);
+        let value = self.memory.read(address);
         self.lda_value(value);
         self.tax();
         self.decode_operand_value(value);
@@ -1510,4 +1521,163 @@ impl Cpu {
         self.adc(address);
         self.frame_log.decoded_args = decoded_args;
     }
+
+    // UNOFFICIAL INSTRUCTION
+    // Equivalent to AND, then copying bit 7 of the result into carry.
+    //
+    //         C    Carry Flag          Set if bit 7 of the result is set
+    //         Z    Zero Flag           Set if result = 0
+    //         I    Interrupt Disable   Not affected
+    //         D    Decimal Mode Flag   Not affected
+    //         B    Break Command       Not affected
+    //         V    Overflow Flag       Not affected
+    //         N    Negative Flag       Set if bit 7 of the result is set
+    pub fn _anc(&mut self, value: u8) {
+        self.and_value(value);
+        let negative = self.registers.p.n();
+        self.registers.p.set_c(negative);
+    }
+
+    // UNOFFICIAL INSTRUCTION
+    // Equivalent to AND then LSR A.
+    pub fn _alr(&mut self, value: u8) {
+        self.and_value(value);
+        let value = self.registers.a;
+        self.registers.a = self.shift_r(value);
+    }
+
+    // UNOFFICIAL INSTRUCTION
+    // Equivalent to AND then ROR A, except for how carry and overflow are
+    // set.
+    //
+    //         C    Carry Flag          Set to bit 6 of the result
+    //         Z    Zero Flag           Set if result = 0
+    //         I    Interrupt Disable   Not affected
+    //         D    Decimal Mode Flag   Not affected
+    //         B    Break Command       Not affected
+    //         V    Overflow Flag       Set to bit 6 xor bit 5 of the result
+    //         N    Negative Flag       Set if bit 7 of the result is set
+    pub fn _arr(&mut self, value: u8) {
+        self.and_value(value);
+        let value = self.registers.a;
+        let result = self.rotate_r(value);
+        self.registers.a = result;
+        self.registers.p.set_c(result & 0x40 != 0);
+        self.registers
+            .p
+            .set_v(((result >> 6) ^ (result >> 5)) & 0x01 != 0);
+    }
+
+    // UNOFFICIAL INSTRUCTION
+    // Sets X to the bitwise AND of A and X, minus the value. The subtraction
+    // ignores the carry flag, and sets flags the way CMP does.
+    //
+    //         C    Carry Flag          Set if A & X >= value
+    //         Z    Zero Flag           Set if X = 0
+    //         I    Interrupt Disable   Not affected
+    //         D    Decimal Mode Flag   Not affected
+    //         B    Break Command       Not affected
+    //         V    Overflow Flag       Not affected
+    //         N    Negative Flag       Set if bit 7 of X is set
+    pub fn _sbx(&mut self, value: u8) {
+        let register = self.registers.a & self.registers.x;
+        self.compare(register, value);
+        self.registers.x = register.wrapping_sub(value);
+    }
+
+    // UNOFFICIAL INSTRUCTION
+    // Loads the bitwise AND of a byte of memory and the stack pointer into
+    // A, X and the stack pointer.
+    // Also adds " = XX" to decoded output.
+    //
+    //         C    Carry Flag          Not affected
+    //         Z    Zero Flag           Set if result = 0
+    //         I    Interrupt Disable   Not affected
+    //         D    Decimal Mode Flag   Not affected
+    //         B    Break Command       Not affected
+    //         V    Overflow Flag       Not affected
+    //         N    Negative Flag       Set if bit 7 of the result is set
+    pub fn _las(&mut self, address: u16) {
+        let value = self.memory.read(address);
+        let result = value & self.registers.sp;
+        self.lda_value(result);
+        self.registers.x = result;
+        self.registers.sp = result;
+        self.decode_operand_value(value);
+    }
+
+    // UNOFFICIAL INSTRUCTION (UNSTABLE)
+    // Loads the bitwise AND of X and the value into A, after ORing A with a
+    // magic constant.
+    pub fn _xaa(&mut self, value: u8) {
+        let result =
+            (self.registers.a | UNSTABLE_CONSTANT) & self.registers.x & value;
+        self.lda_value(result);
+    }
+
+    // UNOFFICIAL INSTRUCTION (UNSTABLE)
+    // Loads the value into A and X, after ORing A with a magic constant and
+    // ANDing it with the value.
+    pub fn _lxa(&mut self, value: u8) {
+        let result = (self.registers.a | UNSTABLE_CONSTANT) & value;
+        self.lda_value(result);
+        self.tax();
+    }
+
+    // UNOFFICIAL INSTRUCTION (UNSTABLE)
+    // Stores the bitwise AND of Y and the high byte of the base address plus
+    // 1.
+    pub fn _shy(&mut self, base_address: u16, address: u16) {
+        let value = self.registers.y;
+        self.unstable_store(base_address, address, value);
+    }
+
+    // UNOFFICIAL INSTRUCTION (UNSTABLE)
+    // Stores the bitwise AND of X and the high byte of the base address plus
+    // 1.
+    pub fn _shx(&mut self, base_address: u16, address: u16) {
+        let value = self.registers.x;
+        self.unstable_store(base_address, address, value);
+    }
+
+    // UNOFFICIAL INSTRUCTION (UNSTABLE)
+    // Stores the bitwise AND of A, X and the high byte of the base address
+    // plus 1.
+    pub fn _ahx(&mut self, base_address: u16, address: u16) {
+        let value = self.registers.a & self.registers.x;
+        self.unstable_store(base_address, address, value);
+    }
+
+    // UNOFFICIAL INSTRUCTION (UNSTABLE)
+    // Sets the stack pointer to the bitwise AND of A and X, then stores that
+    // ANDed with the high byte of the base address plus 1.
+    pub fn _tas(&mut self, base_address: u16, address: u16) {
+        self.registers.sp = self.registers.a & self.registers.x;
+        let value = self.registers.sp;
+        self.unstable_store(base_address, address, value);
+    }
+
+    // UNOFFICIAL INSTRUCTION
+    // Locks up the CPU: it stops fetching instructions, and ignores IRQs and
+    // NMIs, until it's reset.
+    pub fn _jam(&mut self) {
+        self.halted = true;
+    }
+
+    // The store for SHY, SHX, AHX and TAS, which ANDs "value" with the high
+    // byte of the address before indexing, plus 1. When indexing crosses a
+    // page, the result also replaces the high byte of the address written
+    // to.
+    // Also adds " = XX" to decoded output.
+    fn unstable_store(&mut self, base_address: u16, address: u16, value: u8) {
+        let high = (base_address >> 8) as u8;
+        let value = value & high.wrapping_add(1);
+        let address = if (base_address ^ address) & 0xff00 != 0 {
+            concat_bytes(value, address as u8)
+        } else {
+            address
+        };
+        let old_value = self.memory.store(address, value);
+        self.decode_operand_value(old_value);
+    }
 }
@@ -356,4 +356,47 @@ pub enum Opcode {
     _RRA_Zero_X = 0x77,
     _RRA_Ind_X = 0x63,
     _RRA_Ind_Y = 0x73,
+
+    // And with accumulator, copy N to Carry
+    _ANC_Imm_1 = 0x0b,
+    _ANC_Imm_2 = 0x2b,
+
+    // And with accumulator, Logical shift Right
+    _ALR_Imm = 0x4b,
+
+    // And with accumulator, Rotate Right
+    _ARR_Imm = 0x6b,
+
+    // Subtract from bitwise and of accumulator and X register, into X
+    _SBX_Imm = 0xcb,
+
+    // Load Accumulator, X register and Stack pointer
+    _LAS_Abs_Y = 0xbb,
+
+    // Unstable: transfer X register to Accumulator, And
+    _XAA_Imm = 0x8b,
+
+    // Unstable: Load Accumulator and X register
+    _LXA_Imm = 0xab,
+
+    // Unstable: Store bitwise and of a register and the address's high byte
+    _SHY_Abs_X = 0x9c,
+    _SHX_Abs_Y = 0x9e,
+    _AHX_Abs_Y = 0x9f,
+    _AHX_Ind_Y = 0x93,
+    _TAS_Abs_Y = 0x9b,
+
+    // Locks up the CPU until it's reset
+    _JAM_1 = 0x02,
+    _JAM_2 = 0x12,
+    _JAM_3 = 0x22,
+    _JAM_4 = 0x32,
+    _JAM_5 = 0x42,
+    _JAM_6 = 0x52,
+    _JAM_7 = 0x62,
+    _JAM_8 = 0x72,
+    _JAM_9 = 0x92,
+    _JAM_10 = 0xb2,
+    _JAM_11 = 0xd2,
+    _JAM_12 = 0xf2,
 }
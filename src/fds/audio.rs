@@ -0,0 +1,148 @@
+// Envelope control: Set the gain directly to the speed bits, instead of
+// running the envelope.
+const ENVELOPE_DISABLE: u8 = 0b1000_0000;
+// Envelope control: Gain goes up (1) or down (0).
+const ENVELOPE_INCREASE: u8 = 0b0100_0000;
+const ENVELOPE_SPEED: u8 = 0b0011_1111;
+
+// Highest gain an envelope reaches, though the gain can be set higher.
+const MAX_GAIN: u8 = 32;
+
+const WAVE_TABLE_SIZE: usize = 64;
+
+// A volume or mod envelope, which slides the gain up or down by 1 every
+// "speed" ticks.
+struct Envelope {
+    control: u8,
+    gain: u8,
+    counter: u32,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            control: ENVELOPE_DISABLE,
+            gain: 0,
+            counter: 0,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        self.control = value;
+        self.counter = 0;
+        if value & ENVELOPE_DISABLE != 0 {
+            self.gain = value & ENVELOPE_SPEED;
+        }
+    }
+
+    // Called every CPU cycle. Envelopes tick every 8 cycles times the master
+    // envelope speed from $408A.
+    fn clock(&mut self, master_speed: u8) {
+        if self.control & ENVELOPE_DISABLE != 0 {
+            return;
+        }
+
+        self.counter += 1;
+        let period = 8
+            * (u32::from(self.control & ENVELOPE_SPEED) + 1)
+            * u32::from(master_speed);
+        if self.counter >= period {
+            self.counter = 0;
+            if self.control & ENVELOPE_INCREASE != 0 {
+                if self.gain < MAX_GAIN {
+                    self.gain += 1;
+                }
+            } else if self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+// The registers of the FDS expansion audio channel: a single wavetable voice
+// with a 64 step, 6-bit waveform, a volume envelope, and a frequency
+// modulator with an envelope of its own.
+//
+// There's no audio output in the emulator yet, so no sound is made. Only the
+// state that games can read back is kept: the wave table, and the two
+// envelopes' gains. Writes to the frequency and mod table registers are
+// ignored.
+//
+// $4040-$407F: Wave table, writable while $4089 bit 7 is set.
+// $4080:       Volume envelope.
+// $4083:       Wave frequency (ignored), plus halting the wave and envelopes.
+// $4084:       Mod envelope.
+// $4089:       Wave table write enable (master volume is ignored).
+// $408A:       Envelope speed.
+// $4090:       Volume gain (read).
+// $4092:       Mod gain (read).
+//
+// See http://wiki.nesdev.com/w/index.php/FDS_audio for more details.
+pub struct FdsAudio {
+    wave_table: [u8; WAVE_TABLE_SIZE],
+    wave_write: bool,
+    // The envelopes only run while the wave is playing.
+    wave_halted: bool,
+
+    volume: Envelope,
+    modulator: Envelope,
+    envelopes_halted: bool,
+    envelope_speed: u8,
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave_table: [0x00; WAVE_TABLE_SIZE],
+            wave_write: false,
+            wave_halted: true,
+            volume: Envelope::new(),
+            modulator: Envelope::new(),
+            envelopes_halted: false,
+            envelope_speed: 0xe8,
+        }
+    }
+
+    // Fetches a readable register, or returns None for the write-only ones,
+    // which leave open bus.
+    pub fn fetch(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4040..=0x407f => Some(self.wave_table[address as usize - 0x4040]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.modulator.gain),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, address: u16, value: u8) {
+        match address {
+            0x4040..=0x407f if self.wave_write => {
+                self.wave_table[address as usize - 0x4040] = value & 0x3f;
+            }
+            0x4080 => self.volume.write(value),
+            0x4083 => {
+                self.wave_halted = value & 0x80 != 0;
+                self.envelopes_halted = value & 0x40 != 0;
+                if self.envelopes_halted {
+                    self.volume.counter = 0;
+                    self.modulator.counter = 0;
+                }
+            }
+            0x4084 => self.modulator.write(value),
+            0x4089 => self.wave_write = value & 0x80 != 0,
+            0x408a => self.envelope_speed = value,
+            _ => {}
+        }
+    }
+
+    // Called every CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.envelopes_halted
+            && !self.wave_halted
+            && self.envelope_speed != 0
+        {
+            self.volume.clock(self.envelope_speed);
+            self.modulator.clock(self.envelope_speed);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use crate::nes::CPU_FREQ;
+use crate::patch;
+use crate::rom::RomError;
+use crate::utils::io::read_binary;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Size of one side of a disk in an .fds image.
+pub const SIDE_SIZE: usize = 65500;
+
+// Size of the optional fwNES header at the start of an .fds image.
+const HEADER_SIZE: usize = 16;
+type Header = [u8; HEADER_SIZE];
+const HEADER_MAGIC: [u8; 4] = [0x46, 0x44, 0x53, 0x1a];
+// Every disk side starts with a disk info block, which has this magic.
+const DISK_MAGIC: &[u8; 15] = b"\x01*NINTENDO-HVC*";
+
+// The drive sees the gaps and CRCs between blocks that .fds images leave
+// out, so they're put back when a side is loaded. Gaps are measured in bits
+// on the disk, and the drive reads 8 bits per byte.
+const LEAD_IN_SIZE: usize = 28300 / 8;
+const GAP_SIZE: usize = 976 / 8;
+const CRC_SIZE: usize = 2;
+// Every block starts with a single set bit after the gap.
+const BLOCK_START: u8 = 0x80;
+// Stand-in for the CRC after each block. The drive doesn't check it.
+const FAKE_CRC: [u8; CRC_SIZE] = [0x4d, 0x62];
+// Room on each side for the drive to write past the last block, when a game
+// adds a file.
+const RAW_SIDE_SIZE: usize = 68000;
+
+// How long a disk stays out of the drive when switching sides, in CPU
+// cycles. The BIOS only notices a new disk after seeing the drive empty.
+const SIDE_SWITCH_DELAY: u32 = CPU_FREQ;
+
+// Block types, from the first byte of each block.
+const DISK_INFO_BLOCK: u8 = 1;
+const FILE_AMOUNT_BLOCK: u8 = 2;
+const FILE_HEADER_BLOCK: u8 = 3;
+const FILE_DATA_BLOCK: u8 = 4;
+
+// A Famicom Disk System disk image, loaded from an .fds file, along with the
+// drive's view of which side is inserted.
+//
+// The sides are kept the way the drive reads them, with the gaps between
+// blocks. Games write to the disk to save, but those writes never go back
+// to the .fds file. Instead, the differences are saved as an IPS patch next
+// to the image, which is applied again the next time it's loaded.
+//
+// See http://wiki.nesdev.com/w/index.php/FDS_file_format and
+// http://wiki.nesdev.com/w/index.php/FDS_disk_format for more details.
+pub struct Disk {
+    // The image as it was loaded, before the save was applied.
+    original: Vec<u8>,
+    header: Option<Header>,
+    sides: Vec<Vec<u8>>,
+    // Whether the drive has written to the disk since it was loaded.
+    modified: bool,
+
+    // The side that's inserted, or that will be once "insert_delay" runs
+    // out.
+    side: usize,
+    inserted: bool,
+    insert_delay: u32,
+}
+
+impl Disk {
+    // Loads an .fds image, along with its save if there is one.
+    pub fn new(file_name: &str) -> Result<Disk, RomError> {
+        let mut disk = Disk::new_from_buffer(read_binary(file_name)?)?;
+
+        let save_path = save_path(Path::new(file_name));
+        if save_path.is_file() {
+            match patch::apply_file(&save_path, &disk.original) {
+                Ok(image) => disk.sides = read_sides(&image)?.1,
+                Err(error) => {
+                    return Err(RomError::Patch {
+                        path: save_path,
+                        error,
+                    });
+                }
+            }
+        }
+
+        Ok(disk)
+    }
+
+    pub fn new_from_buffer(buffer: Vec<u8>) -> Result<Disk, RomError> {
+        let (header, sides) = read_sides(&buffer)?;
+        Ok(Disk {
+            original: buffer,
+            header,
+            sides,
+            modified: false,
+            side: 0,
+            inserted: true,
+            insert_delay: 0,
+        })
+    }
+
+    // The inserted side, or None if the drive is empty.
+    pub fn side(&self) -> Option<usize> {
+        if self.inserted { Some(self.side) } else { None }
+    }
+
+    // Ejects the disk and inserts the next side a moment later, going back
+    // to the first side after the last one. Returns the side that will be
+    // inserted.
+    pub fn switch_side(&mut self) -> usize {
+        self.side = (self.side + 1) % self.sides.len();
+        self.inserted = false;
+        self.insert_delay = SIDE_SWITCH_DELAY;
+        self.side
+    }
+
+    // Counts down to inserting the next side, after switching sides.
+    pub fn clock(&mut self) {
+        if !self.inserted {
+            self.insert_delay = self.insert_delay.saturating_sub(1);
+            self.inserted = self.insert_delay == 0;
+        }
+    }
+
+    // Size of the inserted side, as the drive sees it.
+    pub fn side_size(&self) -> usize {
+        self.sides[self.side].len()
+    }
+
+    // Reads the byte at "position" on the inserted side.
+    pub fn read(&self, position: usize) -> u8 {
+        self.sides[self.side][position]
+    }
+
+    // Writes the byte at "position" on the inserted side.
+    pub fn write(&mut self, position: usize, value: u8) {
+        self.sides[self.side][position] = value;
+        self.modified = true;
+    }
+
+    // Rebuilds the .fds image, including anything written to the disk.
+    pub fn image(&self) -> Vec<u8> {
+        let mut image = Vec::with_capacity(self.original.len());
+        if let Some(header) = self.header {
+            image.extend_from_slice(&header);
+        }
+        for side in self.sides.iter() {
+            image.extend(from_raw(side));
+        }
+        image
+    }
+
+    // Saves what's been written to the disk next to the image at
+    // "file_name", if anything has.
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        if !self.modified {
+            return Ok(());
+        }
+
+        let mut file = File::create(save_path(Path::new(file_name)))?;
+        file.write_all(&patch::create_ips(&self.original, &self.image()))
+    }
+}
+
+// Where the save for the image at "path" goes, like "game.fds.ips" for
+// "game.fds". It's an IPS patch against the original image, so it gets its
+// own extension, rather than the .sav used for raw PRG RAM saves.
+pub fn save_path(path: &Path) -> PathBuf {
+    path.with_extension("fds.ips")
+}
+
+// Splits an .fds image into its header, if it has one, and its sides, in
+// the form the drive reads them.
+fn read_sides(
+    buffer: &[u8],
+) -> Result<(Option<Header>, Vec<Vec<u8>>), RomError> {
+    let (header, data) = if buffer.starts_with(&HEADER_MAGIC) {
+        if buffer.len() < HEADER_SIZE {
+            return Err(RomError::HeaderTruncated {
+                expected: HEADER_SIZE,
+                found: buffer.len(),
+            });
+        }
+        let (header, data) = buffer.split_at(HEADER_SIZE);
+        (Some(Header::try_from(header).unwrap()), data)
+    } else {
+        (None, buffer)
+    };
+
+    if !data.starts_with(DISK_MAGIC) {
+        return Err(RomError::MissingDiskMagic);
+    }
+
+    // Images without a header hold as many sides as fit.
+    let side_count = match header {
+        Some(header) => usize::from(header[4]).max(1),
+        None => data.len().div_ceil(SIDE_SIZE),
+    };
+    let expected = side_count * SIDE_SIZE;
+    if data.len() < expected {
+        return Err(RomError::DiskTruncated {
+            expected,
+            found: data.len(),
+        });
+    }
+
+    let sides = data[..expected].chunks(SIDE_SIZE).map(to_raw).collect();
+    Ok((header, sides))
+}
+
+// Size of a block, from its type. File data blocks are as big as the file
+// header before them says, plus the block type.
+fn block_size(block_type: u8, file_size: usize) -> Option<usize> {
+    match block_type {
+        DISK_INFO_BLOCK => Some(56),
+        FILE_AMOUNT_BLOCK => Some(2),
+        FILE_HEADER_BLOCK => Some(16),
+        FILE_DATA_BLOCK => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+// The file size in a file header block.
+fn file_size(block: &[u8]) -> usize {
+    match block {
+        [
+            FILE_HEADER_BLOCK,
+            _,
+            _,
+            _,
+            _,
+            _,
+            _,
+            _,
+            _,
+            _,
+            _,
+            _,
+            _,
+            low,
+            high,
+            ..,
+        ] => usize::from(u16::from_le_bytes([*low, *high])),
+        _ => 0,
+    }
+}
+
+// Converts a side of an .fds image into what the drive reads: a lead-in,
+// then each block with a start bit before it, and a CRC and gap after it.
+fn to_raw(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0x00; LEAD_IN_SIZE];
+    let mut position = 0;
+    let mut size = 0;
+    while position < side.len() {
+        let Some(block_size) = block_size(side[position], size) else {
+            break;
+        };
+        let end = (position + block_size).min(side.len());
+        let block = &side[position..end];
+        size = file_size(block);
+
+        raw.push(BLOCK_START);
+        raw.extend_from_slice(block);
+        raw.extend_from_slice(&FAKE_CRC);
+        raw.extend_from_slice(&[0x00; GAP_SIZE]);
+        position = end;
+    }
+    raw.resize(raw.len().max(RAW_SIDE_SIZE), 0x00);
+    raw
+}
+
+// Converts a side the way the drive reads it back into a side of an .fds
+// image, by picking out the blocks between the gaps.
+fn from_raw(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut position = 0;
+    let mut size = 0;
+    loop {
+        while raw.get(position) == Some(&0x00) {
+            position += 1;
+        }
+        if raw.get(position) != Some(&BLOCK_START) {
+            break;
+        }
+        position += 1;
+
+        let Some(block_size) = raw
+            .get(position)
+            .and_then(|block_type| block_size(*block_type, size))
+        else {
+            break;
+        };
+        let end = (position + block_size).min(raw.len());
+        let block = &raw[position..end];
+        size = file_size(block);
+
+        side.extend_from_slice(block);
+        position = end + CRC_SIZE;
+    }
+    side.resize(SIDE_SIZE, 0x00);
+    side
+}
@@ -0,0 +1,229 @@
+use crate::cartridge::{Cartridge, Mapper};
+use crate::fds::disk::{Disk, SIDE_SIZE, save_path};
+use crate::fds::{BIOS_SIZE, Fds};
+use crate::nes::CPU_FREQ;
+use crate::nes::memory::Memory;
+use crate::rom::{MirrorType, RomError};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+// Where the first byte of a side ends up on the disk, after the lead-in and
+// the start of the block.
+const FIRST_BYTE: usize = 28300 / 8 + 1;
+
+// Builds a disk side with a disk info block and a single 4 byte file.
+fn new_side() -> Vec<u8> {
+    let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+    side.resize(56, 0x00);
+    side.extend_from_slice(&[0x02, 0x01]);
+    let mut file_header = vec![0x03; 16];
+    file_header[13..15].copy_from_slice(&4u16.to_le_bytes());
+    side.extend_from_slice(&file_header);
+    side.extend_from_slice(&[0x04, 0xaa, 0xbb, 0xcc, 0xdd]);
+    side.resize(SIDE_SIZE, 0x00);
+    side
+}
+
+// Builds an .fds image with a header and "sides" sides.
+fn new_image(sides: u8) -> Vec<u8> {
+    let mut image = vec![0x46, 0x44, 0x53, 0x1a, sides];
+    image.resize(16, 0x00);
+    for _ in 0..sides {
+        image.extend(new_side());
+    }
+    image
+}
+
+fn new_fds(disk: Disk) -> Fds {
+    Fds::new(Rc::new(RefCell::new(disk)), vec![0x00; BIOS_SIZE])
+}
+
+#[test]
+fn test_load_disk() {
+    let disk = Disk::new_from_buffer(new_image(2)).unwrap();
+    assert_eq!(disk.side(), Some(0));
+    assert_eq!(disk.image(), new_image(2));
+
+    // Images without a header.
+    let disk = Disk::new_from_buffer(new_side()).unwrap();
+    assert_eq!(disk.image(), new_side());
+}
+
+#[test]
+fn test_load_bad_disk() {
+    let error = Disk::new_from_buffer(vec![0x00; SIDE_SIZE]).err().unwrap();
+    assert!(matches!(error, RomError::MissingDiskMagic));
+
+    let mut image = new_image(2);
+    image.truncate(16 + SIDE_SIZE + 100);
+    let error = Disk::new_from_buffer(image).err().unwrap();
+    assert!(matches!(
+        error,
+        RomError::DiskTruncated {
+            expected: 131000,
+            found: 65600,
+        }
+    ));
+}
+
+#[test]
+fn test_write_disk() {
+    let mut disk = Disk::new_from_buffer(new_side()).unwrap();
+    disk.write(FIRST_BYTE + 1, b'#');
+
+    let mut side = new_side();
+    side[1] = b'#';
+    assert_eq!(disk.image(), side);
+
+    // Saves don't share the .sav name used for PRG RAM.
+    assert_eq!(
+        save_path(Path::new("games/game.fds")),
+        Path::new("games/game.fds.ips")
+    );
+}
+
+#[test]
+fn test_switch_side() {
+    let mut disk = Disk::new_from_buffer(new_image(2)).unwrap();
+    assert_eq!(disk.switch_side(), 1);
+    assert_eq!(disk.side(), None);
+    for _ in 0..CPU_FREQ {
+        disk.clock();
+    }
+    assert_eq!(disk.side(), Some(1));
+    assert_eq!(disk.switch_side(), 0);
+}
+
+#[test]
+fn test_timer_irq() {
+    let mut fds = new_fds(Disk::new_from_buffer(new_side()).unwrap());
+    fds.store_prg(0x4023, 0x01);
+    fds.store_prg(0x4020, 0x0a);
+    fds.store_prg(0x4021, 0x00);
+    fds.store_prg(0x4022, 0x02);
+
+    fds.clock_cpu(10);
+    assert!(!fds.irq());
+    fds.clock_cpu(1);
+    assert!(fds.irq());
+
+    // Reading the status acknowledges it, and without repeat it doesn't fire
+    // again.
+    assert_eq!(fds.read_prg(0x4030).unwrap() & 0x01, 0x01);
+    assert!(!fds.irq());
+    fds.clock_cpu(100);
+    assert!(!fds.irq());
+}
+
+#[test]
+fn test_peek_status() {
+    let mut fds = new_fds(Disk::new_from_buffer(new_side()).unwrap());
+    fds.store_prg(0x4023, 0x01);
+    fds.store_prg(0x4020, 0x00);
+    fds.store_prg(0x4021, 0x00);
+    fds.store_prg(0x4022, 0x02);
+    fds.clock_cpu(1);
+    let mut cartridge =
+        Cartridge::with_mapper(Box::new(fds), MirrorType::Vertical);
+    assert!(cartridge.irq());
+
+    // Peeking doesn't acknowledge the IRQ, but reading does.
+    assert_eq!(cartridge.peek(0x4030) & 0x01, 0x01);
+    assert_eq!(cartridge.peek(0x4031), 0x00);
+    assert!(cartridge.irq());
+    assert_eq!(cartridge.read(0x4030) & 0x01, 0x01);
+    assert!(!cartridge.irq());
+}
+
+#[test]
+fn test_read_disk() {
+    let mut fds = new_fds(Disk::new_from_buffer(new_side()).unwrap());
+    fds.store_prg(0x4023, 0x01);
+    assert_eq!(fds.peek_prg(0x4032).unwrap() & 0x03, 0x02);
+
+    // Motor on, read mode, ready and IRQs on, with the drive rewinding
+    // first. The first byte gets read once it's done.
+    fds.store_prg(0x4025, 0xc5);
+    fds.clock_cpu(50_002);
+    assert_eq!(fds.peek_prg(0x4032).unwrap() & 0x03, 0x00);
+
+    // Skip the rest of the lead-in. The start of the block doesn't fire an
+    // IRQ.
+    for _ in 1..FIRST_BYTE {
+        fds.clock_cpu(151);
+    }
+    assert!(!fds.irq());
+
+    for expected in b"\x01*NINTENDO-HVC*" {
+        fds.clock_cpu(151);
+        assert!(fds.irq());
+        assert_eq!(fds.read_prg(0x4031), Some(*expected));
+        assert!(!fds.irq());
+    }
+}
+
+#[test]
+fn test_mirroring() {
+    let mut fds = new_fds(Disk::new_from_buffer(new_side()).unwrap());
+    fds.store_prg(0x4023, 0x01);
+    fds.store_prg(0x4025, 0x08);
+    assert_eq!(fds.mirror_type(), MirrorType::Horizontal);
+    fds.store_prg(0x4025, 0x00);
+    assert_eq!(fds.mirror_type(), MirrorType::Vertical);
+}
+
+#[test]
+fn test_prg_ram_and_bios() {
+    let disk =
+        Rc::new(RefCell::new(Disk::new_from_buffer(new_side()).unwrap()));
+    let mut bios = vec![0x00; BIOS_SIZE];
+    bios[BIOS_SIZE - 2] = 0x24;
+    let mut fds = Fds::new(disk, bios);
+
+    fds.store_prg(0x6000, 0x12);
+    fds.store_prg(0xdfff, 0x34);
+    assert_eq!(fds.peek_prg(0x6000), Some(0x12));
+    assert_eq!(fds.peek_prg(0xdfff), Some(0x34));
+
+    fds.store_prg(0xfffe, 0x00);
+    assert_eq!(fds.peek_prg(0xfffe), Some(0x24));
+}
+
+#[test]
+fn test_audio_registers() {
+    let mut fds = new_fds(Disk::new_from_buffer(new_side()).unwrap());
+
+    // Sound registers are ignored until enabled, leaving open bus.
+    fds.store_prg(0x4080, 0x94);
+    assert_eq!(fds.peek_prg(0x4090), None);
+
+    fds.store_prg(0x4023, 0x02);
+    fds.store_prg(0x4080, 0x94);
+    assert_eq!(fds.peek_prg(0x4090), Some(0x14));
+
+    // The wave table is only writable while $4089 bit 7 is set.
+    fds.store_prg(0x4040, 0x3f);
+    assert_eq!(fds.peek_prg(0x4040), Some(0x00));
+    fds.store_prg(0x4089, 0x80);
+    fds.store_prg(0x4040, 0xff);
+    assert_eq!(fds.peek_prg(0x4040), Some(0x3f));
+}
+
+#[test]
+fn test_audio_envelope() {
+    let mut fds = new_fds(Disk::new_from_buffer(new_side()).unwrap());
+    fds.store_prg(0x4023, 0x02);
+    fds.store_prg(0x408a, 0x01);
+    // Start the wave, which runs the envelopes.
+    fds.store_prg(0x4083, 0x00);
+    // Increase the gain every 8 cycles.
+    fds.store_prg(0x4080, 0x40);
+
+    fds.clock_cpu(8 * 3);
+    assert_eq!(fds.peek_prg(0x4090), Some(3));
+
+    // The gain stops at 32.
+    fds.clock_cpu(8 * 40);
+    assert_eq!(fds.peek_prg(0x4090), Some(32));
+}
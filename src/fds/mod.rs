@@ -0,0 +1,444 @@
+pub mod audio;
+pub mod disk;
+
+// Tests for the Famicom Disk System.
+#[cfg(test)]
+mod fds_test;
+
+use crate::cartridge::Mapper;
+use crate::cartridge::chr::{Chr, DEFAULT_CHR_RAM_SIZE};
+use crate::fds::audio::FdsAudio;
+use crate::fds::disk::Disk;
+use crate::rom::{MirrorType, RomError};
+use crate::utils::io::read_binary;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub const BIOS_SIZE: usize = 0x2000;
+const PRG_RAM_SIZE: usize = 0x8000;
+
+// The BIOS is looked for next to the disk image under this name, unless
+// given explicitly.
+const BIOS_FILE_NAME: &str = "disksys.rom";
+
+// $4022: Keep reloading the timer after it fires.
+const TIMER_REPEAT: u8 = 0b0000_0001;
+// $4022: Enable the timer IRQ.
+const TIMER_ENABLE: u8 = 0b0000_0010;
+
+// $4023: Enable the disk registers.
+const DISK_IO_ENABLE: u8 = 0b0000_0001;
+// $4023: Enable the sound registers.
+const SOUND_IO_ENABLE: u8 = 0b0000_0010;
+
+// $4025: Turn on the drive motor.
+const CONTROL_MOTOR: u8 = 0b0000_0001;
+// $4025: Hold the transfer in its initial state.
+const CONTROL_RESET_TRANSFER: u8 = 0b0000_0010;
+// $4025: Read (1) or write (0) the disk.
+const CONTROL_READ: u8 = 0b0000_0100;
+// $4025: Horizontal (1) or vertical (0) mirroring.
+const CONTROL_HORIZONTAL: u8 = 0b0000_1000;
+// $4025: Write the CRC instead of $4024.
+const CONTROL_CRC: u8 = 0b0001_0000;
+// $4025: Start transferring once the next block starts. Clear while in a
+// gap.
+const CONTROL_READY: u8 = 0b0100_0000;
+// $4025: Fire an IRQ after each byte transferred.
+const CONTROL_IRQ: u8 = 0b1000_0000;
+
+// $4030: The timer IRQ fired.
+const STATUS_TIMER_IRQ: u8 = 0b0000_0001;
+// $4030: A byte was transferred.
+const STATUS_TRANSFER_COMPLETE: u8 = 0b0000_0010;
+// $4030: The head is at the end of the disk.
+const STATUS_END_OF_HEAD: u8 = 0b0100_0000;
+
+// $4032: No disk in the drive.
+const DRIVE_NO_DISK: u8 = 0b0000_0001;
+// $4032: The drive isn't scanning the disk.
+const DRIVE_NOT_READY: u8 = 0b0000_0010;
+// $4032: The disk is write protected (or missing).
+const DRIVE_WRITE_PROTECTED: u8 = 0b0000_0100;
+
+// $4033: Battery level good.
+const BATTERY_GOOD: u8 = 0b1000_0000;
+
+// How long the head takes to go back to the start of the disk, and then to
+// move over each byte, in CPU cycles.
+const REWIND_CYCLES: u32 = 50000;
+const BYTE_CYCLES: u32 = 150;
+
+// The Famicom Disk System: a RAM adapter that plugs into the cartridge slot,
+// with the disk drive attached to it. It isn't a mapper, but it takes the
+// cartridge's place.
+//
+// CPU $4020-$4026: Timer IRQ, disk and control registers (write).
+// CPU $4030-$4033: Status, disk data and drive registers (read).
+// CPU $4040-$4092: Expansion audio registers (not heard), see FdsAudio.
+// CPU $6000-$DFFF: 32 KB PRG RAM, which games load themselves into.
+// CPU $E000-$FFFF: 8 KB BIOS ROM.
+// PPU $0000-$1FFF: 8 KB CHR RAM.
+//
+// The drive reads and writes a byte every 150 CPU cycles or so, firing an
+// IRQ for each one, and takes a while to rewind once it gets to the end of
+// the disk.
+//
+// See http://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System for
+// more details.
+pub struct Fds {
+    disk: Rc<RefCell<Disk>>,
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    audio: FdsAudio,
+
+    io_enable: u8,
+    control: u8,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_control: u8,
+
+    write_data: u8,
+    read_data: u8,
+
+    // Reading the status and data registers acknowledges these.
+    timer_irq: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+
+    // Where the head is on the inserted side, and how long until it gets to
+    // the next byte.
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    motor_on: bool,
+    // Whether a block has started since the drive became ready to read.
+    gap_ended: bool,
+    crc: u16,
+    previous_crc_control: bool,
+}
+
+impl Fds {
+    pub fn new(disk: Rc<RefCell<Disk>>, bios: Vec<u8>) -> Fds {
+        Fds {
+            disk,
+            bios,
+            prg_ram: vec![0x00; PRG_RAM_SIZE],
+            chr: Chr::new_ram(DEFAULT_CHR_RAM_SIZE),
+            audio: FdsAudio::new(),
+            io_enable: 0x00,
+            control: 0x00,
+            timer_reload: 0x0000,
+            timer_counter: 0x0000,
+            timer_control: 0x00,
+            write_data: 0x00,
+            read_data: 0x00,
+            timer_irq: false,
+            disk_irq: false,
+            transfer_complete: false,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            motor_on: false,
+            gap_ended: false,
+            crc: 0x0000,
+            previous_crc_control: false,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4020 => {
+                self.timer_reload =
+                    (self.timer_reload & 0xff00) | u16::from(value);
+            }
+            0x4021 => {
+                self.timer_reload =
+                    (self.timer_reload & 0x00ff) | (u16::from(value) << 8);
+            }
+            0x4022 => {
+                self.timer_control = value;
+                if self.timer_enabled() {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.io_enable = value;
+                if value & DISK_IO_ENABLE == 0 {
+                    self.timer_control &= !TIMER_ENABLE;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 => {
+                self.control = value;
+                self.motor_on = value & CONTROL_MOTOR != 0;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn timer_enabled(&self) -> bool {
+        self.timer_control & TIMER_ENABLE != 0
+            && self.io_enable & DISK_IO_ENABLE != 0
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled() {
+            return;
+        }
+
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            if self.timer_control & TIMER_REPEAT == 0 {
+                self.timer_control &= !TIMER_ENABLE;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    // Moves the head along the disk, reading or writing a byte whenever it
+    // gets to the next one.
+    fn clock_drive(&mut self) {
+        let mut disk = self.disk.borrow_mut();
+        disk.clock();
+
+        if disk.side().is_none() || !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.control & CONTROL_RESET_TRANSFER != 0 && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.end_of_head = false;
+            self.delay = REWIND_CYCLES;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let ready = self.control & CONTROL_READY != 0;
+        let crc_control = self.control & CONTROL_CRC != 0;
+        let mut irq = self.control & CONTROL_IRQ != 0;
+        if !ready {
+            self.gap_ended = false;
+            self.crc = 0x0000;
+        }
+
+        if self.control & CONTROL_READ != 0 {
+            let value = disk.read(self.position);
+            // The first byte after a gap is the start of a block, which
+            // doesn't get passed on.
+            if ready && value != 0x00 && !self.gap_ended {
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.read_data = value;
+                self.transfer_complete = true;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut value = 0x00;
+            if !crc_control {
+                value = self.write_data;
+                self.transfer_complete = true;
+                self.disk_irq |= irq;
+            }
+            if !ready {
+                value = 0x00;
+            }
+            if !crc_control {
+                self.crc = update_crc(self.crc, value);
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = update_crc(self.crc, 0x00);
+                    self.crc = update_crc(self.crc, 0x00);
+                }
+                value = self.crc as u8;
+                self.crc >>= 8;
+            }
+            disk.write(self.position, value);
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = crc_control;
+
+        self.position += 1;
+        if self.position >= disk.side_size() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    // Reading the status register acknowledges the IRQs, and reading the
+    // data register acknowledges the transfer.
+    fn read_prg(&mut self, address: u16) -> Option<u8> {
+        let value = self.peek_prg(address);
+        if self.io_enable & DISK_IO_ENABLE != 0 {
+            match address {
+                0x4030 => {
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                    self.transfer_complete = false;
+                }
+                0x4031 => {
+                    self.disk_irq = false;
+                    self.transfer_complete = false;
+                }
+                _ => {}
+            }
+        }
+        value
+    }
+
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        let disk_io = self.io_enable & DISK_IO_ENABLE != 0;
+        let sound_io = self.io_enable & SOUND_IO_ENABLE != 0;
+        match address {
+            0x4030 if disk_io => {
+                let mut status = 0x00;
+                if self.timer_irq {
+                    status |= STATUS_TIMER_IRQ;
+                }
+                if self.transfer_complete {
+                    status |= STATUS_TRANSFER_COMPLETE;
+                }
+                if self.end_of_head {
+                    status |= STATUS_END_OF_HEAD;
+                }
+                Some(status)
+            }
+            0x4031 if disk_io => Some(self.read_data),
+            0x4032 if disk_io => {
+                let inserted = self.disk.borrow().side().is_some();
+                let mut status = 0x00;
+                if !inserted {
+                    status |= DRIVE_NO_DISK | DRIVE_WRITE_PROTECTED;
+                }
+                if !inserted || !self.scanning {
+                    status |= DRIVE_NOT_READY;
+                }
+                Some(status)
+            }
+            0x4033 if disk_io => Some(BATTERY_GOOD),
+            0x4040..=0x4092 if sound_io => self.audio.fetch(address),
+            0x6000..=0xdfff => Some(self.prg_ram[address as usize - 0x6000]),
+            0xe000..=0xffff => Some(self.bios[address as usize - 0xe000]),
+            _ => None,
+        }
+    }
+
+    fn store_prg(&mut self, address: u16, value: u8) -> u8 {
+        let disk_io = self.io_enable & DISK_IO_ENABLE != 0;
+        let sound_io = self.io_enable & SOUND_IO_ENABLE != 0;
+        match address {
+            // $4023 is the only register that works with disk I/O disabled.
+            0x4023 => self.write_register(address, value),
+            0x4020..=0x4026 if disk_io => self.write_register(address, value),
+            0x4040..=0x408a if sound_io => self.audio.store(address, value),
+            0x6000..=0xdfff => {
+                let index = address as usize - 0x6000;
+                let old_value = self.prg_ram[index];
+                self.prg_ram[index] = value;
+                return old_value;
+            }
+            // The BIOS can't be written to.
+            0xe000..=0xffff => return self.bios[address as usize - 0xe000],
+            _ => {}
+        }
+        0x00
+    }
+
+    fn fetch_chr(&self, address: u16) -> u8 {
+        self.chr.fetch(address as usize)
+    }
+
+    fn store_chr(&mut self, address: u16, value: u8) -> u8 {
+        self.chr.store(address as usize, value)
+    }
+
+    fn mirror_type(&self) -> MirrorType {
+        if self.control & CONTROL_HORIZONTAL != 0 {
+            MirrorType::Horizontal
+        } else {
+            MirrorType::Vertical
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn clock_cpu(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.clock_timer();
+            self.clock_drive();
+            self.audio.clock();
+        }
+    }
+}
+
+// Adds a byte to the CRC of a block being written to the disk, which is
+// CRC-16/KERMIT.
+fn update_crc(mut crc: u16, value: u8) -> u16 {
+    for bit in 0..8 {
+        let carry = crc & 0x0001 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if value & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
+
+// Loads the BIOS from "path", or from disksys.rom next to the disk image at
+// "disk_path" if there isn't one.
+pub fn load_bios(
+    path: Option<&Path>,
+    disk_path: &Path,
+) -> Result<Vec<u8>, RomError> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => disk_path
+            .parent()
+            .map(|directory| directory.join(BIOS_FILE_NAME))
+            .unwrap_or_else(|| PathBuf::from(BIOS_FILE_NAME)),
+    };
+
+    let bios = read_binary(path)?;
+    if bios.len() != BIOS_SIZE {
+        return Err(RomError::BiosSize {
+            expected: BIOS_SIZE,
+            found: bios.len(),
+        });
+    }
+    Ok(bios)
+}
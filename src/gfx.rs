@@ -1,5 +1,5 @@
 use crate::ppu::{SCREEN_HEIGHT, SCREEN_SIZE, SCREEN_WIDTH};
-use sdl2::pixels::PixelFormatEnum::BGR24;
+use sdl2::pixels::PixelFormatEnum::RGB24;
 use sdl2::render::{Canvas, TextureAccess, TextureCreator};
 use sdl2::video::{Window, WindowContext};
 use sdl2::{EventPump, Sdl, init};
@@ -280,7 +280,7 @@ impl Gfx {
         let mut texture = self
             .texture_creator
             .create_texture(
-                BGR24,
+                RGB24,
                 TextureAccess::Streaming,
                 SCREEN_WIDTH as u32,
                 SCREEN_HEIGHT as u32,
@@ -1,18 +1,46 @@
 extern crate clap;
 extern crate sdl2;
 
+mod cartridge;
 mod cpu;
+mod fds;
 mod gfx;
 mod nes;
+mod nsf;
+mod patch;
 mod ppu;
 mod rom;
+mod unif;
 mod utils;
 
+// Tests for ROM file parsing.
+#[cfg(test)]
+mod rom_test;
+
+// Tests for ROM patching.
+#[cfg(test)]
+mod patch_test;
+
+// Tests for the UNIF loader.
+#[cfg(test)]
+mod unif_test;
+
+use cartridge::Cartridge;
 use clap::{ArgAction, arg, command};
+use fds::Fds;
+use fds::disk::Disk;
 use gfx::Gfx;
 use nes::{Nes, Options};
-use rom::RomFile;
+use nsf::NsfPlayer;
+use nsf::file::NsfFile;
+use rom::{MirrorType, RomFile};
 use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
 
 // The version of neskimo that we're building.
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -22,7 +50,7 @@ fn main() {
         .version(VERSION)
         .author("Pat Lillis <lillispm@gmail.com>")
         .about("A bare-bones NES emulator written in Rust.")
-        .arg(arg!(<ROM> "The .nes ROM file to run"))
+        .arg(arg!(<ROM> "The .nes or .unf ROM file or .fds disk image to run, or .nsf or .nsfe music file to play"))
         .arg(arg!(-l --logfile <LOGFILE> "Writes the CPU log to a file"))
         .arg(
             arg!(-p --"program-counter" <PROGRAM_COUNTER> "Sets the initial program counter to the provided hex value")
@@ -30,15 +58,30 @@ fn main() {
         .arg(
             arg!(-d --"mem-dump" <MEM_DUMP> "When execution reaches this point, contents of memory will be written to mem_dump.bin")
         )
+        .arg(
+            arg!(-w --watch <WATCH> "Prints every read, write and execution of an address or range of addresses, given in hex like 0300 or 0300-03FF. Can be given more than once")
+                .action(ArgAction::Append)
+        )
         .arg(
             arg!(-f --fps "Print frames-per-second during emulator run")
                 .action(ArgAction::SetTrue)
         )
+        .arg(
+            arg!(--patch <PATCH> "Applies an IPS, UPS or BPS patch to the ROM before running it. Can be given more than once")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            arg!(--"fds-bios" <FDS_BIOS> "The Famicom Disk System BIOS to run .fds images with. Defaults to disksys.rom next to the image")
+        )
         .after_help(
             "EXAMPLES:
     neskimo mario.nes
     neskimo -l=testing.log donkey_kong.nes
     neskimo -p=C000 castlevania.nes
+    neskimo --patch=translation.ips mother.nes
+    neskimo mega_man_2.nsf
+    neskimo --fds-bios=disksys.rom zelda.fds
+    neskimo --watch=0300-03FF --watch=6000 metroid.nes
     neskimo --logfile=testing.log --program-counter=0F00 my-cool-game.nes"
         )
         .get_matches();
@@ -46,7 +89,19 @@ fn main() {
     // .expect() is safe here ROM is required, so clap will crash if it's not
     // there.
     let file_name = matches.get_one::<String>("ROM").expect("ROM is required");
-    let rom = RomFile::new(file_name);
+
+    // Get the FPS flag.
+    let fps = *matches.get_one::<bool>("fps").unwrap_or(&false);
+
+    // Music rips get played instead of run.
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    if let Some("nsf" | "nsfe") = extension.as_deref() {
+        play_nsf(file_name, fps);
+        return;
+    }
 
     // Get logfile, program counter, and memory dump counter.
     let logfile = matches.get_one::<String>("logfile").cloned();
@@ -57,22 +112,63 @@ fn main() {
         .get_one::<String>("mem-dump")
         .and_then(|s| u16::from_str_radix(s, 16).ok());
 
-    // Get the FPS flag.
-    let fps = *matches.get_one::<bool>("fps").unwrap_or(&false);
+    let mut watches = Vec::new();
+    for watch in matches.get_many::<String>("watch").into_iter().flatten() {
+        match parse_address_range(watch) {
+            Some(range) => watches.push(range),
+            None => {
+                eprintln!("Invalid address range to watch: {}", watch);
+                process::exit(1);
+            }
+        }
+    }
 
-    let options = Options {
+    let mut options = Options {
         logfile,
         program_counter: pc,
         mem_dump_counter: dump_pc,
+        save_file: None,
+        watches,
     };
 
-    let mut nes = match rom {
-        Ok(rom) => Nes::new(&rom, options),
-        Err(e) => panic!("{}", e),
+    // Disk images need the Famicom Disk System instead of a cartridge.
+    if let Some("fds") = extension.as_deref() {
+        let bios = matches.get_one::<String>("fds-bios").map(Path::new);
+        run_fds(file_name, bios, options, fps);
+        return;
+    }
+
+    // Battery-backed PRG RAM is kept next to the ROM.
+    options.save_file = Some(Path::new(file_name).with_extension("sav"));
+    // A patch next to the ROM with the same name is used, unless some are
+    // given explicitly.
+    let mut patches: Vec<PathBuf> = matches
+        .get_many::<String>("patch")
+        .map(|paths| paths.map(PathBuf::from).collect())
+        .unwrap_or_default();
+    if patches.is_empty() {
+        patches.extend(patch::find_patch(Path::new(file_name)));
+    }
+
+    let rom = match RomFile::new(file_name, &patches) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Unable to load {}: {}", file_name, e);
+            process::exit(1);
+        }
+    };
+    for warning in rom.warnings.iter() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    let mut nes = match Nes::new(&rom, options) {
+        Ok(nes) => nes,
+        Err(e) => {
+            eprintln!("Unable to run {}: {}", file_name, e);
+            process::exit(1);
+        }
     };
 
-    // Test screen that fades from black to blue and has a single pixel moving
-    // across it.
     let (mut gfx, _) = Gfx::new(fps);
 
     'run: loop {
@@ -87,4 +183,113 @@ fn main() {
             }
         }
     }
+
+    if let Err(e) = nes.save() {
+        eprintln!("Unable to save {}: {}", file_name, e);
+    }
+}
+
+// Parses a hex address like "0300", or an inclusive range of them like
+// "0300-03FF".
+fn parse_address_range(s: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let start = u16::from_str_radix(start, 16).ok()?;
+    let end = u16::from_str_radix(end, 16).ok()?;
+    (start <= end).then_some(start..=end)
+}
+
+// Runs an FDS disk image with the BIOS at "bios_path", or disksys.rom next
+// to it, with F4 switching disk sides. Anything the game saves to the disk is
+// written next to the image on exit.
+fn run_fds(
+    file_name: &str,
+    bios_path: Option<&Path>,
+    options: Options,
+    fps: bool,
+) {
+    let disk = match Disk::new(file_name) {
+        Ok(disk) => Rc::new(RefCell::new(disk)),
+        Err(e) => {
+            eprintln!("Unable to load {}: {}", file_name, e);
+            process::exit(1);
+        }
+    };
+    let bios = match fds::load_bios(bios_path, Path::new(file_name)) {
+        Ok(bios) => bios,
+        Err(e) => {
+            eprintln!("Unable to load FDS BIOS: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let fds = Fds::new(disk.clone(), bios);
+    let cartridge =
+        Cartridge::with_mapper(Box::new(fds), MirrorType::Horizontal);
+    let mut nes = Nes::with_cartridge(cartridge, options);
+
+    let (mut gfx, _) = Gfx::new(fps);
+
+    'run: loop {
+        nes.run_frame();
+
+        gfx.composite(&mut nes.ppu.borrow_mut().screen);
+
+        for event in gfx.events.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'run,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    let side = disk.borrow_mut().switch_side();
+                    println!(
+                        "Inserting disk {} side {}",
+                        side / 2 + 1,
+                        if side % 2 == 0 { 'A' } else { 'B' }
+                    );
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    if let Err(e) = disk.borrow().save(file_name) {
+        eprintln!("Unable to save {}: {}", file_name, e);
+    }
+}
+
+// Plays an NSF or NSFe file, with the left and right arrow keys changing
+// tracks.
+fn play_nsf(file_name: &str, fps: bool) {
+    let nsf = match NsfFile::new(file_name) {
+        Ok(nsf) => nsf,
+        Err(e) => {
+            eprintln!("Unable to load {}: {}", file_name, e);
+            process::exit(1);
+        }
+    };
+    let mut player = NsfPlayer::new(nsf);
+
+    let (mut gfx, _) = Gfx::new(fps);
+
+    'run: loop {
+        player.run_frame();
+
+        gfx.composite(&mut player.screen);
+
+        for event in gfx.events.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'run,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Right),
+                    ..
+                } => player.next_song(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Left),
+                    ..
+                } => player.previous_song(),
+                _ => continue,
+            }
+        }
+    }
 }
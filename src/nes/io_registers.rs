@@ -0,0 +1,38 @@
+use crate::nes::memory::Memory;
+use std::ops::RangeInclusive;
+
+pub const IO_REGISTERS_START: u16 = 0x4000;
+pub const IO_REGISTERS_END: u16 = 0x4020;
+// APU status and the two controller ports. The rest of the registers are
+// write-only, so reading them gives open bus.
+pub const READABLE_IO_REGISTERS: RangeInclusive<u16> = 0x4015..=0x4017;
+
+// The APU and I/O registers at $4000-$401F, apart from OAMDMA at $4014,
+// which belongs to the PPU. Neither the APU nor the controllers are
+// emulated yet, so the registers just hold on to what was last written.
+//
+// See http://wiki.nesdev.com/w/index.php/2A03 for more details.
+pub struct IoRegisters {
+    registers: [u8; (IO_REGISTERS_END - IO_REGISTERS_START) as usize],
+}
+
+impl IoRegisters {
+    pub fn new() -> IoRegisters {
+        IoRegisters {
+            registers: [0x00; (IO_REGISTERS_END - IO_REGISTERS_START) as usize],
+        }
+    }
+}
+
+impl Memory for IoRegisters {
+    fn peek(&self, address: u16) -> u8 {
+        self.registers[(address - IO_REGISTERS_START) as usize]
+    }
+
+    fn store(&mut self, address: u16, value: u8) -> u8 {
+        let index = (address - IO_REGISTERS_START) as usize;
+        let old_value = self.registers[index];
+        self.registers[index] = value;
+        old_value
+    }
+}
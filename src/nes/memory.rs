@@ -1,18 +1,48 @@
+use crate::nes::watch::{Access, MemoryAccess, Watch, Watcher};
 use crate::utils;
 use log::warn;
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::mem;
+use std::ops::{Bound, Range, RangeBounds, RangeInclusive};
 use std::rc::Rc;
 
 // 2^16 unsigned bytes.
 pub const DEFAULT_MEMORY_SIZE: usize = 65536;
 
 pub trait Memory {
-    // Fetches a byte from the specified address in memory.
-    fn fetch(&self, address: u16) -> u8;
+    // Reads a byte from the specified address in memory, the way the CPU
+    // does. Reading some hardware registers changes their state. Default
+    // implementation has no side effects, and just peeks.
+    fn read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
+
+    // Looks at the byte at the specified address in memory without any side
+    // effects, for trace logging, dumps and debuggers.
+    fn peek(&self, address: u16) -> u8;
+
+    // Reads a byte the way read() does, or returns None if nothing at the
+    // address drives the data bus, for MappedMemory to fill in with open
+    // bus. Default implementation always drives it.
+    fn read_bus(&mut self, address: u16) -> Option<u8> {
+        Some(self.read(address))
+    }
+
+    // Looks at a byte the way peek() does, or returns None where read_bus()
+    // would.
+    fn peek_bus(&self, address: u16) -> Option<u8> {
+        Some(self.peek(address))
+    }
+
+    // Reads the opcode of the instruction the CPU is about to execute at
+    // "pc", "cycle" CPU cycles after power on. Default implementation is a
+    // plain read.
+    fn read_opcode(&mut self, pc: u16, _cycle: u64) -> u8 {
+        self.read(pc)
+    }
 
     // Stores value into memory at the specified address.
     // Returns the previous value.
@@ -27,25 +57,25 @@ pub trait Memory {
         Result::Ok(())
     }
 
-    // Fetches two bytes stored consecutively in memory.
-    fn fetch_u16(&self, address: u16) -> u16 {
-        let low = self.fetch(address);
-        let high = self.fetch(address + 1);
+    // Reads two bytes stored consecutively in memory.
+    fn read_u16(&mut self, address: u16) -> u16 {
+        let low = self.read(address);
+        let high = self.read(address + 1);
         utils::arithmetic::concat_bytes(high, low)
     }
 
-    // Fetches two bytes from memory.
+    // Reads two bytes from memory.
     //
     // This method implements a bug found in the original MOS6502 hardware,
     // where the two bytes read had to be on the same page. So if the low
-    // byte is stored at 0x33ff, then the high byte would be fetched from
+    // byte is stored at 0x33ff, then the high byte would be read from
     // 0x3300 instead of 0x3400.
-    fn fetch_u16_wrap_msb(&self, address: u16) -> u16 {
-        let low = self.fetch(address);
+    fn read_u16_wrap_msb(&mut self, address: u16) -> u16 {
+        let low = self.read(address);
         let high = if address & 0x00ff == 0x00ff {
-            self.fetch(address & 0xff00)
+            self.read(address & 0xff00)
         } else {
-            self.fetch(address + 1)
+            self.read(address + 1)
         };
         utils::arithmetic::concat_bytes(high, low)
     }
@@ -104,8 +134,9 @@ impl Memory for BasicMemory {
         }
     }
 
-    // Fetches a byte from the specified address in memory.
-    fn fetch(&self, address: u16) -> u8 {
+    // Looks at a byte at the specified address in memory. Plain memory has
+    // no side effects, so reads do the same.
+    fn peek(&self, address: u16) -> u8 {
         self.backing_store[address as usize]
     }
 
@@ -124,147 +155,258 @@ impl Memory for BasicMemory {
     }
 }
 
+// Size of the pages that mirrors are set up in.
+const PAGE_SIZE: usize = 0x100;
+const PAGE_COUNT: usize = DEFAULT_MEMORY_SIZE / PAGE_SIZE;
+
+// Marks an address without a delegate in the dispatch tables.
+const UNMAPPED: u8 = u8::MAX;
+
 // A memory storage type that can defer memory operations to memory
 // implementations, with each fetch/store operation potentially mapped to a
-// specific memory implementation. In addition, memory addresses can be
-// mirrored to always point at another memory address.
-#[derive(Default)]
+// specific memory implementation. In addition, ranges of memory can be
+// mirrored by masking their addresses, the way hardware that doesn't decode
+// every address line repeats itself.
+//
+// Lookups go through flat dispatch tables holding the index of the delegate
+// for each address, so that accessing memory doesn't have to search for the
+// right delegate.
+//
+// Addresses that nothing is mapped to behave like open bus: reads return the
+// last value that was on the data bus, and writes go nowhere. So do reads
+// that the delegate doesn't drive the bus for, see Memory::read_bus().
+//
+// Accesses can also be watched, for debugging. With no watches added, the
+// only cost is checking that there aren't any.
 pub struct MappedMemory {
-    mirrors: HashMap<u16, u16>,
     delegates: Vec<Rc<RefCell<dyn Memory>>>,
     // Maps from memory address to index in "delegates" where the address is
-    // mapped to.
-    fetch: HashMap<u16, usize>,
-    store: HashMap<u16, usize>,
+    // mapped to, or UNMAPPED.
+    fetch: Box<[u8; DEFAULT_MEMORY_SIZE]>,
+    store: Box<[u8; DEFAULT_MEMORY_SIZE]>,
+    // Mask applied to addresses in each page before looking them up.
+    mirror_masks: [u16; PAGE_COUNT],
+    // The last value read or written through this memory.
+    data_bus: u8,
+
+    watches: Vec<Watch>,
+    // The instruction the CPU is executing, from the last opcode read, to
+    // report to watches.
+    pc: u16,
+    cycle: u64,
+}
+
+impl Default for MappedMemory {
+    fn default() -> MappedMemory {
+        MappedMemory::new()
+    }
 }
 
 impl MappedMemory {
     pub fn new() -> MappedMemory {
         MappedMemory {
-            mirrors: HashMap::new(),
             delegates: Vec::new(),
-            fetch: HashMap::new(),
-            store: HashMap::new(),
+            fetch: Box::new([UNMAPPED; DEFAULT_MEMORY_SIZE]),
+            store: Box::new([UNMAPPED; DEFAULT_MEMORY_SIZE]),
+            mirror_masks: [0xffff; PAGE_COUNT],
+            data_bus: 0x00,
+            watches: Vec::new(),
+            pc: 0x0000,
+            cycle: 0,
         }
     }
 
-    // Add new address mirrors. Note that this will override any previous
-    // mirroring for those addresses.
-    pub fn add_mirrors(&mut self, mirrors: &HashMap<u16, u16>) {
-        for (from, to) in mirrors {
-            self.add_mirror(*from, *to);
-        }
+    // Mirrors the addresses in "range" by masking them with "mask", so that
+    // e.g. a mask of $07FF repeats $0000-$07FF all the way through the
+    // range. The range has to start and end on 256 byte page boundaries.
+    // Note that this will override any previous mirroring for the range.
+    pub fn add_mirror<R: RangeBounds<u16>>(&mut self, range: R, mask: u16) {
+        let range = to_indexes(range);
+        assert!(
+            range.start.is_multiple_of(PAGE_SIZE)
+                && range.end.is_multiple_of(PAGE_SIZE),
+            "Mirrored range {:#06x}-{:#06x} isn't aligned to pages",
+            range.start,
+            range.end
+        );
+        self.mirror_masks[range.start / PAGE_SIZE..range.end / PAGE_SIZE]
+            .fill(mask);
     }
 
-    // Add a new address mirror. Note that this will override any previous
-    // mirroring for the "from" address.
-    pub fn add_mirror(&mut self, from: u16, to: u16) {
-        if from == to {
-            warn!("Address {} cannot be mirrored to itself", from);
-        }
-        if let Some(old_mirror) = self.mirrors.insert(from, to) {
-            warn!(
-                concat!(
-                    "Address {:#04x} is already mirrored to address {:#04x}. ",
-                    "Overriding with new mirroring."
-                ),
-                from, old_mirror
-            );
-        }
+    // Applies any mirroring to "address".
+    fn get_mirror(&self, address: u16) -> u16 {
+        address & self.mirror_masks[address as usize / PAGE_SIZE]
     }
 
-    // Helper function to get the mirrored address value for the passed in
-    // address. If there is no mirror defined for the address, will return back
-    // the passed in address instead.
-    fn get_mirror(&self, address: u16) -> u16 {
-        match self.mirrors.get(&address) {
-            Some(mapped_address) => *mapped_address,
-            None => address,
+    // The delegate that reads of "address" go to, if there is one. Expects
+    // the address to be mirrored already.
+    fn fetch_delegate(&self, address: u16) -> Option<&Rc<RefCell<dyn Memory>>> {
+        match self.fetch[address as usize] {
+            UNMAPPED => None,
+            delegate_index => Some(&self.delegates[delegate_index as usize]),
         }
     }
 
-    // Add new fetch & store mappings for all addresses specified by "mapping".
-    // Note that this will override any previous mappings for the specified
-    // addresses.
-    pub fn add_mapping<I1, I2>(
+    // Add new fetch & store mappings for the addresses in the "fetch_range"
+    // and "store_range" ranges. Note that this will override any previous
+    // mappings for those addresses.
+    pub fn add_mapping<R1, R2>(
         &mut self,
         memory: Rc<RefCell<dyn Memory>>,
-        fetch_addresses: I1,
-        store_addresses: I2,
+        fetch_range: R1,
+        store_range: R2,
     ) where
-        I1: IntoIterator<Item = u16>,
-        I2: IntoIterator<Item = u16>,
+        R1: RangeBounds<u16>,
+        R2: RangeBounds<u16>,
     {
+        let fetch_range = to_indexes(fetch_range);
+        let store_range = to_indexes(store_range);
+
         // If we don't end up adding any mappings, then we don't need to keep
         // the delegate around.
-        let mut mappings_added = false;
-
-        self.delegates.push(memory.clone());
-        let delegate_index = self.delegates.len() - 1;
-
-        // Add fetch mappings.
-        for fetch_address in fetch_addresses {
-            if self.fetch.insert(fetch_address, delegate_index).is_some() {
-                warn!(
-                    concat!(
-                        "Address {:#04x} is already mapped for fetch. ",
-                        "Overriding with new mapping."
-                    ),
-                    fetch_address
-                );
-            }
-            mappings_added = true;
+        if fetch_range.is_empty() && store_range.is_empty() {
+            return;
         }
 
-        // Add store mappings.
-        for store_address in store_addresses {
-            if self.store.insert(store_address, delegate_index).is_some() {
+        assert!(
+            self.delegates.len() < UNMAPPED as usize,
+            "Too many memory delegates"
+        );
+        let delegate_index = self.delegates.len() as u8;
+        self.delegates.push(memory);
+
+        for (table, range, kind) in [
+            (&mut self.fetch, fetch_range, "fetch"),
+            (&mut self.store, store_range, "store"),
+        ] {
+            if table[range.clone()].iter().any(|index| *index != UNMAPPED) {
                 warn!(
                     concat!(
-                        "Address {:#04x} is already mapped for store. ",
+                        "Addresses {:#06x}-{:#06x} are already mapped for {}. ",
                         "Overriding with new mapping."
                     ),
-                    store_address
+                    range.start,
+                    range.end.saturating_sub(1),
+                    kind
                 );
             }
-            mappings_added = true;
+            table[range].fill(delegate_index);
         }
+    }
+
+    // Reports the kinds of "accesses" to addresses in "range" to "watcher".
+    // Addresses are matched both as the CPU sees them and after mirroring,
+    // so watching $0000 also catches accesses to $0800.
+    pub fn add_watch(
+        &mut self,
+        range: RangeInclusive<u16>,
+        accesses: &[Access],
+        watcher: Watcher,
+    ) {
+        self.watches.push(Watch::new(range, accesses, watcher));
+    }
+
+    // Passes an access on to the watches that are watching it, dropping any
+    // whose watchers have gone away. Callers check for watches first, so
+    // that there's no cost without any.
+    fn notify(
+        &mut self,
+        access: Access,
+        address: u16,
+        mapped_address: u16,
+        value: u8,
+    ) {
+        let event = MemoryAccess {
+            access,
+            address,
+            value,
+            pc: self.pc,
+            cycle: self.cycle,
+        };
+        // The watches are taken out while they run, so that callbacks can
+        // look at the rest of memory.
+        let mut watches = mem::take(&mut self.watches);
+        watches.retain_mut(|watch| {
+            !watch.matches(access, address, mapped_address)
+                || watch.notify(&event, self)
+        });
+        self.watches = watches;
+    }
+
+    // Reads through to the delegate for "address", reporting the read to
+    // any watches as "access".
+    fn read_as(&mut self, address: u16, access: Access) -> u8 {
+        let mapped_address = self.get_mirror(address);
 
-        // If no mappings were added, remove delegate from list.
-        if !mappings_added {
-            self.delegates.remove(delegate_index);
+        self.data_bus = self
+            .fetch_delegate(mapped_address)
+            .and_then(|delegate| delegate.borrow_mut().read_bus(mapped_address))
+            .unwrap_or(self.data_bus);
+        if !self.watches.is_empty() {
+            self.notify(access, address, mapped_address, self.data_bus);
         }
+        self.data_bus
     }
 }
 
+// Converts a range of addresses to a range of indexes into the dispatch
+// tables.
+fn to_indexes<R: RangeBounds<u16>>(range: R) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(start) => *start as usize,
+        Bound::Excluded(start) => *start as usize + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => *end as usize + 1,
+        Bound::Excluded(end) => *end as usize,
+        Bound::Unbounded => DEFAULT_MEMORY_SIZE,
+    };
+    start..end.max(start)
+}
+
 impl Memory for MappedMemory {
-    fn fetch(&self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> u8 {
+        self.read_as(address, Access::Read)
+    }
+
+    fn read_opcode(&mut self, pc: u16, cycle: u64) -> u8 {
+        self.pc = pc;
+        self.cycle = cycle;
+        self.read_as(pc, Access::Execute)
+    }
+
+    fn peek(&self, address: u16) -> u8 {
         let mapped_address = self.get_mirror(address);
 
-        // Use the mirrored fetch, or the backing memory.
-        match self.fetch.get(&mapped_address) {
-            Some(delegate_index) => self.delegates[*delegate_index]
-                .borrow()
-                .fetch(mapped_address),
-            None => panic!(
-                "No delegate memory for fetch at address {:#04x}",
-                address
-            ),
-        }
+        self.fetch_delegate(mapped_address)
+            .and_then(|delegate| delegate.borrow().peek_bus(mapped_address))
+            .unwrap_or(self.data_bus)
     }
 
     fn store(&mut self, address: u16, value: u8) -> u8 {
         let mapped_address = self.get_mirror(address);
+        let old_value = mem::replace(&mut self.data_bus, value);
+        if !self.watches.is_empty() {
+            self.notify(Access::Write, address, mapped_address, value);
+        }
 
-        // Use the mirrored store, or the backing memory.
-        match self.store.get_mut(&mapped_address) {
-            Some(delegate_index) => self.delegates[*delegate_index]
+        match self.store[mapped_address as usize] {
+            UNMAPPED => {
+                warn!("Ignoring store to unmapped address {:#06x}", address);
+                old_value
+            }
+            delegate_index => self.delegates[delegate_index as usize]
                 .borrow_mut()
                 .store(mapped_address, value),
-            None => panic!(
-                "No delegate memory for store at address {:#04x}",
-                address
-            ),
         }
     }
+
+    // Dumps what the CPU would see at every address, without disturbing any
+    // of the mapped hardware.
+    fn dump(&self, file: &mut File) -> io::Result<()> {
+        let contents: Vec<u8> =
+            (0..=u16::MAX).map(|address| self.peek(address)).collect();
+        file.write_all(&contents)
+    }
 }
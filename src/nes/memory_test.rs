@@ -1,17 +1,21 @@
+use crate::cpu::Cpu;
 use crate::nes::memory::{
     BasicMemory, DEFAULT_MEMORY_SIZE, MappedMemory, Memory,
 };
-use maplit::hashmap;
-use std::cell::RefCell;
+use crate::nes::watch::{Access, MemoryAccess, Watcher};
+use crate::nes::{Nes, Options};
+use crate::rom::RomFile;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::sync::mpsc;
 
 #[test]
 fn test_fetch_store() {
     let mut memory = BasicMemory::with_default_size();
 
     // Test initial fetch.
-    assert_eq!(memory.fetch(0x0000), 0x00);
-    assert_eq!(memory.fetch(0xf000), 0x00);
+    assert_eq!(memory.read(0x0000), 0x00);
+    assert_eq!(memory.read(0xf000), 0x00);
 
     // Test store.
     assert_eq!(memory.store(0x0000, 0x01), 0x00);
@@ -20,18 +24,18 @@ fn test_fetch_store() {
     assert_eq!(memory.store(0xf000, 0xf2), 0xf1);
 
     // Test fetch after store.
-    assert_eq!(memory.fetch(0x0000), 0x02);
-    assert_eq!(memory.fetch(0xf000), 0xf2);
+    assert_eq!(memory.read(0x0000), 0x02);
+    assert_eq!(memory.read(0xf000), 0xf2);
 }
 
 #[test]
-fn test_fetch_u16() {
+fn test_read_u16() {
     let mut memory = BasicMemory::with_default_size();
 
     // Test initial fetch.
-    assert_eq!(memory.fetch_u16(0x0000), 0x0000);
-    assert_eq!(memory.fetch_u16(0xf000), 0x0000);
-    assert_eq!(memory.fetch_u16(0x33ff), 0x0000);
+    assert_eq!(memory.read_u16(0x0000), 0x0000);
+    assert_eq!(memory.read_u16(0xf000), 0x0000);
+    assert_eq!(memory.read_u16(0x33ff), 0x0000);
 
     memory.store(0x0001, 0x01);
     memory.store(0x0000, 0x02);
@@ -41,19 +45,19 @@ fn test_fetch_u16() {
     memory.store(0x33ff, 0xad);
 
     // Test fetch after store.
-    assert_eq!(memory.fetch_u16(0x0000), 0x0102);
-    assert_eq!(memory.fetch_u16(0xf000), 0xf1f2);
-    assert_eq!(memory.fetch_u16(0x33ff), 0xdead);
+    assert_eq!(memory.read_u16(0x0000), 0x0102);
+    assert_eq!(memory.read_u16(0xf000), 0xf1f2);
+    assert_eq!(memory.read_u16(0x33ff), 0xdead);
 }
 
 #[test]
-fn test_fetch_u16_wrap_msb() {
+fn test_read_u16_wrap_msb() {
     let mut memory = BasicMemory::with_default_size();
 
     // Test initial fetch.
-    assert_eq!(memory.fetch_u16_wrap_msb(0x0000), 0x0000);
-    assert_eq!(memory.fetch_u16_wrap_msb(0xf000), 0x0000);
-    assert_eq!(memory.fetch_u16_wrap_msb(0x33ff), 0x0000);
+    assert_eq!(memory.read_u16_wrap_msb(0x0000), 0x0000);
+    assert_eq!(memory.read_u16_wrap_msb(0xf000), 0x0000);
+    assert_eq!(memory.read_u16_wrap_msb(0x33ff), 0x0000);
 
     memory.store(0x0001, 0x01);
     memory.store(0x0000, 0x02);
@@ -64,9 +68,9 @@ fn test_fetch_u16_wrap_msb() {
     memory.store(0x33ff, 0xad);
 
     // Test fetch after store.
-    assert_eq!(memory.fetch_u16_wrap_msb(0x0000), 0x0102);
-    assert_eq!(memory.fetch_u16_wrap_msb(0xf000), 0xf1f2);
-    assert_eq!(memory.fetch_u16_wrap_msb(0x33ff), 0xedad);
+    assert_eq!(memory.read_u16_wrap_msb(0x0000), 0x0102);
+    assert_eq!(memory.read_u16_wrap_msb(0xf000), 0xf1f2);
+    assert_eq!(memory.read_u16_wrap_msb(0x33ff), 0xedad);
 }
 
 #[test]
@@ -98,17 +102,17 @@ fn test_store_bytes() {
     memory.store_bytes(0xf000, &[0x11, 0x22, 0x33]);
     memory.store_bytes(0x33ff, &[0x77, 0x88, 0x99]);
 
-    assert_eq!(memory.fetch(0x0000), 0xff);
-    assert_eq!(memory.fetch(0x0001), 0xee);
-    assert_eq!(memory.fetch(0x0002), 0xdd);
+    assert_eq!(memory.read(0x0000), 0xff);
+    assert_eq!(memory.read(0x0001), 0xee);
+    assert_eq!(memory.read(0x0002), 0xdd);
 
-    assert_eq!(memory.fetch(0xf000), 0x11);
-    assert_eq!(memory.fetch(0xf001), 0x22);
-    assert_eq!(memory.fetch(0xf002), 0x33);
+    assert_eq!(memory.read(0xf000), 0x11);
+    assert_eq!(memory.read(0xf001), 0x22);
+    assert_eq!(memory.read(0xf002), 0x33);
 
-    assert_eq!(memory.fetch(0x33ff), 0x77);
-    assert_eq!(memory.fetch(0x3400), 0x88);
-    assert_eq!(memory.fetch(0x3401), 0x99);
+    assert_eq!(memory.read(0x33ff), 0x77);
+    assert_eq!(memory.read(0x3400), 0x88);
+    assert_eq!(memory.read(0x3401), 0x99);
 }
 
 #[test]
@@ -133,32 +137,182 @@ fn test_basic_memory_default_size() {
 fn test_mirror() {
     // Set up values in destination addresses of fallback memory.
     let memory = Rc::new(RefCell::new(BasicMemory::with_default_size()));
-    memory.borrow_mut().store(0xa000, 0xaa);
-    memory.borrow_mut().store(0xb000, 0xbb);
-    memory.borrow_mut().store(0xc000, 0xcc);
-    memory.borrow_mut().store(0xd000, 0xdd);
+    memory.borrow_mut().store(0xa00c, 0xcc);
+    memory.borrow_mut().store(0xa00d, 0xdd);
 
-    // Set up mirrors in mapped memory.
+    // Set up mirrors in mapped memory, repeating $A000-$A0FF through
+    // $A000-$BFFF.
     let mut mapped_memory = MappedMemory::new();
-    mapped_memory.add_mapping(
-        memory,
-        (0x00..DEFAULT_MEMORY_SIZE).map(|x| x as u16),
-        (0x00..DEFAULT_MEMORY_SIZE).map(|x| x as u16),
-    );
-    mapped_memory.add_mirrors(&hashmap! {
-        0x0c00 => 0xc000,
-        0x0d00 => 0xd000,
-    });
+    mapped_memory.add_mapping(memory, .., ..);
+    mapped_memory.add_mirror(0xa000..0xc000, 0xa0ff);
 
     // Test mirrored address fetch.
-    assert_eq!(mapped_memory.fetch(0x0c00), 0xcc);
-    assert_eq!(mapped_memory.fetch(0x0d00), 0xdd);
+    assert_eq!(mapped_memory.read(0xa10c), 0xcc);
+    assert_eq!(mapped_memory.read(0xbf0d), 0xdd);
 
     // Test mirrored address store.
-    assert_eq!(mapped_memory.store(0x0c00, 0x0c), 0xcc);
-    assert_eq!(mapped_memory.fetch(0x0c00), 0x0c);
-    assert_eq!(mapped_memory.store(0x0d00, 0x0d), 0xdd);
-    assert_eq!(mapped_memory.fetch(0x0d00), 0x0d);
+    assert_eq!(mapped_memory.store(0xb00c, 0x0c), 0xcc);
+    assert_eq!(mapped_memory.read(0xa00c), 0x0c);
+    assert_eq!(mapped_memory.store(0xbf0d, 0x0d), 0xdd);
+    assert_eq!(mapped_memory.read(0xa00d), 0x0d);
+
+    // Addresses outside of the mirrored range are left alone.
+    assert_eq!(mapped_memory.read(0xc00c), 0x00);
+}
+
+#[test]
+fn test_mirrored_range() {
+    let memory = Rc::new(RefCell::new(BasicMemory::new(0x0800)));
+    let mut mapped_memory = MappedMemory::new();
+    mapped_memory.add_mapping(memory, 0x0000..0x0800, 0x0000..0x0800);
+    mapped_memory.add_mirror(0x0000..0x2000, 0x07ff);
+
+    mapped_memory.store(0x0123, 0x45);
+    assert_eq!(mapped_memory.read(0x0923), 0x45);
+    assert_eq!(mapped_memory.read(0x1923), 0x45);
+    mapped_memory.store(0x1fff, 0x67);
+    assert_eq!(mapped_memory.read(0x07ff), 0x67);
+}
+
+#[test]
+fn test_cpu_memory_map() {
+    // An NROM-128 ROM with the reset vector pointing at $8000.
+    let mut rom = b"NES\x1a\x01\x01".to_vec();
+    rom.resize(16, 0x00);
+    let mut prg_rom = vec![0x00; 0x4000];
+    prg_rom[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0x80]);
+    rom.extend(prg_rom);
+    rom.extend([0x00; 0x2000]);
+    let rom = RomFile::new_from_buffer("test".to_string(), &rom).unwrap();
+    let mut nes = Nes::new(&rom, Options::default()).unwrap();
+    let memory = &mut nes.cpu.memory;
+
+    // 2 KB of RAM is mirrored 4 times.
+    memory.store(0x0001, 0x12);
+    assert_eq!(memory.read(0x0801), 0x12);
+    assert_eq!(memory.read(0x1001), 0x12);
+    assert_eq!(memory.read(0x1801), 0x12);
+
+    // PPU registers are mirrored every 8 bytes.
+    memory.store(0x3ffe, 0x23);
+    memory.store(0x3ffe, 0x45);
+    memory.store(0x2007, 0x67);
+    memory.store(0x2006, 0x23);
+    memory.store(0x2006, 0x45);
+    memory.read(0x2007);
+    assert_eq!(memory.read(0x3fff), 0x67);
+
+    // I/O registers and the cartridge are separate from RAM.
+    memory.store(0x4000, 0x89);
+    assert_eq!(memory.read(0x0000), 0x00);
+    // Write-only registers read back as open bus.
+    assert_eq!(memory.read(0x4000), 0x00);
+    memory.store(0x6000, 0xab);
+    assert_eq!(memory.read(0x6000), 0xab);
+    assert_eq!(memory.read(0x0000), 0x00);
+    assert_eq!(memory.read(0xfffd), 0x80);
+}
+
+#[test]
+fn test_watches() {
+    let memory = Rc::new(RefCell::new(BasicMemory::new(0x0800)));
+    let mut mapped_memory = MappedMemory::new();
+    mapped_memory.add_mapping(memory, 0x0000..0x0800, 0x0000..0x0800);
+    mapped_memory.add_mirror(0x0000..0x2000, 0x07ff);
+
+    let (sender, receiver) = mpsc::channel();
+    mapped_memory.add_watch(
+        0x0010..=0x001f,
+        &[Access::Write],
+        Watcher::Channel(sender),
+    );
+    let reads = Rc::new(Cell::new(0));
+    let watched_reads = reads.clone();
+    mapped_memory.add_watch(
+        0x0010..=0x0010,
+        &[Access::Read],
+        Watcher::Callback(Box::new(move |access, memory| {
+            // Callbacks can look at the rest of memory.
+            assert_eq!(memory.peek(0x0011), 0x34);
+            watched_reads.set(watched_reads.get() + access.value);
+        })),
+    );
+
+    // Only the kinds of accesses asked for are reported, including through
+    // mirrors.
+    mapped_memory.read_opcode(0x0200, 42);
+    mapped_memory.store(0x0011, 0x34);
+    mapped_memory.store(0x0020, 0x56);
+    mapped_memory.read(0x0011);
+    mapped_memory.store(0x1010, 0x12);
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        [
+            MemoryAccess {
+                access: Access::Write,
+                address: 0x0011,
+                value: 0x34,
+                pc: 0x0200,
+                cycle: 42,
+            },
+            MemoryAccess {
+                access: Access::Write,
+                address: 0x1010,
+                value: 0x12,
+                pc: 0x0200,
+                cycle: 42,
+            },
+        ]
+    );
+    mapped_memory.read(0x0810);
+    assert_eq!(reads.get(), 0x12);
+
+    // Peeking isn't an access.
+    mapped_memory.peek(0x0010);
+    assert_eq!(reads.get(), 0x12);
+
+    // Watches stop once their channel is closed.
+    drop(receiver);
+    mapped_memory.store(0x0010, 0x01);
+    mapped_memory.read(0x0010);
+    assert_eq!(reads.get(), 0x13);
+}
+
+#[test]
+fn test_watch_execution() {
+    let memory = Rc::new(RefCell::new(BasicMemory::with_default_size()));
+    let mut mapped_memory = MappedMemory::new();
+    mapped_memory.add_mapping(memory, .., ..);
+    // LDA $10, then STA $11.
+    mapped_memory.store_bytes(0x0200, &[0xa5, 0x10, 0x85, 0x11]);
+
+    let (sender, receiver) = mpsc::channel();
+    mapped_memory.add_watch(
+        0x0000..=0x00ff,
+        &[Access::Read, Access::Write],
+        Watcher::Channel(sender.clone()),
+    );
+    mapped_memory.add_watch(
+        0x0202..=0x0202,
+        &[Access::Execute],
+        Watcher::Channel(sender),
+    );
+
+    let mut cpu = Cpu::new(Box::new(mapped_memory), Some(0x0200));
+    cpu.execute();
+    cpu.execute();
+    let accesses: Vec<_> = receiver
+        .try_iter()
+        .map(|access| (access.access, access.address, access.pc, access.cycle))
+        .collect();
+    assert_eq!(
+        accesses,
+        [
+            (Access::Read, 0x0010, 0x0200, 0),
+            (Access::Execute, 0x0202, 0x0202, 3),
+            (Access::Write, 0x0011, 0x0202, 3),
+        ]
+    );
 }
 
 struct TestMemoryMapping {
@@ -174,8 +328,8 @@ impl TestMemoryMapping {
 }
 
 impl Memory for TestMemoryMapping {
-    // Test fetch just returns "address + 1", truncated to 8 bits.
-    fn fetch(&self, address: u16) -> u8 {
+    // Test peek just returns "address + 1", truncated to 8 bits.
+    fn peek(&self, address: u16) -> u8 {
         address.wrapping_add(1) as u8
     }
 
@@ -195,11 +349,51 @@ fn test_mappings() {
     let mut mapped_memory = MappedMemory::new();
     mapped_memory.add_mapping(
         mappings.clone(),
-        vec![0x0000, 0x0100],
-        vec![0x0000, 0x0100],
+        0x0000..=0x0100,
+        0x0100..0x0200,
     );
 
-    assert_eq!(mapped_memory.fetch(0x0000), 0x0001);
+    assert_eq!(mapped_memory.read(0x0000), 0x0001);
+    assert_eq!(mapped_memory.read(0x0100), 0x0001);
     assert_eq!(mapped_memory.store(0x0100, 0xff), 0x00);
-    assert_eq!(mappings.borrow().last_stored_value, 0xff);
+    assert_eq!(mapped_memory.store(0x01ff, 0xee), 0xff);
+    assert_eq!(mappings.borrow().last_stored_value, 0xee);
+}
+
+#[test]
+fn test_mapping_override() {
+    let memory = Rc::new(RefCell::new(BasicMemory::with_default_size()));
+    let mappings = Rc::new(RefCell::new(TestMemoryMapping::new()));
+    let mut mapped_memory = MappedMemory::new();
+    mapped_memory.add_mapping(memory, .., ..);
+    mapped_memory.add_mapping(mappings, 0x4014..=0x4014, 0x4014..=0x4014);
+
+    // Later mappings take over the addresses they cover.
+    assert_eq!(mapped_memory.read(0x4013), 0x00);
+    assert_eq!(mapped_memory.read(0x4014), 0x15);
+    assert_eq!(mapped_memory.read(0x4015), 0x00);
+}
+
+#[test]
+fn test_open_bus() {
+    let memory = Rc::new(RefCell::new(BasicMemory::with_default_size()));
+    let mut mapped_memory = MappedMemory::new();
+    mapped_memory.add_mapping(memory, 0x0000..0x4000, 0x0000..0x4000);
+
+    // Reads from unmapped addresses return whatever was last on the bus.
+    mapped_memory.store(0x0010, 0x42);
+    assert_eq!(mapped_memory.read(0x4000), 0x42);
+    mapped_memory.store(0x0011, 0x24);
+    assert_eq!(mapped_memory.read(0x0010), 0x42);
+    assert_eq!(mapped_memory.read(0x8000), 0x42);
+
+    // Peeking doesn't drive the bus.
+    assert_eq!(mapped_memory.peek(0x0011), 0x24);
+    assert_eq!(mapped_memory.peek(0x8000), 0x42);
+
+    // Writes to unmapped addresses go nowhere, but still drive the bus.
+    mapped_memory.store(0x8000, 0x99);
+    assert_eq!(mapped_memory.read(0x8000), 0x99);
+    assert_eq!(mapped_memory.read(0x0010), 0x42);
+    assert_eq!(mapped_memory.read(0x0011), 0x24);
 }
@@ -1,26 +1,49 @@
+pub mod io_registers;
 pub mod memory;
+pub mod watch;
 
 // Tests for various NES stuff.
 #[cfg(test)]
 mod memory_test;
 
+use crate::cartridge::Cartridge;
 use crate::cpu::Cpu;
-use crate::nes::memory::{
-    BasicMemory, DEFAULT_MEMORY_SIZE, MappedMemory, Memory,
+use crate::nes::io_registers::{
+    IO_REGISTERS_END, IO_REGISTERS_START, IoRegisters, READABLE_IO_REGISTERS,
 };
+use crate::nes::memory::{BasicMemory, MappedMemory, Memory};
+use crate::nes::watch::{Access, MemoryAccess, Watcher};
 use crate::ppu::Ppu;
-use crate::rom::PRG_ROM_SIZE;
 use crate::rom::RomFile;
+use crate::utils::io::read_binary;
+use log::warn;
 use std::cell::RefCell;
+use std::fs;
 use std::fs::{File, OpenOptions};
+use std::io;
 use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::time::Instant;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 
-const CPU_FREQ: u32 = 1_789_773; // 1.789773 MHz
+pub const CPU_FREQ: u32 = 1_789_773; // 1.789773 MHz
 const PPU_CYCLES_PER_CPU_CYCLE: u32 = 3; // PPU runs 3x faster than CPU
 const FRAME_RATE: u32 = 60;
-const CPU_CYCLES_PER_FRAME: u32 = CPU_FREQ / FRAME_RATE; // ~29780 cycles
+pub const CPU_CYCLES_PER_FRAME: u32 = CPU_FREQ / FRAME_RATE; // ~29780 cycles
+pub const FRAME_TIME: Duration = Duration::from_nanos(16_666_667); // 60Hz
+const OAM_DMA_CYCLES: u32 = 513; // CPU is suspended while copying to OAM
+const RAM_SIZE: u16 = 0x0800; // 2 KB of internal RAM
+const RAM_END: u16 = 0x2000; // RAM is mirrored up to here
+const PPU_REGISTERS_START: u16 = 0x2000;
+const PPU_REGISTERS_SIZE: u16 = 0x0008;
+const PPU_REGISTERS_END: u16 = 0x4000; // PPU registers are mirrored up to here
+const OAMDMA: u16 = 0x4014; // PPU register among the I/O registers
+const CARTRIDGE_START: u16 = 0x4020; // Start of cartridge space for the CPU
+const SAVE_INTERVAL: u32 = 5 * FRAME_RATE; // Frames between saving PRG RAM
+const MEM_DUMP_FILE: &str = "mem-dump.bin"; // Written by --mem-dump
 
 #[derive(Debug, Default)]
 pub struct Options {
@@ -32,18 +55,49 @@ pub struct Options {
 
     // Program counter to dump memory at.
     pub mem_dump_counter: Option<u16>,
+
+    // File to keep battery-backed PRG RAM in.
+    pub save_file: Option<PathBuf>,
+
+    // Addresses to print every access to.
+    pub watches: Vec<RangeInclusive<u16>>,
 }
 
 pub struct Nes {
     pub cpu: Cpu,
     pub ppu: Rc<RefCell<Ppu>>,
+    cartridge: Rc<RefCell<Cartridge>>,
     cycles: u32,
-    last_frame_start: std::time::Instant,
+    frames: u32,
+    last_frame_start: Instant,
     logfile: Option<File>,
+    save_file: Option<PathBuf>,
+    // Accesses to the watched addresses from the options.
+    watched: Option<Receiver<MemoryAccess>>,
 }
 
 impl Nes {
-    pub fn new(rom: &RomFile, options: Options) -> Nes {
+    pub fn new(rom: &RomFile, options: Options) -> io::Result<Nes> {
+        let mut cartridge = Cartridge::new(rom)?;
+
+        // Restore battery-backed PRG RAM from the last run.
+        if let Some(path) = options.save_file.as_ref().filter(|p| p.is_file()) {
+            cartridge.load_save(&read_binary(path)?);
+        }
+
+        let mut nes = Nes::with_cartridge(cartridge, options);
+
+        // Copy trainer data to 0x7000.
+        if let Some(data) = rom.trainer_data {
+            nes.cpu.memory.store_bytes(0x7000, &data);
+        }
+
+        Ok(nes)
+    }
+
+    // Builds a console around an already loaded cartridge, e.g. the Famicom
+    // Disk System adapter.
+    pub fn with_cartridge(cartridge: Cartridge, options: Options) -> Nes {
         // Set up log file.
         let buffer = options.logfile.and_then(|f| {
             OpenOptions::new()
@@ -54,56 +108,93 @@ impl Nes {
                 .ok()
         });
 
+        let cartridge = Rc::new(RefCell::new(cartridge));
+
+        // The CPU's memory map. Internal RAM and the PPU registers are only
+        // partially decoded, so they repeat all the way up to the next chip.
+        //
+        // See http://wiki.nesdev.com/w/index.php/CPU_memory_map for more
+        // details.
         let mut memory = MappedMemory::new();
         memory.add_mapping(
-            Rc::new(RefCell::new(BasicMemory::with_default_size())),
-            (0x00..DEFAULT_MEMORY_SIZE).map(|x| x as u16),
-            (0x00..DEFAULT_MEMORY_SIZE).map(|x| x as u16),
+            Rc::new(RefCell::new(BasicMemory::new(RAM_SIZE as usize))),
+            0x0000..RAM_SIZE,
+            0x0000..RAM_SIZE,
         );
-        let ppu = Rc::new(RefCell::new(Ppu::new(rom.mirror_type)));
+        memory.add_mirror(0x0000..RAM_END, RAM_SIZE - 1);
+        memory.add_mirror(
+            PPU_REGISTERS_START..PPU_REGISTERS_END,
+            PPU_REGISTERS_START | (PPU_REGISTERS_SIZE - 1),
+        );
+        // OAMDMA sits among the I/O registers, but goes to the PPU.
+        let io_registers = Rc::new(RefCell::new(IoRegisters::new()));
+        // Only a few of them can be read; the rest are left as open bus.
         memory.add_mapping(
-            ppu.clone(),
-            Ppu::mapped_addresses(),
-            Ppu::mapped_addresses(),
+            io_registers.clone(),
+            READABLE_IO_REGISTERS,
+            OAMDMA + 1..IO_REGISTERS_END,
         );
-
-        // Copy trainer data to 0x7000.
-        if let Some(data) = rom.trainer_data {
-            memory.store_bytes(0x7000, &data);
+        memory.add_mapping(io_registers, 0..0, IO_REGISTERS_START..OAMDMA);
+        memory.add_mapping(
+            cartridge.clone(),
+            CARTRIDGE_START..=0xffff,
+            CARTRIDGE_START..=0xffff,
+        );
+        let ppu = Rc::new(RefCell::new(Ppu::new(cartridge.clone())));
+        for range in Ppu::mapped_addresses() {
+            memory.add_mapping(ppu.clone(), range.clone(), range);
         }
 
-        // Copy PRG ROM into 0x800.
-        let lower_bank = 0x8000;
-        let upper_bank = 0xc000;
-        match rom.prg_rom_data.len() {
-            1 => {
-                // Store PRG ROM in lower bank.
-                memory.store_bytes(lower_bank, &rom.prg_rom_data[0]);
-
-                // Mirror the upper bank to point to the lower bank.
-                for offset in 0x0000..PRG_ROM_SIZE {
-                    let from = upper_bank + offset as u16;
-                    let to = lower_bank + offset as u16;
-                    memory.add_mirror(from, to);
-                }
-            }
-            2 => {
-                memory.store_bytes(lower_bank, &rom.prg_rom_data[0]);
-                memory.store_bytes(upper_bank, &rom.prg_rom_data[1]);
+        // Accesses to watched addresses are printed once a frame.
+        let watched = if options.watches.is_empty() {
+            None
+        } else {
+            let (sender, receiver) = mpsc::channel();
+            for range in options.watches {
+                memory.add_watch(
+                    range,
+                    &[Access::Read, Access::Write, Access::Execute],
+                    Watcher::Channel(sender.clone()),
+                );
             }
-            _ => {}
+            Some(receiver)
+        };
+
+        // Dump memory when execution reaches the given point.
+        if let Some(pc) = options.mem_dump_counter {
+            memory.add_watch(
+                pc..=pc,
+                &[Access::Execute],
+                Watcher::Callback(Box::new(|_, memory| {
+                    if let Ok(mut file) = File::create(MEM_DUMP_FILE) {
+                        memory.dump(&mut file).ok();
+                    }
+                })),
+            );
         }
 
         Nes {
-            cpu: Cpu::new(
-                Box::new(memory),
-                options.program_counter,
-                options.mem_dump_counter,
-            ),
+            cpu: Cpu::new(Box::new(memory), options.program_counter),
             ppu,
+            cartridge,
             cycles: 0,
+            frames: 0,
             last_frame_start: Instant::now(),
             logfile: buffer,
+            save_file: options.save_file,
+            watched,
+        }
+    }
+
+    // Writes battery-backed PRG RAM to the save file, if it has changed
+    // since the last save.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = &self.save_file else {
+            return Ok(());
+        };
+        match self.cartridge.borrow_mut().take_save() {
+            Some(save) => fs::write(path, save),
+            None => Ok(()),
         }
     }
 
@@ -112,38 +203,65 @@ impl Nes {
         let mut cpu_cycles_this_frame = 0;
 
         while cpu_cycles_this_frame < CPU_CYCLES_PER_FRAME {
-            let cpu_cycles = self.cpu.execute();
+            let mut cpu_cycles = self.cpu.execute();
+
+            // Writing to OAMDMA copies a page of CPU memory into OAM.
+            let oam_dma = self.ppu.borrow_mut().take_oam_dma();
+            if let Some(page) = oam_dma {
+                let dma_cycles = self.oam_dma(page);
+                self.cpu.cycles += u64::from(dma_cycles);
+                cpu_cycles += dma_cycles;
+            }
+
+            // Pass on any NMI the PPU raised while the last instruction ran.
+            // This happens before stepping the PPU, so that a PPUSTATUS read
+            // right after VBlank starts still has a chance to suppress it.
+            if self.ppu.borrow_mut().take_nmi() {
+                self.cpu.nmi = true;
+            }
 
             let ppu_cycles = cpu_cycles * PPU_CYCLES_PER_CPU_CYCLE;
-            let (_new_frame, _v_blank) = self.ppu.borrow_mut().step(ppu_cycles);
+            self.ppu.borrow_mut().step(ppu_cycles);
 
-            // TODO: Handle NMI on VBlank
-            // if v_blank {
-            //     self.cpu.nmi = true;
-            // }
+            // The cartridge's IRQ line stays asserted until the game
+            // acknowledges it.
+            self.cartridge.borrow_mut().clock_cpu(cpu_cycles);
+            self.cpu.irq = self.cartridge.borrow().irq();
 
             cpu_cycles_this_frame += cpu_cycles;
         }
 
-        self.sync_frame();
+        if let Some(watched) = &self.watched {
+            for access in watched.try_iter() {
+                println!("{}", access);
+            }
+        }
+
+        sync_frame(&mut self.last_frame_start);
+
+        // Save every so often, so that progress isn't lost if the emulator
+        // doesn't exit cleanly.
+        self.frames = self.frames.wrapping_add(1);
+        if self.frames.is_multiple_of(SAVE_INTERVAL)
+            && let Err(e) = self.save()
+        {
+            warn!("Unable to save PRG RAM: {}", e);
+        }
 
         if self.logfile.is_some() {
             self.log();
         }
     }
 
-    fn sync_frame(&mut self) {
-        const FRAME_TIME: std::time::Duration =
-            std::time::Duration::from_nanos(16_666_667); // 60Hz
-        let elapsed = self.last_frame_start.elapsed();
-        if elapsed < FRAME_TIME {
-            std::thread::sleep(FRAME_TIME - elapsed);
-        } else {
-            // We're running behind, don't sleep
-            // Log if significant drift occurs
-            println!("Frame time drift: {:?}", elapsed - FRAME_TIME);
+    // Copies the 256 bytes of CPU memory at $XX00-$XXFF into OAM, where XX is
+    // "page". Returns the number of CPU cycles taken.
+    fn oam_dma(&mut self, page: u8) -> u32 {
+        let base = u16::from(page) << 8;
+        for offset in 0x00..=0xff {
+            let value = self.cpu.memory.read(base | offset);
+            self.ppu.borrow_mut().write_oam(value);
         }
-        self.last_frame_start = std::time::Instant::now();
+        OAM_DMA_CYCLES
     }
 
     fn log(&mut self) {
@@ -159,3 +277,17 @@ impl Nes {
         }
     }
 }
+
+// Sleeps until a frame's worth of time has passed since "last_frame_start",
+// then resets it to now.
+pub fn sync_frame(last_frame_start: &mut Instant) {
+    let elapsed = last_frame_start.elapsed();
+    if elapsed < FRAME_TIME {
+        std::thread::sleep(FRAME_TIME - elapsed);
+    } else {
+        // We're running behind, don't sleep
+        // Log if significant drift occurs
+        println!("Frame time drift: {:?}", elapsed - FRAME_TIME);
+    }
+    *last_frame_start = Instant::now();
+}
@@ -0,0 +1,105 @@
+use crate::nes::memory::MappedMemory;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::mpsc::Sender;
+
+// The kinds of memory accesses that can be watched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    // The CPU fetching the opcode of an instruction it's about to execute.
+    Execute,
+}
+
+impl Access {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// A single watched access, as handed to watchers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub access: Access,
+    // The address as the CPU saw it, before any mirroring.
+    pub address: u16,
+    // The value read, written, or the opcode executed.
+    pub value: u8,
+    // Address of the instruction that made the access.
+    pub pc: u16,
+    // CPU cycles since power on, at the start of that instruction.
+    pub cycle: u64,
+}
+
+impl fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} ${:04X} = {:02X} at PC ${:04X}, cycle {}",
+            self.access, self.address, self.value, self.pc, self.cycle
+        )
+    }
+}
+
+pub type WatchCallback = Box<dyn FnMut(&MemoryAccess, &MappedMemory)>;
+
+// Where watched accesses get reported. Callbacks are run straight away, and
+// can look at memory (without disturbing it) through the MappedMemory they
+// get. Channels let accesses be handled later, like once a frame or on
+// another thread, and stop being watched once the receiving end is dropped.
+pub enum Watcher {
+    Callback(WatchCallback),
+    Channel(Sender<MemoryAccess>),
+}
+
+// A watcher along with the addresses and kinds of accesses it's watching.
+pub struct Watch {
+    range: RangeInclusive<u16>,
+    // Bits from Access::bit().
+    accesses: u8,
+    watcher: Watcher,
+}
+
+impl Watch {
+    pub fn new(
+        range: RangeInclusive<u16>,
+        accesses: &[Access],
+        watcher: Watcher,
+    ) -> Watch {
+        Watch {
+            range,
+            accesses: accesses.iter().fold(0, |bits, a| bits | a.bit()),
+            watcher,
+        }
+    }
+
+    // Whether this watches "access" to "address", or its mirror at
+    // "mapped_address".
+    pub fn matches(
+        &self,
+        access: Access,
+        address: u16,
+        mapped_address: u16,
+    ) -> bool {
+        self.accesses & access.bit() != 0
+            && (self.range.contains(&address)
+                || self.range.contains(&mapped_address))
+    }
+
+    // Reports "access" to the watcher. Returns false if the watcher has gone
+    // away, and the watch can be dropped.
+    pub fn notify(
+        &mut self,
+        access: &MemoryAccess,
+        memory: &MappedMemory,
+    ) -> bool {
+        match &mut self.watcher {
+            Watcher::Callback(callback) => {
+                callback(access, memory);
+                true
+            }
+            Watcher::Channel(sender) => sender.send(*access).is_ok(),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::rom::RomError;
+use crate::utils::io::read_binary;
+use log::warn;
+
+pub const NSF_MAGIC: &[u8] = b"NESM\x1a";
+pub const NSFE_MAGIC: &[u8] = b"NSFE";
+
+const HEADER_SIZE: usize = 0x80;
+
+// Default time between PLAY calls, in microseconds, for files that don't say.
+pub const DEFAULT_NTSC_SPEED: u16 = 16_639;
+pub const DEFAULT_PAL_SPEED: u16 = 19_997;
+
+// A music rip, made of a game's sound driver and music data, and the
+// addresses of routines to start a song and to play one frame of it.
+//
+// See http://wiki.nesdev.com/w/index.php/NSF and
+// http://wiki.nesdev.com/w/index.php/NSFe for more details.
+#[derive(Debug)]
+pub struct NsfFile {
+    pub total_songs: u8,
+    // 0-based, unlike in NSF headers.
+    pub starting_song: u8,
+
+    // Where "data" goes in CPU memory.
+    pub load_address: u16,
+    // Called with the song number in A to start playing it.
+    pub init_address: u16,
+    // Called once per frame, at "ntsc_speed" or "pal_speed".
+    pub play_address: u16,
+
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+    // Names for each song, if the file has them (NSFe only).
+    pub track_labels: Vec<String>,
+
+    // Microseconds between PLAY calls.
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    // Whether the music was made for PAL consoles only.
+    pub pal: bool,
+
+    // Initial banks for $8000-$FFFF in 4 KB units, if the file uses
+    // bankswitching.
+    pub banks: Option<[u8; 8]>,
+
+    // Expansion sound chips used (bit 0 = VRC6, 1 = VRC7, 2 = FDS, 3 = MMC5,
+    // 4 = Namco 163, 5 = Sunsoft 5B).
+    pub sound_chips: u8,
+
+    pub data: Vec<u8>,
+}
+
+impl NsfFile {
+    pub fn new(file_name: &str) -> Result<NsfFile, RomError> {
+        NsfFile::new_from_buffer(&read_binary(file_name)?)
+    }
+
+    pub fn new_from_buffer(file: &[u8]) -> Result<NsfFile, RomError> {
+        if file.starts_with(NSFE_MAGIC) {
+            NsfFile::new_nsfe(&file[NSFE_MAGIC.len()..])
+        } else if file.starts_with(NSF_MAGIC) {
+            NsfFile::new_nsf(file)
+        } else {
+            Err(RomError::MissingMagic)
+        }
+    }
+
+    fn new_nsf(file: &[u8]) -> Result<NsfFile, RomError> {
+        if file.len() < HEADER_SIZE {
+            return Err(RomError::HeaderTruncated {
+                expected: HEADER_SIZE,
+                found: file.len(),
+            });
+        }
+        let word = |offset: usize| {
+            u16::from_le_bytes([file[offset], file[offset + 1]])
+        };
+
+        // Byte $7A: 0 = NTSC, 1 = PAL, 2 or 3 = both.
+        let pal = file[0x7a] & 0x03 == 0x01;
+
+        let mut banks = [0x00; 8];
+        banks.copy_from_slice(&file[0x70..0x78]);
+
+        // Bytes $7D-$7F: In NSF2 files, the length of the program data, if
+        // there's metadata after it. 0 means it runs to the end of the file.
+        let data = &file[HEADER_SIZE..];
+        let data_length = usize::from(file[0x7d])
+            | usize::from(file[0x7e]) << 8
+            | usize::from(file[0x7f]) << 16;
+        let data = if file[0x05] >= 2 && data_length != 0 {
+            &data[..data_length.min(data.len())]
+        } else {
+            data
+        };
+
+        Ok(NsfFile {
+            total_songs: file[0x06],
+            // 1-based in the header. Out of range songs start the last one.
+            starting_song: file[0x07]
+                .saturating_sub(1)
+                .min(file[0x06].saturating_sub(1)),
+            load_address: word(0x08),
+            init_address: word(0x0a),
+            play_address: word(0x0c),
+            name: header_string(&file[0x0e..0x2e]),
+            artist: header_string(&file[0x2e..0x4e]),
+            copyright: header_string(&file[0x4e..0x6e]),
+            track_labels: Vec::new(),
+            ntsc_speed: word(0x6e),
+            pal_speed: word(0x78),
+            pal,
+            // Bankswitching is used if any of the initial banks are set.
+            banks: banks.iter().any(|&bank| bank != 0).then_some(banks),
+            sound_chips: file[0x7b],
+            data: data.to_vec(),
+        })
+    }
+
+    // NSFe files are a list of chunks, each with a 4 byte length and a 4 byte
+    // ID.
+    fn new_nsfe(mut chunks: &[u8]) -> Result<NsfFile, RomError> {
+        let mut nsf = NsfFile {
+            total_songs: 1,
+            starting_song: 0,
+            load_address: 0x8000,
+            init_address: 0x8000,
+            play_address: 0x8000,
+            name: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            track_labels: Vec::new(),
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            pal: false,
+            banks: None,
+            sound_chips: 0,
+            data: Vec::new(),
+        };
+        let mut has_info = false;
+        let mut has_data = false;
+
+        while !chunks.is_empty() {
+            let id = &chunks[4.min(chunks.len())..8.min(chunks.len())];
+            let id_string = String::from_utf8_lossy(id).to_string();
+            if chunks.len() < 8 {
+                return Err(RomError::ChunkTruncated {
+                    id: id_string,
+                    expected: 8,
+                    found: chunks.len(),
+                });
+            }
+            let length = u32::from_le_bytes([
+                chunks[0], chunks[1], chunks[2], chunks[3],
+            ]) as usize;
+            if chunks.len() - 8 < length {
+                return Err(RomError::ChunkTruncated {
+                    id: id_string,
+                    expected: length,
+                    found: chunks.len() - 8,
+                });
+            }
+            let chunk = &chunks[8..8 + length];
+            chunks = &chunks[8 + length..];
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(RomError::ChunkTruncated {
+                            id: id_string,
+                            expected: 8,
+                            found: chunk.len(),
+                        });
+                    }
+                    let word = |offset: usize| {
+                        u16::from_le_bytes([chunk[offset], chunk[offset + 1]])
+                    };
+                    nsf.load_address = word(0);
+                    nsf.init_address = word(2);
+                    nsf.play_address = word(4);
+                    nsf.pal = chunk[6] & 0x03 == 0x01;
+                    nsf.sound_chips = chunk[7];
+                    nsf.total_songs = chunk.get(8).copied().unwrap_or(1);
+                    // 0-based here. Out of range songs start the last one.
+                    nsf.starting_song = chunk
+                        .get(9)
+                        .copied()
+                        .unwrap_or(0)
+                        .min(nsf.total_songs.saturating_sub(1));
+                    has_info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    has_data = true;
+                }
+                b"BANK" => {
+                    let mut banks = [0x00; 8];
+                    let length = chunk.len().min(8);
+                    banks[..length].copy_from_slice(&chunk[..length]);
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" if chunk.len() >= 2 => {
+                    nsf.ntsc_speed = u16::from_le_bytes([chunk[0], chunk[1]]);
+                    if chunk.len() >= 4 {
+                        nsf.pal_speed =
+                            u16::from_le_bytes([chunk[2], chunk[3]]);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = chunk_strings(chunk).into_iter();
+                    nsf.name = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => nsf.track_labels = chunk_strings(chunk),
+                b"NEND" => break,
+                // Chunks starting with a capital letter are needed to play
+                // the file correctly, others are optional.
+                _ if id[0].is_ascii_uppercase() => {
+                    warn!("Unsupported NSFe chunk {}", id_string)
+                }
+                _ => (),
+            }
+        }
+
+        if !has_info {
+            return Err(RomError::MissingChunk("INFO"));
+        }
+        if !has_data {
+            return Err(RomError::MissingChunk("DATA"));
+        }
+        Ok(nsf)
+    }
+
+    // The name of a song, for display.
+    pub fn track_label(&self, song: u8) -> Option<&str> {
+        self.track_labels
+            .get(usize::from(song))
+            .map(String::as_str)
+            .filter(|label| !label.is_empty())
+    }
+}
+
+// Reads a NUL-padded string from an NSF header.
+fn header_string(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+// Reads a list of NUL-terminated strings from an NSFe chunk.
+fn chunk_strings(chunk: &[u8]) -> Vec<String> {
+    let chunk = chunk.strip_suffix(&[0]).unwrap_or(chunk);
+    chunk
+        .split(|&byte| byte == 0)
+        .map(|string| String::from_utf8_lossy(string).to_string())
+        .collect()
+}
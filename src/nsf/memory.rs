@@ -0,0 +1,85 @@
+use crate::nes::memory::Memory;
+use crate::nsf::file::NsfFile;
+
+pub const BANK_REGISTERS_START: u16 = 0x5ff8;
+
+const BANK_SIZE: usize = 0x1000;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+// The memory an NSF player maps at $5FF8-$FFFF: 8 bank registers at
+// $5FF8-$5FFF, 8 KB of RAM at $6000-$7FFF, and the music data at
+// $8000-$FFFF.
+//
+// Files that use bankswitching are split into 4 KB banks starting from
+// "load_address" rounded down to a multiple of 4 KB, and writing to
+// $5FF8 + N picks the bank at $8000 + N * $1000. Files that don't are just
+// loaded at "load_address".
+pub struct NsfMemory {
+    data: Vec<u8>,
+    ram: Vec<u8>,
+    bankswitched: bool,
+    initial_banks: [u8; 8],
+    banks: [u8; 8],
+}
+
+impl NsfMemory {
+    pub fn new(nsf: &NsfFile) -> NsfMemory {
+        let (padding, initial_banks) = match nsf.banks {
+            Some(banks) => (usize::from(nsf.load_address) % BANK_SIZE, banks),
+            None => (
+                usize::from(nsf.load_address.saturating_sub(0x8000)),
+                [0, 1, 2, 3, 4, 5, 6, 7],
+            ),
+        };
+        let mut data = vec![0x00; padding];
+        data.extend_from_slice(&nsf.data);
+
+        NsfMemory {
+            data,
+            ram: vec![0x00; PRG_RAM_SIZE],
+            bankswitched: nsf.banks.is_some(),
+            initial_banks,
+            banks: initial_banks,
+        }
+    }
+}
+
+impl Memory for NsfMemory {
+    // Clears RAM and sets the banks back to how they start.
+    fn reset(&mut self) {
+        self.ram.fill(0x00);
+        self.banks = self.initial_banks;
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7fff => self.ram[address as usize - 0x6000],
+            0x8000..=0xffff => {
+                let slot = (address as usize - 0x8000) / BANK_SIZE;
+                let bank = usize::from(self.banks[slot]);
+                let index = bank * BANK_SIZE + address as usize % BANK_SIZE;
+                self.data.get(index).copied().unwrap_or(0x00)
+            }
+            // The bank registers are write-only.
+            _ => 0x00,
+        }
+    }
+
+    fn store(&mut self, address: u16, value: u8) -> u8 {
+        match address {
+            BANK_REGISTERS_START..=0x5fff if self.bankswitched => {
+                let slot = (address - BANK_REGISTERS_START) as usize;
+                let old_value = self.banks[slot];
+                self.banks[slot] = value;
+                old_value
+            }
+            0x6000..=0x7fff => {
+                let index = address as usize - 0x6000;
+                let old_value = self.ram[index];
+                self.ram[index] = value;
+                old_value
+            }
+            _ => self.peek(address),
+        }
+    }
+}
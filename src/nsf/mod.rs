@@ -0,0 +1,191 @@
+pub mod file;
+pub mod memory;
+
+// Tests for the NSF player.
+#[cfg(test)]
+mod nsf_test;
+
+use crate::cpu::Cpu;
+use crate::gfx::draw_text;
+use crate::nes::memory::{BasicMemory, MappedMemory, Memory};
+use crate::nes::{CPU_FREQ, FRAME_TIME, sync_frame};
+use crate::nsf::file::{DEFAULT_NTSC_SPEED, DEFAULT_PAL_SPEED, NsfFile};
+use crate::nsf::memory::{BANK_REGISTERS_START, NsfMemory};
+use crate::ppu::{SCREEN_SIZE, SCREEN_WIDTH};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+// INIT and PLAY are called as subroutines that return here. Nothing is ever
+// executed at this address, the player just stops when it gets here.
+const RETURN_ADDRESS: u16 = 0x5ff0;
+
+// INIT and PLAY are cut off if they run for longer than a second, so that a
+// broken rip can't hang the player.
+const MAX_ROUTINE_CYCLES: u32 = CPU_FREQ;
+
+const TEXT_X: isize = 16;
+const LINE_HEIGHT: isize = 16;
+
+// Plays NSF music rips. Instead of running a game, the CPU calls the rip's
+// INIT routine to start a song, and then its PLAY routine at the rate the rip
+// asks for.
+pub struct NsfPlayer {
+    pub nsf: NsfFile,
+    pub cpu: Cpu,
+    ram: Rc<RefCell<BasicMemory>>,
+    nsf_memory: Rc<RefCell<NsfMemory>>,
+    // 0-based number of the song being played.
+    pub song: u8,
+    // Microseconds of the current frame not yet covered by PLAY calls.
+    play_time: u32,
+    pub screen: [u8; SCREEN_SIZE],
+    last_frame_start: Instant,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: NsfFile) -> NsfPlayer {
+        let ram = Rc::new(RefCell::new(BasicMemory::with_default_size()));
+        let nsf_memory = Rc::new(RefCell::new(NsfMemory::new(&nsf)));
+
+        let mut memory = MappedMemory::new();
+        memory.add_mapping(
+            ram.clone(),
+            0x0000..BANK_REGISTERS_START,
+            0x0000..BANK_REGISTERS_START,
+        );
+        memory.add_mapping(
+            nsf_memory.clone(),
+            BANK_REGISTERS_START..=0xffff,
+            BANK_REGISTERS_START..=0xffff,
+        );
+
+        let song = nsf.starting_song;
+        let mut player = NsfPlayer {
+            nsf,
+            cpu: Cpu::new(Box::new(memory), Some(RETURN_ADDRESS)),
+            ram,
+            nsf_memory,
+            song,
+            play_time: 0,
+            screen: [0x00; SCREEN_SIZE],
+            last_frame_start: Instant::now(),
+        };
+        player.start_song(song);
+        player
+    }
+
+    // Microseconds between PLAY calls.
+    fn play_speed(&self) -> u32 {
+        let speed = if self.nsf.pal {
+            self.nsf.pal_speed
+        } else {
+            self.nsf.ntsc_speed
+        };
+        match speed {
+            0 if self.nsf.pal => u32::from(DEFAULT_PAL_SPEED),
+            0 => u32::from(DEFAULT_NTSC_SPEED),
+            speed => u32::from(speed),
+        }
+    }
+
+    // Resets the machine and calls INIT for "song".
+    pub fn start_song(&mut self, song: u8) {
+        self.song = song;
+        self.play_time = 0;
+
+        self.ram.borrow_mut().reset();
+        self.nsf_memory.borrow_mut().reset();
+        self.cpu.reset_to_pc(RETURN_ADDRESS);
+
+        // Silence the APU, then enable all of its channels.
+        let memory = &mut self.cpu.memory;
+        for address in 0x4000..=0x4013 {
+            memory.store(address, 0x00);
+        }
+        memory.store(0x4015, 0x00);
+        memory.store(0x4015, 0x0f);
+        memory.store(0x4017, 0x40);
+
+        self.cpu.registers.a = song;
+        self.cpu.registers.x = u8::from(self.nsf.pal);
+        self.call_routine(self.nsf.init_address);
+    }
+
+    pub fn next_song(&mut self) {
+        let song =
+            (u16::from(self.song) + 1) % u16::from(self.nsf.total_songs.max(1));
+        self.start_song(song as u8);
+    }
+
+    pub fn previous_song(&mut self) {
+        let song = match self.song {
+            0 => self.nsf.total_songs.max(1) - 1,
+            song => song - 1,
+        };
+        self.start_song(song);
+    }
+
+    // Runs the subroutine at "address" until it returns. Returns the number
+    // of CPU cycles taken.
+    pub fn call_routine(&mut self, address: u16) -> u32 {
+        self.cpu.push_u16(RETURN_ADDRESS - 1);
+        self.cpu.registers.pc = address;
+
+        let mut cycles = 0;
+        while self.cpu.registers.pc != RETURN_ADDRESS
+            && cycles < MAX_ROUTINE_CYCLES
+        {
+            cycles += self.cpu.execute();
+        }
+        cycles
+    }
+
+    // Calls PLAY as many times as fit in a frame, and draws the track info.
+    pub fn run_frame(&mut self) {
+        let speed = self.play_speed();
+        self.play_time += FRAME_TIME.as_micros() as u32;
+        while self.play_time >= speed {
+            self.call_routine(self.nsf.play_address);
+            self.play_time -= speed;
+        }
+
+        self.draw();
+        sync_frame(&mut self.last_frame_start);
+    }
+
+    fn draw(&mut self) {
+        let mut lines = vec![
+            self.nsf.name.clone(),
+            self.nsf.artist.clone(),
+            self.nsf.copyright.clone(),
+            String::new(),
+            format!(
+                "Track {} / {}",
+                u16::from(self.song) + 1,
+                self.nsf.total_songs
+            ),
+        ];
+        if let Some(label) = self.nsf.track_label(self.song) {
+            lines.push(label.to_string());
+        }
+        lines.push(String::new());
+        lines.push("Left / Right: change track".to_string());
+
+        self.screen.fill(0x00);
+        for (index, line) in lines.iter().enumerate() {
+            // The font only has printable ASCII characters.
+            let line: String = line
+                .chars()
+                .map(|c| if c.is_ascii_graphic() { c } else { ' ' })
+                .collect();
+            draw_text(
+                &mut self.screen,
+                SCREEN_WIDTH,
+                TEXT_X,
+                LINE_HEIGHT * (index as isize + 2),
+                &line,
+            );
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::nsf::NsfPlayer;
+use crate::nsf::file::NsfFile;
+
+// A tiny sound driver, loaded at $8000:
+//
+//   INIT ($8000): STA $00; INC $01; RTS
+//   PLAY ($8005): INC $02; LDA $9000; STA $03; RTS
+const DRIVER: [u8; 14] = [
+    0x85, 0x00, 0xe6, 0x01, 0x60, 0xe6, 0x02, 0xad, 0x00, 0x90, 0x85, 0x03,
+    0x60, 0x00,
+];
+
+// Builds an NSF file around DRIVER, with each 4 KB bank after the first
+// filled with its bank number.
+fn new_nsf(banks: [u8; 8]) -> Vec<u8> {
+    let mut file = b"NESM\x1a\x01\x03\x02".to_vec();
+    file.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x05, 0x80]);
+    let mut name = [0x00; 32];
+    name[..4].copy_from_slice(b"Test");
+    file.extend_from_slice(&name);
+    file.extend_from_slice(&[0x00; 64]);
+    // A PLAY call every frame.
+    file.extend_from_slice(&16_666u16.to_le_bytes());
+    file.extend_from_slice(&banks);
+    file.extend_from_slice(&[0x00; 8]);
+
+    let mut bank = DRIVER.to_vec();
+    bank.resize(0x1000, 0x00);
+    file.extend_from_slice(&bank);
+    for number in 1..4 {
+        file.extend_from_slice(&[number; 0x1000]);
+    }
+    file
+}
+
+#[test]
+fn test_nsf_header() {
+    let nsf = NsfFile::new_from_buffer(&new_nsf([0; 8])).unwrap();
+    assert_eq!(nsf.total_songs, 3);
+    assert_eq!(nsf.starting_song, 1);
+    assert_eq!(nsf.load_address, 0x8000);
+    assert_eq!(nsf.init_address, 0x8000);
+    assert_eq!(nsf.play_address, 0x8005);
+    assert_eq!(nsf.name, "Test");
+    assert_eq!(nsf.ntsc_speed, 16_666);
+    assert!(!nsf.pal);
+    assert!(nsf.banks.is_none());
+    assert_eq!(nsf.data.len(), 0x4000);
+}
+
+#[test]
+fn test_init_and_play() {
+    let nsf = NsfFile::new_from_buffer(&new_nsf([0; 8])).unwrap();
+    let mut player = NsfPlayer::new(nsf);
+
+    // INIT gets called with the starting song.
+    assert_eq!(player.cpu.memory.read(0x0000), 1);
+    assert_eq!(player.cpu.memory.read(0x0001), 1);
+    assert_eq!(player.cpu.memory.read(0x4015), 0x0f);
+
+    player.run_frame();
+    player.run_frame();
+    assert_eq!(player.cpu.memory.read(0x0002), 2);
+    // Without bankswitching, the second 4 KB of data is at $9000.
+    assert_eq!(player.cpu.memory.read(0x0003), 1);
+
+    // Changing songs starts over with fresh RAM.
+    player.next_song();
+    assert_eq!(player.song, 2);
+    assert_eq!(player.cpu.memory.read(0x0000), 2);
+    assert_eq!(player.cpu.memory.read(0x0001), 1);
+    assert_eq!(player.cpu.memory.read(0x0002), 0);
+    player.next_song();
+    assert_eq!(player.song, 0);
+    player.previous_song();
+    assert_eq!(player.song, 2);
+
+    // The song number doesn't overflow with 255 songs.
+    let mut file = new_nsf([0; 8]);
+    file[0x06] = 0xff;
+    file[0x07] = 0xff;
+    let mut player = NsfPlayer::new(NsfFile::new_from_buffer(&file).unwrap());
+    assert_eq!(player.song, 254);
+    player.next_song();
+    assert_eq!(player.song, 0);
+}
+
+#[test]
+fn test_bankswitching() {
+    let nsf =
+        NsfFile::new_from_buffer(&new_nsf([0, 3, 0, 0, 0, 0, 0, 2])).unwrap();
+    let mut player = NsfPlayer::new(nsf);
+    assert_eq!(player.cpu.memory.read(0x9000), 3);
+    assert_eq!(player.cpu.memory.read(0xf000), 2);
+
+    player.call_routine(0x8005);
+    assert_eq!(player.cpu.memory.read(0x0003), 3);
+
+    player.cpu.memory.store(0x5ff9, 0x01);
+    assert_eq!(player.cpu.memory.read(0x9000), 1);
+    player.call_routine(0x8005);
+    assert_eq!(player.cpu.memory.read(0x0003), 1);
+
+    // Starting a song puts the banks back.
+    player.start_song(0);
+    assert_eq!(player.cpu.memory.read(0x9000), 3);
+}
+
+// Builds an NSFe file out of (ID, data) chunks.
+fn new_nsfe(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut file = b"NSFE".to_vec();
+    for (id, data) in chunks {
+        file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend_from_slice(*id);
+        file.extend_from_slice(data);
+    }
+    file
+}
+
+#[test]
+fn test_nsfe() {
+    let info: &[u8] = &[0x00, 0x80, 0x00, 0x80, 0x05, 0x80, 0x00, 0x00, 0x02];
+    let file = new_nsfe(&[
+        (b"INFO", info),
+        (b"DATA", &DRIVER),
+        (b"auth", b"Game\0Composer\0Copyright\0Ripper\0"),
+        (b"tlbl", b"Title\0Ending\0"),
+        (b"NEND", &[]),
+    ]);
+
+    let nsf = NsfFile::new_from_buffer(&file).unwrap();
+    assert_eq!(nsf.total_songs, 2);
+    assert_eq!(nsf.starting_song, 0);
+    assert_eq!(nsf.play_address, 0x8005);
+    assert_eq!(nsf.name, "Game");
+    assert_eq!(nsf.artist, "Composer");
+    assert_eq!(nsf.copyright, "Copyright");
+    assert_eq!(nsf.track_label(1), Some("Ending"));
+    assert_eq!(nsf.data, DRIVER);
+
+    // Starting songs past the end are clamped to the last song.
+    let mut info = info.to_vec();
+    info.push(0x05);
+    let file = new_nsfe(&[(b"INFO", &info), (b"DATA", &DRIVER)]);
+    let nsf = NsfFile::new_from_buffer(&file).unwrap();
+    assert_eq!(nsf.starting_song, 1);
+
+    // As is the NSF header's.
+    let mut file = new_nsf([0; 8]);
+    file[0x07] = 0xff;
+    let nsf = NsfFile::new_from_buffer(&file).unwrap();
+    assert_eq!(nsf.starting_song, 2);
+
+    // DATA is required.
+    let file = new_nsfe(&[(b"INFO", &info), (b"NEND", &[])]);
+    let error = NsfFile::new_from_buffer(&file).err().unwrap();
+    assert_eq!(error.to_string(), "File has no DATA chunk");
+}
@@ -0,0 +1,422 @@
+// Soft-patching of ROM files with IPS, UPS and BPS patches, which is how most
+// translations and romhacks are distributed. Patches apply to the whole file,
+// header included.
+use crate::utils::crc32::crc32;
+use crate::utils::io::read_binary;
+use log::warn;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Extensions of patch files that are applied automatically when they sit
+// next to a ROM with the same name, in order of preference. Patches for the
+// same game in different formats are usually the same patch, so only the
+// first one found is used. BPS and UPS come first because they check that
+// they're being applied to the right ROM.
+const AUTO_PATCH_EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+// The offset that ends the list of IPS records, which spells "EOF".
+const IPS_EOF: usize = 0x45_4f46;
+// The largest IPS record.
+const IPS_MAX_RECORD_SIZE: usize = 0xffff;
+
+// Reasons a patch can't be applied.
+#[derive(Debug)]
+pub enum PatchError {
+    // The patch file couldn't be read.
+    Io(io::Error),
+    // The patch doesn't start with a known signature.
+    UnknownFormat,
+    // The patch ends in the middle of a record.
+    Truncated,
+    // The patch refers to data outside of the file it's patching.
+    OutOfBounds,
+    // A CRC-32 stored in a UPS or BPS patch doesn't match. "kind" is which
+    // of the source, target, or patch it is for.
+    ChecksumMismatch {
+        kind: &'static str,
+        expected: u32,
+        found: u32,
+    },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::Io(error) => write!(f, "{}", error),
+            PatchError::UnknownFormat => {
+                write!(f, "not an IPS, UPS or BPS patch")
+            }
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::OutOfBounds => {
+                write!(f, "patch refers to data past the end of the file")
+            }
+            PatchError::ChecksumMismatch {
+                kind,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} CRC-32 mismatch: expected {:08X}, found {:08X}",
+                kind, expected, found
+            ),
+        }
+    }
+}
+
+impl error::Error for PatchError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PatchError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PatchError {
+    fn from(error: io::Error) -> PatchError {
+        PatchError::Io(error)
+    }
+}
+
+// Finds the patch next to "rom_path" with the same name, like "game.ips" for
+// "game.nes". If there's more than one, the others are ignored with a warning.
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    let mut patches = AUTO_PATCH_EXTENSIONS
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .filter(|path| path.is_file());
+    let patch = patches.next()?;
+    for ignored in patches {
+        warn!(
+            "Ignoring {}: already using {}",
+            ignored.display(),
+            patch.display()
+        );
+    }
+    Some(patch)
+}
+
+// Reads the patch at "path" and applies it to "data".
+pub fn apply_file(path: &Path, data: &[u8]) -> Result<Vec<u8>, PatchError> {
+    apply(&read_binary(path)?, data)
+}
+
+// Applies a patch to "data", working out the format from its signature.
+pub fn apply(patch: &[u8], data: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(patch, data)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(patch, data)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(patch, data)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+// Reads the parts of a patch in order.
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(patch: &'a [u8], position: usize) -> PatchReader<'a> {
+        PatchReader { patch, position }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.patch.len())
+            .ok_or(PatchError::Truncated)?;
+        let bytes = &self.patch[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    // Reads a big-endian number, as used by IPS.
+    fn big_endian(&mut self, size: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(size)?
+            .iter()
+            .fold(0, |value, byte| (value << 8) | usize::from(*byte)))
+    }
+
+    // Reads a little-endian CRC-32, as used by UPS and BPS.
+    fn crc32(&mut self) -> Result<u32, PatchError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Reads a variable-length number, as used by UPS and BPS. Each byte holds
+    // 7 bits, lowest first, and the last byte has its top bit set.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = usize::from(byte & 0x7f)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+}
+
+// Checks a CRC-32 stored in a patch.
+fn check_crc32(
+    kind: &'static str,
+    expected: u32,
+    data: &[u8],
+) -> Result<(), PatchError> {
+    let found = crc32(data);
+    if found != expected {
+        return Err(PatchError::ChecksumMismatch {
+            kind,
+            expected,
+            found,
+        });
+    }
+    Ok(())
+}
+
+// IPS patches are a list of records that each overwrite part of the file:
+// a 3 byte offset and 2 byte size, then the data. A size of 0 means a run of
+// the same byte, with a 2 byte count and then the byte. The list ends with
+// "EOF", optionally followed by a 3 byte size to truncate the file to.
+//
+// See http://fileformats.archiveteam.org/wiki/IPS_(binary_patch_format) for
+// more details.
+fn apply_ips(patch: &[u8], data: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = PatchReader::new(patch, 5);
+    let mut target = data.to_vec();
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let size = reader.big_endian(2)?;
+        let (size, record) = if size == 0 {
+            let count = reader.big_endian(2)?;
+            (count, None)
+        } else {
+            (size, Some(reader.bytes(size)?))
+        };
+
+        if target.len() < offset + size {
+            target.resize(offset + size, 0x00);
+        }
+        match record {
+            Some(record) => {
+                target[offset..offset + size].copy_from_slice(record)
+            }
+            None => target[offset..offset + size].fill(reader.byte()?),
+        }
+    }
+
+    if let Ok(size) = reader.big_endian(3) {
+        target.truncate(size);
+    }
+    Ok(target)
+}
+
+// Builds an IPS patch that turns "source" into "target", with a record for
+// each run of bytes that differ. Both have to be smaller than 16 MB.
+pub fn create_ips(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+    let mut offset = 0;
+    while offset < target.len() {
+        if source.get(offset) == Some(&target[offset]) {
+            offset += 1;
+            continue;
+        }
+
+        // A record can't start at the offset that spells "EOF", so start it
+        // a byte early instead.
+        let start = if offset == IPS_EOF {
+            offset - 1
+        } else {
+            offset
+        };
+        let mut end = offset;
+        while end < target.len()
+            && end - start < IPS_MAX_RECORD_SIZE
+            && source.get(end) != Some(&target[end])
+        {
+            end += 1;
+        }
+
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..end]);
+        offset = end;
+    }
+    patch.extend_from_slice(b"EOF");
+
+    if target.len() < source.len() {
+        patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    patch
+}
+
+// UPS patches store the sizes of the source and target files, then a list of
+// hunks that each skip forward some number of bytes and XOR the following
+// bytes with the source, up to and including a 0 byte. They end with the
+// CRC-32s of the source, target, and patch.
+//
+// See http://www.romhacking.net/documents/392/ for more details.
+fn apply_ups(patch: &[u8], data: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (body, footer) = split_footer(patch)?;
+
+    let mut reader = PatchReader::new(body, 4);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_crc32("source", footer.source, data)?;
+    if data.len() != source_size {
+        return Err(PatchError::OutOfBounds);
+    }
+
+    let source_byte = |offset: usize| data.get(offset).copied().unwrap_or(0);
+    let mut target = vec![0x00; target_size];
+    let mut offset: usize = 0;
+    while reader.position < body.len() {
+        let skip = reader.number()?;
+        let end = offset.checked_add(skip).ok_or(PatchError::OutOfBounds)?;
+        while offset < end {
+            if offset < target_size {
+                target[offset] = source_byte(offset);
+            }
+            offset += 1;
+        }
+        loop {
+            let xor = reader.byte()?;
+            if offset < target_size {
+                target[offset] = source_byte(offset) ^ xor;
+            }
+            offset += 1;
+            if xor == 0 {
+                break;
+            }
+        }
+    }
+    while offset < target_size {
+        target[offset] = source_byte(offset);
+        offset += 1;
+    }
+
+    check_crc32("target", footer.target, &target)?;
+    Ok(target)
+}
+
+// BPS patches build the target file from a list of actions, which copy from
+// the source at the same offset, from the patch itself, or from anywhere in
+// the source or the target built so far. Like UPS, they end with the CRC-32s
+// of the source, target, and patch.
+//
+// See https://www.romhacking.net/documents/746/ for more details.
+fn apply_bps(patch: &[u8], data: &[u8]) -> Result<Vec<u8>, PatchError> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+    const SOURCE_COPY: usize = 2;
+
+    let (body, footer) = split_footer(patch)?;
+
+    let mut reader = PatchReader::new(body, 4);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    check_crc32("source", footer.source, data)?;
+    if data.len() != source_size {
+        return Err(PatchError::OutOfBounds);
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    while reader.position < body.len() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        match action & 0x03 {
+            SOURCE_READ => {
+                let start = target.len();
+                let source = start
+                    .checked_add(length)
+                    .and_then(|end| data.get(start..end))
+                    .ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(source);
+            }
+            TARGET_READ => target.extend_from_slice(reader.bytes(length)?),
+            command => {
+                let relative = reader.number()?;
+                let offset = if command == SOURCE_COPY {
+                    &mut source_offset
+                } else {
+                    &mut target_offset
+                };
+                *offset = if relative & 1 == 1 {
+                    offset.checked_sub(relative >> 1)
+                } else {
+                    offset.checked_add(relative >> 1)
+                }
+                .ok_or(PatchError::OutOfBounds)?;
+
+                for _ in 0..length {
+                    let byte = if command == SOURCE_COPY {
+                        data.get(*offset).copied()
+                    } else {
+                        // Target copies can overlap what they're writing,
+                        // to repeat a pattern.
+                        target.get(*offset).copied()
+                    };
+                    target.push(byte.ok_or(PatchError::OutOfBounds)?);
+                    *offset += 1;
+                }
+            }
+        }
+        if target.len() > target_size {
+            return Err(PatchError::OutOfBounds);
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+
+    check_crc32("target", footer.target, &target)?;
+    Ok(target)
+}
+
+// The CRC-32s at the end of UPS and BPS patches.
+struct Footer {
+    source: u32,
+    target: u32,
+}
+
+// Splits the 12 byte footer off a UPS or BPS patch, and checks the CRC-32 of
+// the patch itself.
+fn split_footer(patch: &[u8]) -> Result<(&[u8], Footer), PatchError> {
+    if patch.len() < 16 {
+        return Err(PatchError::Truncated);
+    }
+    let (body, footer) = patch.split_at(patch.len() - 12);
+    let mut reader = PatchReader::new(footer, 0);
+    let footer = Footer {
+        source: reader.crc32()?,
+        target: reader.crc32()?,
+    };
+    check_crc32("patch", reader.crc32()?, &patch[..patch.len() - 4])?;
+    Ok((body, footer))
+}
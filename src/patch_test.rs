@@ -0,0 +1,138 @@
+use crate::patch::{PatchError, apply, create_ips, find_patch};
+use crate::utils::crc32::crc32;
+use std::fs;
+
+// Adds the source, target and patch CRC-32s to the end of a UPS or BPS patch.
+fn add_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+#[test]
+fn test_ips() {
+    let source = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05];
+    let mut patch = b"PATCH".to_vec();
+    // Overwrite 2 bytes at offset 1.
+    patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);
+    // Run of 3 0xcc bytes at offset 5, growing the file.
+    patch.extend_from_slice(&[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xcc]);
+    patch.extend_from_slice(b"EOF");
+    assert_eq!(
+        apply(&patch, &source).unwrap(),
+        [0x00, 0xaa, 0xbb, 0x03, 0x04, 0xcc, 0xcc, 0xcc]
+    );
+
+    // Truncating the result.
+    patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+    assert_eq!(apply(&patch, &source).unwrap(), [0x00, 0xaa, 0xbb, 0x03]);
+
+    // A record cut off before its data.
+    let patch = b"PATCH\x00\x00\x01\x00\x04\xaa".to_vec();
+    assert!(matches!(apply(&patch, &source), Err(PatchError::Truncated)));
+}
+
+#[test]
+fn test_create_ips() {
+    let source = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05];
+    let target = [0x00, 0xaa, 0xbb, 0x03, 0x04, 0xcc, 0xdd];
+    let patch = create_ips(&source, &target);
+    assert_eq!(apply(&patch, &source).unwrap(), target);
+
+    // Shrinking the file.
+    let patch = create_ips(&source, &source[..4]);
+    assert_eq!(apply(&patch, &source).unwrap(), source[..4]);
+}
+
+#[test]
+fn test_ups() {
+    let source = [0x10, 0x11, 0x12, 0x13];
+    let target = [0x10, 0x21, 0x12, 0x13, 0x00, 0x55];
+
+    // Sizes 4 and 6, then skip 1 byte and XOR 0x30 into the next, then skip 2
+    // bytes (past the 0 terminator) and XOR 0x55 into the last byte.
+    let patch = b"UPS1\x84\x86\x81\x30\x00\x82\x55\x00".to_vec();
+    let patch = add_footer(patch, &source, &target);
+    assert_eq!(apply(&patch, &source).unwrap(), target);
+
+    // Patching the wrong file.
+    let error = apply(&patch, &[0x10, 0x11, 0x12, 0x14]).err().unwrap();
+    assert!(matches!(
+        error,
+        PatchError::ChecksumMismatch { kind: "source", .. }
+    ));
+
+    // A corrupted patch.
+    let mut corrupted = patch.clone();
+    corrupted[7] = 0x31;
+    let error = apply(&corrupted, &source).err().unwrap();
+    assert!(matches!(
+        error,
+        PatchError::ChecksumMismatch { kind: "patch", .. }
+    ));
+}
+
+#[test]
+fn test_bps() {
+    let source = [0x01, 0x02, 0x03, 0x04];
+    let target = [0x01, 0x02, 0xee, 0x03, 0x04, 0xee, 0x03, 0x04, 0xee];
+
+    let mut patch = b"BPS1\x84\x89\x80".to_vec();
+    // Source read of 2 bytes.
+    patch.push(0x80 | (1 << 2));
+    // Target read of 1 byte.
+    patch.extend_from_slice(&[0x80 | 0x01, 0xee]);
+    // Source copy of 2 bytes from offset 2.
+    patch.extend_from_slice(&[0x80 | (1 << 2) | 0x02, 0x80 | (2 << 1)]);
+    // Target copy of 4 bytes from offset 2, which overlaps what it writes.
+    patch.extend_from_slice(&[0x80 | (3 << 2) | 0x03, 0x80 | (2 << 1)]);
+    let patch = add_footer(patch, &source, &target);
+    assert_eq!(apply(&patch, &source).unwrap(), target);
+
+    // The stored target CRC-32 doesn't match what the patch produces.
+    let mut patch = patch[..patch.len() - 12].to_vec();
+    patch.extend_from_slice(&crc32(&source).to_le_bytes());
+    patch.extend_from_slice(&0x1234_5678_u32.to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    let error = apply(&patch, &source).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "target CRC-32 mismatch: expected 12345678, found {:08X}",
+            crc32(&target)
+        )
+    );
+}
+
+#[test]
+fn test_unknown_format() {
+    let error = apply(b"NOT A PATCH", &[0x00]).err().unwrap();
+    assert!(matches!(error, PatchError::UnknownFormat));
+}
+
+#[test]
+fn test_find_patch() {
+    let directory = std::env::temp_dir().join("neskimo_find_patch");
+    fs::create_dir_all(&directory).unwrap();
+    let rom = directory.join("game.nes");
+    for name in ["game.nes", "game.ips", "other.bps"] {
+        fs::write(directory.join(name), b"").unwrap();
+    }
+    assert_eq!(find_patch(&rom), Some(directory.join("game.ips")));
+
+    // Only the first in order of preference is used.
+    for name in ["game.ups", "game.bps"] {
+        fs::write(directory.join(name), b"").unwrap();
+        assert_eq!(find_patch(&rom), Some(directory.join(name)));
+    }
+
+    assert_eq!(
+        find_patch(&directory.join("other.nes")),
+        Some(directory.join("other.bps"))
+    );
+    assert_eq!(find_patch(&directory.join("missing.nes")), None);
+    fs::remove_dir_all(&directory).unwrap();
+}
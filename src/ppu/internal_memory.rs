@@ -1,48 +1,74 @@
+use crate::cartridge::Cartridge;
 use crate::nes::memory::Memory;
+use crate::ppu::palette::PaletteRam;
 use crate::ppu::vram::Vram;
-use crate::rom::MirrorType;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// The PPU's address bus is 14 bits wide, so everything above $3FFF mirrors
+// $0000-$3FFF.
+const ADDRESS_MASK: u16 = 0x3fff;
 
 pub struct InternalMemory {
+    // The cartridge, which maps its CHR ROM or RAM into the pattern tables.
+    cartridge: Rc<RefCell<Cartridge>>,
     vram: Vram,
+    palette_ram: PaletteRam,
 }
 
 impl InternalMemory {
-    pub fn new(nametable_mirror_type: MirrorType) -> InternalMemory {
+    pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> InternalMemory {
         InternalMemory {
-            vram: Vram::new(nametable_mirror_type),
+            cartridge: cartridge.clone(),
+            vram: Vram::new(cartridge),
+            palette_ram: PaletteRam::new(),
         }
     }
 }
 
 impl Memory for InternalMemory {
-    // Fetches a byte from the specified address in memory.
-    fn fetch(&self, address: u16) -> u8 {
+    // Reads a byte from the specified address in memory. Reads of the
+    // pattern tables are visible to the cartridge's mapper.
+    fn read(&mut self, address: u16) -> u8 {
+        let address = address & ADDRESS_MASK;
+        if address < 0x2000 {
+            self.cartridge.borrow_mut().notify_ppu_address(address);
+        }
+        self.peek(address)
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        let address = address & ADDRESS_MASK;
         match address {
             // Pattern tables, normally mapped by the cartridge to a CHR-ROM or
             // CHR-RAM.
-            0x0000..=0x1fff => 0x00,
+            0x0000..=0x1fff => self.cartridge.borrow().fetch_chr(address),
 
             // 2kB VRAM, with special mirroring configuration. Can be remapped
             // to cartridge RAM, allowing up to 4 simultaneous nametables.
-            0x2000..=0x2fff => self.vram.fetch(address),
+            0x2000..=0x2fff => self.vram.peek(address),
 
             // Usually mirrored to $2000-$2eff.
-            0x3000..=0x3eff => self.vram.fetch(address - 0x1000),
-
-            // Not configurable, always mapped to the interal palette control.
-            0x3f00..=0x3fff => 0x03,
+            0x3000..=0x3eff => self.vram.peek(address - 0x1000),
 
-            _ => 0xff,
+            // $3F00-$3FFF: Not configurable, always mapped to the internal
+            // palette RAM.
+            _ => self.palette_ram.peek(address),
         }
     }
 
     // Stores value into memory at the specified address.
     // Returns the previous value.
     fn store(&mut self, address: u16, value: u8) -> u8 {
+        let address = address & ADDRESS_MASK;
         match address {
             // Pattern tables, normally mapped by the cartridge to a CHR-ROM or
             // CHR-RAM.
-            0x0000..=0x1fff => 0x00,
+            0x0000..=0x1fff => {
+                let mut cartridge = self.cartridge.borrow_mut();
+                cartridge.notify_ppu_address(address);
+                cartridge.store_chr(address, value)
+            }
 
             // 2kB VRAM, with special mirroring configuration. Can be remapped
             // to cartridge RAM, allowing up to 4 simultaneous nametables.
@@ -51,10 +77,9 @@ impl Memory for InternalMemory {
             // Usually mirrored to $2000-$2eff.
             0x3000..=0x3eff => self.vram.store(address - 0x1000, value),
 
-            // Not configurable, always mapped to the interal palette control.
-            0x3f00..=0x3fff => 0x03,
-
-            _ => 0xff,
+            // $3F00-$3FFF: Not configurable, always mapped to the internal
+            // palette RAM.
+            _ => self.palette_ram.store(address, value),
         }
     }
 }
@@ -1,10 +1,28 @@
 pub mod internal_memory;
+pub mod palette;
+pub mod sprite;
 pub mod vram;
 
+// Tests for the PPU.
+#[cfg(test)]
+mod ppu_test;
+
+// Tests for nametable mirroring.
+#[cfg(test)]
+mod vram_test;
+
+use crate::cartridge::Cartridge;
 use crate::nes::memory::Memory;
 use crate::ppu::internal_memory::InternalMemory;
-use crate::rom::MirrorType;
+use crate::ppu::sprite::{
+    OAM_SIZE, SPRITE_COUNT, SPRITES_PER_SCANLINE, Sprite,
+};
 use arrayvec::ArrayVec;
+use log::warn;
+use std::cell::RefCell;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
 
 // Emulated screen width in pixels.
 pub const SCREEN_WIDTH: usize = 256;
@@ -27,8 +45,77 @@ pub const PRE_RENDER_SCANLINE: u16 = 261;
 // Total number of scanlines, numbered 0 - 261.
 pub const TOTAL_SCANLINE_COUNT: u16 = 262;
 
+// PPUCTRL: Base nametable (0 = $2000, 1 = $2400, 2 = $2800, 3 = $2C00).
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+// PPUCTRL: VRAM address increment per PPUDATA access (0 = 1, 1 = 32).
+const CTRL_INCREMENT: u8 = 0b0000_0100;
+// PPUCTRL: Sprite pattern table address for 8x8 sprites (0 = $0000, 1 = $1000).
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+// PPUCTRL: Background pattern table address (0 = $0000, 1 = $1000).
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+// PPUCTRL: Sprite size (0 = 8x8, 1 = 8x16).
+const CTRL_SPRITE_SIZE: u8 = 0b0010_0000;
+// PPUCTRL: Generate an NMI at the start of VBlank.
+const CTRL_NMI: u8 = 0b1000_0000;
+
+// PPUMASK: Show background in the leftmost 8 pixels of the screen.
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+// PPUMASK: Show sprites in the leftmost 8 pixels of the screen.
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+// PPUMASK: Show background.
+const MASK_BACKGROUND: u8 = 0b0000_1000;
+// PPUMASK: Show sprites.
+const MASK_SPRITES: u8 = 0b0001_0000;
+// PPUMASK: Greyscale, which keeps only the brightness of each color.
+const MASK_GREYSCALE: u8 = 0b0000_0001;
+// PPUMASK: Emphasize red.
+const MASK_EMPHASIZE_RED: u8 = 0b0010_0000;
+// PPUMASK: Emphasize green.
+const MASK_EMPHASIZE_GREEN: u8 = 0b0100_0000;
+// PPUMASK: Emphasize blue.
+const MASK_EMPHASIZE_BLUE: u8 = 0b1000_0000;
+
+// PPUSTATUS: More than 8 sprites appeared on a scanline.
+const STATUS_SPRITE_OVERFLOW: u8 = 0b0010_0000;
+// PPUSTATUS: An opaque pixel of sprite 0 overlapped an opaque background
+// pixel.
+const STATUS_SPRITE_ZERO_HIT: u8 = 0b0100_0000;
+// PPUSTATUS: VBlank has started.
+const STATUS_V_BLANK: u8 = 0b1000_0000;
+
+// Loopy v/t: Coarse X scroll (tile column).
+const SCROLL_COARSE_X: u16 = 0b000_0000_0001_1111;
+// Loopy v/t: Coarse Y scroll (tile row).
+const SCROLL_COARSE_Y: u16 = 0b000_0011_1110_0000;
+// Loopy v/t: Horizontal nametable select.
+const SCROLL_NAMETABLE_X: u16 = 0b000_0100_0000_0000;
+// Loopy v/t: Vertical nametable select.
+const SCROLL_NAMETABLE_Y: u16 = 0b000_1000_0000_0000;
+// Loopy v/t: Fine Y scroll (row within a tile).
+const SCROLL_FINE_Y: u16 = 0b111_0000_0000_0000;
+// Loopy v/t: Bits copied from t to v at the end of each scanline.
+const SCROLL_HORIZONTAL: u16 = SCROLL_COARSE_X | SCROLL_NAMETABLE_X;
+// Loopy v/t: Bits copied from t to v before the start of each frame.
+const SCROLL_VERTICAL: u16 =
+    SCROLL_COARSE_Y | SCROLL_NAMETABLE_Y | SCROLL_FINE_Y;
+
+// Start of the nametables in PPU memory.
+const NAMETABLE_BASE: u16 = 0x2000;
+// Offset of the attribute table within each nametable.
+const ATTRIBUTE_TABLE_OFFSET: u16 = 0x03c0;
+// Start of palette RAM in PPU memory.
+const PALETTE_BASE: u16 = 0x3f00;
+
+// Color emphasis darkens the channels that aren't emphasized to roughly this
+// fraction (out of 256) of their brightness.
+const EMPHASIS_ATTENUATION: u16 = 209;
+
+// The I/O latch holds its value for roughly 600ms after it was last
+// refreshed, before decaying to 0.
+const IO_LATCH_DECAY_FRAMES: u32 = 36;
+
+// The 64 colors the NES can display, as RGB triples.
 #[rustfmt::skip]
-#[allow(dead_code)]
 static PALETTE: [u8; 192] = [
     124,124,124,    0,0,252,        0,0,188,        68,40,188,
     148,0,132,      168,0,32,       168,16,0,       136,20,0,
@@ -59,19 +146,51 @@ pub struct Ppu {
     ppumask: u8,
     ppustatus: u8,
     oamaddr: u8,
-    oamdata: u8,
-    ppuscroll: u8,
-    ppuaddr: u8,
-    ppudata: u8,
     oamdma: u8,
 
+    // Internal scroll registers, as named on the nesdev wiki ("loopy"
+    // registers). See http://wiki.nesdev.com/w/index.php/PPU_scrolling.
+    //
+    // v: Current VRAM address (15 bits).
+    vram_address: u16,
+    // t: Temporary VRAM address (15 bits), the address of the top-left tile
+    // on screen.
+    temp_vram_address: u16,
+    // x: Fine X scroll (3 bits).
+    fine_x: u8,
+    // w: First or second write toggle, shared by PPUSCROLL and PPUADDR.
+    write_toggle: bool,
+
+    // PPUDATA reads below the palettes return the contents of this buffer,
+    // which is then filled with the byte at the current VRAM address.
+    read_buffer: u8,
+
+    // The PPU's internal data bus. Reads of write-only registers return
+    // whatever was last written to any register.
+    io_latch: u8,
+    // Frames left until the I/O latch decays.
+    io_latch_decay: u32,
+
+    // Set when the CPU writes to OAMDMA, so that the 256-byte copy into OAM
+    // can be performed on the CPU's side of the bus.
+    oam_dma_requested: bool,
+
+    // Set when the PPU has raised an NMI that the CPU hasn't taken yet.
+    nmi_pending: bool,
+
+    // Set when PPUSTATUS is read one dot before VBlank starts, which stops
+    // the VBlank flag (and so the NMI) from being set for this frame.
+    suppress_v_blank: bool,
+
+    // Object Attribute Memory, which holds the data for all 64 sprites.
+    oam: [u8; OAM_SIZE],
+
     // Internal memory storage/access.
-    #[allow(dead_code)]
     internal_memory: InternalMemory,
 }
 
 impl Ppu {
-    pub fn new(nametable_mirror_type: MirrorType) -> Ppu {
+    pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> Ppu {
         Ppu {
             cycle: 0,
             screen: Box::new([0x00; SCREEN_SIZE]),
@@ -81,22 +200,50 @@ impl Ppu {
             ppumask: 0x00,
             ppustatus: 0x00,
             oamaddr: 0x00,
-            oamdata: 0x00,
-            ppuscroll: 0x00,
-            ppuaddr: 0x00,
-            ppudata: 0x00,
             oamdma: 0x00,
-            internal_memory: InternalMemory::new(nametable_mirror_type),
+            vram_address: 0x0000,
+            temp_vram_address: 0x0000,
+            fine_x: 0x00,
+            write_toggle: false,
+            read_buffer: 0x00,
+            io_latch: 0x00,
+            io_latch_decay: 0,
+            oam_dma_requested: false,
+            nmi_pending: false,
+            suppress_v_blank: false,
+            oam: [0x00; OAM_SIZE],
+            internal_memory: InternalMemory::new(cartridge),
         }
     }
 
-    // Addresses that PPU can provide access to for CPU's mapped registers.
+    // Ranges of addresses that PPU can provide access to for CPU's mapped
+    // registers.
     // TODO: separate out registers that are read-only, write-only, etc.
-    pub fn mapped_addresses() -> ArrayVec<u16, 9> {
-        ArrayVec::from([
-            0x2000, 0x2001, 0x2002, 0x2003, 0x2004, 0x2005, 0x2006, 0x2007,
-            0x4014,
-        ])
+    pub fn mapped_addresses() -> [RangeInclusive<u16>; 2] {
+        [0x2000..=0x2007, 0x4014..=0x4014]
+    }
+
+    // Returns true if the CPU has requested an OAM DMA since the last call.
+    // The page to copy from is the value written to OAMDMA.
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        if self.oam_dma_requested {
+            self.oam_dma_requested = false;
+            Some(self.oamdma)
+        } else {
+            None
+        }
+    }
+
+    // Returns true if the PPU has raised an NMI since the last call.
+    pub fn take_nmi(&mut self) -> bool {
+        mem::replace(&mut self.nmi_pending, false)
+    }
+
+    // Writes a byte into OAM at OAMADDR, incrementing OAMADDR. This is what
+    // both OAMDATA writes and OAM DMA do.
+    pub fn write_oam(&mut self, value: u8) {
+        self.oam[self.oamaddr as usize] = value;
+        self.oamaddr = self.oamaddr.wrapping_add(1);
     }
 
     pub fn write_to_screen(&mut self, x: usize, y: usize, color: [u8; 3]) {
@@ -107,7 +254,7 @@ impl Ppu {
     }
 
     pub fn read_from_screen(&self, x: usize, y: usize) -> [u8; 3] {
-        let base_index = x + (y * SCREEN_WIDTH);
+        let base_index = (x + (y * SCREEN_WIDTH)) * 3;
         [
             self.screen[base_index],
             self.screen[base_index + 1],
@@ -142,15 +289,33 @@ impl Ppu {
 
             match self.current_scanline {
                 FIRST_VISIBLE_SCANLINE..=LAST_VISIBLE_SCANLINE => {
+                    // The vertical scroll bits are reloaded at the end of the
+                    // pre-render scanline, just before the first visible one.
+                    if self.current_scanline == FIRST_VISIBLE_SCANLINE
+                        && self.rendering_enabled()
+                    {
+                        self.copy_scroll_bits(
+                            SCROLL_HORIZONTAL | SCROLL_VERTICAL,
+                        );
+                    }
                     self.render_scanline()
                 }
-                V_BLANK_SCANLINE => v_blank = true,
+                V_BLANK_SCANLINE => v_blank = self.start_v_blank(),
+                PRE_RENDER_SCANLINE => {
+                    self.ppustatus &= !(STATUS_V_BLANK
+                        | STATUS_SPRITE_OVERFLOW
+                        | STATUS_SPRITE_ZERO_HIT);
+                    if self.rendering_enabled() {
+                        self.fetch_pre_render_patterns();
+                    }
+                }
                 _ => (),
             }
         }
 
         if new_frame {
             self.odd_frame = !self.odd_frame;
+            self.decay_io_latch();
         }
 
         (new_frame, v_blank)
@@ -167,63 +332,532 @@ impl Ppu {
         // self.screen[115 * SCREEN_WIDTH + cycle_index + 2] = 255;
     }
 
+    // Sets the VBlank flag, and raises an NMI if PPUCTRL asks for one.
+    // Returns false if a PPUSTATUS read suppressed VBlank for this frame.
+    fn start_v_blank(&mut self) -> bool {
+        if mem::replace(&mut self.suppress_v_blank, false) {
+            return false;
+        }
+        self.ppustatus |= STATUS_V_BLANK;
+        if self.ppuctrl & CTRL_NMI != 0 {
+            self.nmi_pending = true;
+        }
+        true
+    }
+
     // Renders a scanline to the internal "screen".
     fn render_scanline(&mut self) {
         let y = self.current_scanline as usize;
-        let (mut r, mut g, mut b) = match y % 3 {
-            0 => (255, 0, 0),
-            1 => (0, 255, 0),
-            2 => (0, 0, 255),
-            _ => (0, 0, 0),
+
+        // Palette index of each background pixel on the scanline. Index 0 is
+        // the backdrop color.
+        let mut background = [0x00; SCREEN_WIDTH];
+        if self.ppumask & MASK_BACKGROUND != 0 {
+            self.render_background(&mut background);
+        }
+
+        // Sprites are composited over the background.
+        let mut line = background;
+        if self.rendering_enabled() {
+            let sprites = self.evaluate_sprites(self.current_scanline);
+            let mut fetched = 0;
+            if self.ppumask & MASK_SPRITES != 0 {
+                self.render_sprites(y, &sprites, &background, &mut line);
+                fetched = sprites.len();
+            }
+            self.fetch_unused_sprite_patterns(SPRITES_PER_SCANLINE - fetched);
+        }
+
+        for (x, palette_index) in line.iter().enumerate() {
+            let color = self.palette_color(*palette_index);
+            self.write_to_screen(x, y, color);
+        }
+
+        // Move v down to the next row, and reset its horizontal position.
+        if self.rendering_enabled() {
+            self.increment_scroll_y();
+            self.copy_scroll_bits(SCROLL_HORIZONTAL);
+        }
+    }
+
+    // Whether either background or sprite rendering is turned on. The scroll
+    // registers are only updated while rendering.
+    fn rendering_enabled(&self) -> bool {
+        self.ppumask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    // Copies the bits selected by "mask" from t into v.
+    fn copy_scroll_bits(&mut self, mask: u16) {
+        self.vram_address =
+            (self.vram_address & !mask) | (self.temp_vram_address & mask);
+    }
+
+    // Increments the fine Y scroll in v, overflowing into coarse Y and then
+    // into the vertical nametable select. Row 29 is the last row of tiles, so
+    // coarse Y wraps from 29 to 0 and switches nametables. Coarse Y values of
+    // 30 and 31 (attribute data) wrap to 0 without switching.
+    fn increment_scroll_y(&mut self) {
+        let mut v = self.vram_address;
+        if v & SCROLL_FINE_Y != SCROLL_FINE_Y {
+            v += 0x1000;
+        } else {
+            v &= !SCROLL_FINE_Y;
+            let mut coarse_y = (v & SCROLL_COARSE_Y) >> 5;
+            match coarse_y {
+                29 => {
+                    coarse_y = 0;
+                    v ^= SCROLL_NAMETABLE_Y;
+                }
+                31 => coarse_y = 0,
+                _ => coarse_y += 1,
+            }
+            v = (v & !SCROLL_COARSE_Y) | (coarse_y << 5);
+        }
+        self.vram_address = v;
+    }
+
+    // Increments v after a PPUDATA access, by 1 or 32 depending on PPUCTRL.
+    fn increment_vram_address(&mut self) {
+        let increment = if self.ppuctrl & CTRL_INCREMENT != 0 {
+            32
+        } else {
+            1
         };
-        if self.odd_frame {
-            r = (f32::from(r) * 0.75) as u8;
-            g = (f32::from(g) * 0.75) as u8;
-            b = (f32::from(b) * 0.75) as u8;
+        self.vram_address = self.vram_address.wrapping_add(increment) & 0x7fff;
+    }
+
+    // Reads PPUSTATUS. This clears the VBlank flag and resets the write
+    // toggle. The lower 5 bits are not driven, so they come from the latch.
+    //
+    // Reading right around the start of VBlank races with the flag being
+    // set: one dot before, the flag reads as clear and is never set, and on
+    // the first few dots after, the flag reads as set but no NMI happens.
+    fn read_status(&mut self) -> u8 {
+        let value = self.peek_status();
+        if self.current_scanline == V_BLANK_SCANLINE - 1
+            && self.cycle == CYCLES_PER_SCANLINE - 1
+        {
+            self.suppress_v_blank = true;
+        } else if self.current_scanline == V_BLANK_SCANLINE && self.cycle < 3 {
+            self.nmi_pending = false;
         }
-        for x in 0..SCREEN_WIDTH {
-            self.write_to_screen(x, y, [r, g, b]);
+
+        self.ppustatus &= !STATUS_V_BLANK;
+        self.write_toggle = false;
+        value
+    }
+
+    // What reading PPUSTATUS would return, without reading it.
+    fn peek_status(&self) -> u8 {
+        (self.ppustatus & 0xe0) | (self.io_latch & 0x1f)
+    }
+
+    // Puts "value" on the I/O latch, which keeps it from decaying for a
+    // while. Returns the previous value.
+    fn set_io_latch(&mut self, value: u8) -> u8 {
+        self.io_latch_decay = IO_LATCH_DECAY_FRAMES;
+        mem::replace(&mut self.io_latch, value)
+    }
+
+    // Called once a frame. Clears the I/O latch once it hasn't been
+    // refreshed for long enough.
+    fn decay_io_latch(&mut self) {
+        self.io_latch_decay = self.io_latch_decay.saturating_sub(1);
+        if self.io_latch_decay == 0 {
+            self.io_latch = 0x00;
+        }
+    }
+
+    // Reads PPUDATA. Reads below the palettes are delayed by one read
+    // through the read buffer. Palette reads are returned immediately, but
+    // still fill the buffer with the nametable byte "underneath" them.
+    // Palette entries are only 6 bits, so the top 2 bits come from the I/O
+    // latch.
+    fn read_data(&mut self) -> u8 {
+        let value = self.peek_data();
+        let address = self.vram_address & 0x3fff;
+        self.read_buffer = if address >= PALETTE_BASE {
+            self.internal_memory.read(address - 0x1000)
+        } else {
+            self.internal_memory.read(address)
+        };
+        self.increment_vram_address();
+        value
+    }
+
+    // What reading PPUDATA would return, without reading it.
+    fn peek_data(&self) -> u8 {
+        let address = self.vram_address & 0x3fff;
+        if address >= PALETTE_BASE {
+            (self.internal_memory.peek(address) & 0x3f) | (self.io_latch & 0xc0)
+        } else {
+            self.read_buffer
         }
     }
+
+    // Writes PPUCTRL. The nametable select bits also go into t. Turning on
+    // NMIs while the VBlank flag is set raises one straight away.
+    fn write_ctrl(&mut self, value: u8) {
+        if self.ppuctrl & CTRL_NMI == 0
+            && value & CTRL_NMI != 0
+            && self.ppustatus & STATUS_V_BLANK != 0
+        {
+            self.nmi_pending = true;
+        }
+        self.ppuctrl = value;
+        self.temp_vram_address = (self.temp_vram_address
+            & !(SCROLL_NAMETABLE_X | SCROLL_NAMETABLE_Y))
+            | (u16::from(value & CTRL_NAMETABLE) << 10);
+    }
+
+    // Writes PPUSCROLL. The first write sets the X scroll, the second write
+    // sets the Y scroll.
+    fn write_scroll(&mut self, value: u8) {
+        let value = u16::from(value);
+        let t = self.temp_vram_address;
+        if !self.write_toggle {
+            self.temp_vram_address = (t & !SCROLL_COARSE_X) | (value >> 3);
+            self.fine_x = (value & 0x07) as u8;
+            self.write_toggle = true;
+        } else {
+            self.temp_vram_address = (t & !(SCROLL_COARSE_Y | SCROLL_FINE_Y))
+                | ((value & 0xf8) << 2)
+                | ((value & 0x07) << 12);
+            self.write_toggle = false;
+        }
+    }
+
+    // Writes PPUADDR. The first write sets the high 6 bits of t, the second
+    // sets the low 8 bits and copies t into v.
+    fn write_address(&mut self, value: u8) {
+        let value = u16::from(value);
+        let t = self.temp_vram_address;
+        if !self.write_toggle {
+            self.temp_vram_address = (t & 0x00ff) | ((value & 0x3f) << 8);
+            self.write_toggle = true;
+        } else {
+            self.temp_vram_address = (t & 0xff00) | value;
+            self.vram_address = self.temp_vram_address;
+            self.write_toggle = false;
+        }
+    }
+
+    // Writes PPUDATA into VRAM at v.
+    fn write_data(&mut self, value: u8) {
+        let address = self.vram_address & 0x3fff;
+        self.internal_memory.store(address, value);
+        self.increment_vram_address();
+    }
+
+    // Height of sprites in pixels, based on PPUCTRL.
+    fn sprite_height(&self) -> u16 {
+        if self.ppuctrl & CTRL_SPRITE_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // Finds the first 8 sprites in OAM that fall on "scanline", and sets the
+    // sprite overflow flag if there are more.
+    //
+    // The overflow check reproduces the hardware bug, where after 8 sprites
+    // have been found the PPU increments the byte offset along with the
+    // sprite index, so it ends up comparing tile, attribute and X bytes
+    // against the scanline as if they were Y positions.
+    fn evaluate_sprites(
+        &mut self,
+        scanline: u16,
+    ) -> ArrayVec<Sprite, SPRITES_PER_SCANLINE> {
+        let height = self.sprite_height();
+        let mut sprites = ArrayVec::new();
+
+        let mut n = 0;
+        while n < SPRITE_COUNT && !sprites.is_full() {
+            let sprite = Sprite::from_oam(&self.oam, n);
+            if Sprite::row_on_scanline(sprite.y, scanline, height).is_some() {
+                sprites.push(sprite);
+            }
+            n += 1;
+        }
+
+        let mut m = 0;
+        while n < SPRITE_COUNT {
+            let y = self.oam[n * 4 + m];
+            if Sprite::row_on_scanline(y, scanline, height).is_some() {
+                self.ppustatus |= STATUS_SPRITE_OVERFLOW;
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+
+        sprites
+    }
+
+    // The pre-render scanline fetches patterns like a visible one, but draws
+    // nothing: background tiles from wherever v points, then 8 sprites'
+    // worth. With the background and sprites in different pattern tables,
+    // this is what gives MMC3 its scanline clock for the pre-render line.
+    fn fetch_pre_render_patterns(&mut self) {
+        let mut line = [0x00; SCREEN_WIDTH];
+        self.render_background(&mut line);
+        self.fetch_unused_sprite_patterns(SPRITES_PER_SCANLINE);
+    }
+
+    // The PPU fetches patterns for 8 sprites on every rendered scanline, even
+    // if fewer were found. The unused slots fetch tile $FF. Nothing is drawn,
+    // but mappers watching the PPU's address bus count on these fetches.
+    fn fetch_unused_sprite_patterns(&mut self, count: usize) {
+        let height = self.sprite_height();
+        let pattern_table = if self.ppuctrl & CTRL_SPRITE_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        let sprite = Sprite {
+            index: 0,
+            y: 0xff,
+            tile: 0xff,
+            attributes: 0x00,
+            x: 0xff,
+        };
+        let address = sprite.pattern_address(0, height, pattern_table);
+        for _ in 0..count {
+            self.internal_memory.read(address);
+            self.internal_memory.read(address + 8);
+        }
+    }
+
+    // Draws "sprites" over the background pixels in "line". Also sets the
+    // sprite 0 hit flag if an opaque pixel of sprite 0 overlaps an opaque
+    // background pixel.
+    fn render_sprites(
+        &mut self,
+        y: usize,
+        sprites: &[Sprite],
+        background: &[u8; SCREEN_WIDTH],
+        line: &mut [u8; SCREEN_WIDTH],
+    ) {
+        let height = self.sprite_height();
+        let pattern_table = if self.ppuctrl & CTRL_SPRITE_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        let background_enabled = self.ppumask & MASK_BACKGROUND != 0;
+        let left_clip = self.ppumask
+            & (MASK_BACKGROUND_LEFT | MASK_SPRITES_LEFT)
+            != (MASK_BACKGROUND_LEFT | MASK_SPRITES_LEFT);
+
+        // Palette index of the frontmost sprite pixel drawn so far at each X,
+        // where 0 means no sprite pixel has been drawn yet.
+        let mut drawn = [0x00; SCREEN_WIDTH];
+
+        for sprite in sprites {
+            let row = match Sprite::row_on_scanline(sprite.y, y as u16, height)
+            {
+                Some(row) => row,
+                None => continue,
+            };
+            let address = sprite.pattern_address(row, height, pattern_table);
+            let low = self.internal_memory.read(address);
+            let high = self.internal_memory.read(address + 8);
+
+            for column in 0..8u8 {
+                let x = sprite.x as usize + column as usize;
+                if x >= SCREEN_WIDTH {
+                    break;
+                }
+
+                let pixel = sprite.pixel(low, high, column);
+                if pixel == 0 {
+                    continue;
+                }
+                if x < 8 && self.ppumask & MASK_SPRITES_LEFT == 0 {
+                    continue;
+                }
+
+                // Sprite 0 hit never happens at X = 255, or in the leftmost
+                // 8 pixels if either of them are clipped.
+                if sprite.index == 0
+                    && background_enabled
+                    && background[x] != 0
+                    && x != 255
+                    && !(x < 8 && left_clip)
+                {
+                    self.ppustatus |= STATUS_SPRITE_ZERO_HIT;
+                }
+
+                // Lower-indexed sprites always win, even if they end up
+                // behind the background.
+                if drawn[x] != 0 {
+                    continue;
+                }
+                drawn[x] = 0x10 | (sprite.palette() << 2) | pixel;
+
+                if !sprite.behind_background() || background[x] == 0 {
+                    line[x] = drawn[x];
+                }
+            }
+        }
+    }
+
+    // Fills "line" with the palette index for each background pixel on the
+    // current scanline, starting at the tile addressed by v and offset by the
+    // fine X scroll.
+    fn render_background(&mut self, line: &mut [u8; SCREEN_WIDTH]) {
+        let pattern_table = if self.ppuctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        let mut v = self.vram_address;
+        let fine_y = (v & SCROLL_FINE_Y) >> 12;
+        let fine_x = u16::from(self.fine_x);
+
+        // Each nametable is 32 tiles wide, but with fine X scrolling the
+        // scanline can span 33 tiles.
+        for tile_index in 0..33u16 {
+            let coarse_x = v & SCROLL_COARSE_X;
+            let coarse_y = (v & SCROLL_COARSE_Y) >> 5;
+
+            // Fetch the tile index from the nametable.
+            let tile = self.internal_memory.read(NAMETABLE_BASE | (v & 0x0fff));
+
+            // Fetch the attribute byte. Each attribute byte covers a 4x4 tile
+            // area, split into four 2x2 tile quadrants of 2 bits each.
+            let attribute = self.internal_memory.read(
+                NAMETABLE_BASE
+                    | ATTRIBUTE_TABLE_OFFSET
+                    | (v & (SCROLL_NAMETABLE_X | SCROLL_NAMETABLE_Y))
+                    | ((coarse_y >> 2) << 3)
+                    | (coarse_x >> 2),
+            );
+            let attribute_shift = ((coarse_y & 0x02) << 1) | (coarse_x & 0x02);
+            let palette = (attribute >> attribute_shift) & 0x03;
+
+            // Fetch the two bit planes for this row of the tile.
+            let pattern_address =
+                pattern_table | (u16::from(tile) << 4) | fine_y;
+            let low = self.internal_memory.read(pattern_address);
+            let high = self.internal_memory.read(pattern_address + 8);
+
+            for column in 0..8 {
+                let x = (tile_index * 8 + column) as usize;
+                if x < fine_x as usize {
+                    continue;
+                }
+                let x = x - fine_x as usize;
+                if x >= SCREEN_WIDTH {
+                    break;
+                }
+
+                let bit = 7 - column;
+                let pixel =
+                    ((low >> bit) & 0x01) | (((high >> bit) & 0x01) << 1);
+                line[x] = if pixel == 0 {
+                    0
+                } else {
+                    (palette << 2) | pixel
+                };
+            }
+
+            // Move to the next tile, wrapping into the horizontally adjacent
+            // nametable.
+            if coarse_x == 31 {
+                v = (v & !SCROLL_COARSE_X) ^ SCROLL_NAMETABLE_X;
+            } else {
+                v += 1;
+            }
+        }
+
+        // Optionally hide the background in the leftmost 8 pixels.
+        if self.ppumask & MASK_BACKGROUND_LEFT == 0 {
+            line[..8].fill(0);
+        }
+    }
+
+    // Looks up the RGB color for an index into palette RAM, applying the
+    // greyscale and color emphasis bits from PPUMASK.
+    fn palette_color(&self, palette_index: u8) -> [u8; 3] {
+        let mut color = self
+            .internal_memory
+            .peek(PALETTE_BASE | u16::from(palette_index))
+            & 0x3f;
+
+        // Greyscale drops the hue, leaving only the column of grey colors.
+        if self.ppumask & MASK_GREYSCALE != 0 {
+            color &= 0x30;
+        }
+
+        let base = color as usize * 3;
+        let mut rgb = [PALETTE[base], PALETTE[base + 1], PALETTE[base + 2]];
+
+        // Emphasizing a color darkens the other two. With every bit set, all
+        // three are darkened.
+        let emphasis = [
+            MASK_EMPHASIZE_RED,
+            MASK_EMPHASIZE_GREEN,
+            MASK_EMPHASIZE_BLUE,
+        ];
+        let any_emphasis = self.ppumask & 0xe0 != 0;
+        let all_emphasis = self.ppumask & 0xe0 == 0xe0;
+        for (channel, mask) in rgb.iter_mut().zip(emphasis) {
+            if any_emphasis && (all_emphasis || self.ppumask & mask == 0) {
+                *channel =
+                    (u16::from(*channel) * EMPHASIS_ATTENUATION / 256) as u8;
+            }
+        }
+        rgb
+    }
 }
 
 impl Memory for Ppu {
-    // Fetches a byte from the specified address in memory.
-    fn fetch(&self, address: u16) -> u8 {
+    // Reads a byte from the specified address in memory. Reading PPUSTATUS
+    // and PPUDATA has side effects on the PPU's internal state.
+    fn read(&mut self, address: u16) -> u8 {
+        let value = match address {
+            0x2002 => self.read_status(),
+            0x2007 => self.read_data(),
+            _ => self.peek(address),
+        };
+        self.set_io_latch(value);
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
         match address {
-            0x2000 => self.ppuctrl,
-            0x2001 => self.ppumask,
-            0x2002 => self.ppustatus,
-            0x2003 => self.oamaddr,
-            0x2004 => self.oamdata,
-            0x2005 => self.ppuscroll,
-            0x2006 => self.ppuaddr,
-            0x2007 => self.ppudata,
-            0x4014 => self.oamdma,
-            _ => panic!(
-                "Tried to access non-existent PPU register at {:#04x}",
-                address
-            ),
+            0x2002 => self.peek_status(),
+            0x2004 => self.oam[self.oamaddr as usize],
+            0x2007 => self.peek_data(),
+
+            // Write-only registers, along with anything else, read back
+            // the I/O latch.
+            _ => self.io_latch,
         }
     }
 
     // Stores value into memory at the specified address.
-    // Returns the previous value.
+    // Returns the previous value on the PPU's data bus.
     fn store(&mut self, address: u16, value: u8) -> u8 {
-        let old_value = self.fetch(address);
+        let old_value = self.set_io_latch(value);
 
         match address {
-            0x2000 => self.ppuctrl = value,
+            0x2000 => self.write_ctrl(value),
             0x2001 => self.ppumask = value,
-            0x2002 => self.ppustatus = value,
+            0x2002 => (),
             0x2003 => self.oamaddr = value,
-            0x2004 => self.oamdata = value,
-            0x2005 => self.ppuscroll = value,
-            0x2006 => self.ppuaddr = value,
-            0x2007 => self.ppudata = value,
-            0x4014 => self.oamdma = value,
-            _ => panic!(
-                "Tried to access non-existent PPU register at {:#04x}",
+            0x2004 => self.write_oam(value),
+            0x2005 => self.write_scroll(value),
+            0x2006 => self.write_address(value),
+            0x2007 => self.write_data(value),
+            0x4014 => {
+                self.oamdma = value;
+                self.oam_dma_requested = true;
+            }
+            _ => warn!(
+                "Ignoring store to non-existent PPU register at {:#06x}",
                 address
             ),
         };
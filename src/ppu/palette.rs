@@ -0,0 +1,49 @@
+use crate::nes::memory::Memory;
+
+// Size of palette RAM in bytes: 4 background palettes and 4 sprite palettes,
+// of 4 colors each.
+pub const PALETTE_RAM_SIZE: usize = 32;
+
+// Palette RAM only stores the 6 bits needed for a color index.
+const COLOR_MASK: u8 = 0x3f;
+
+// PPU palette RAM, mapped to $3F00-$3F1F and mirrored up to $3FFF.
+//
+// Entry 0 of each sprite palette ($3F10/$3F14/$3F18/$3F1C) is a mirror of
+// entry 0 of the matching background palette ($3F00/$3F04/$3F08/$3F0C).
+//
+// See http://wiki.nesdev.com/w/index.php/PPU_palettes for more details.
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+}
+
+impl PaletteRam {
+    pub fn new() -> Self {
+        PaletteRam {
+            data: [0x00; PALETTE_RAM_SIZE],
+        }
+    }
+
+    // Maps an address in $3F00-$3FFF to an index into "data".
+    fn index(address: u16) -> usize {
+        let index = address as usize % PALETTE_RAM_SIZE;
+        if index & 0x13 == 0x10 {
+            index & 0x0f
+        } else {
+            index
+        }
+    }
+}
+
+impl Memory for PaletteRam {
+    fn peek(&self, address: u16) -> u8 {
+        self.data[PaletteRam::index(address)]
+    }
+
+    fn store(&mut self, address: u16, value: u8) -> u8 {
+        let index = PaletteRam::index(address);
+        let old_value = self.data[index];
+        self.data[index] = value & COLOR_MASK;
+        old_value
+    }
+}
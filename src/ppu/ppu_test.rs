@@ -0,0 +1,589 @@
+use crate::cartridge::Cartridge;
+use crate::nes::memory::Memory;
+use crate::ppu::{
+    CTRL_SPRITE_TABLE, IO_LATCH_DECAY_FRAMES, MASK_BACKGROUND,
+    MASK_BACKGROUND_LEFT, MASK_EMPHASIZE_RED, MASK_GREYSCALE, MASK_SPRITES,
+    MASK_SPRITES_LEFT, Ppu, SCREEN_WIDTH, STATUS_SPRITE_OVERFLOW,
+    STATUS_SPRITE_ZERO_HIT, STATUS_V_BLANK, V_BLANK_SCANLINE,
+};
+use crate::rom::{CHR_ROM_SIZE, PRG_ROM_SIZE, RomFile};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Builds a PPU with an NROM cartridge whose pattern table has a solid tile
+// (every pixel is color 3) at index 1.
+fn new_ppu() -> Ppu {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01];
+    rom.resize(16 + PRG_ROM_SIZE, 0x00);
+    let mut chr_rom = [0x00; CHR_ROM_SIZE];
+    for byte in chr_rom[0x0010..0x0020].iter_mut() {
+        *byte = 0xff;
+    }
+    rom.extend_from_slice(&chr_rom);
+
+    let rom = RomFile::new_from_buffer("test".to_string(), &rom).unwrap();
+    let cartridge = Cartridge::new(&rom).unwrap();
+    Ppu::new(Rc::new(RefCell::new(cartridge)))
+}
+
+// Builds a PPU with an NROM cartridge that has CHR RAM and vertical
+// mirroring, so that tests can write their own patterns and use both of the
+// side by side nametables.
+fn new_chr_ram_ppu() -> Ppu {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x01];
+    rom.resize(16 + PRG_ROM_SIZE, 0x00);
+    let rom = RomFile::new_from_buffer("test".to_string(), &rom).unwrap();
+    let cartridge = Cartridge::new(&rom).unwrap();
+    Ppu::new(Rc::new(RefCell::new(cartridge)))
+}
+
+// Stores a tile at "address" in the pattern tables, with every row made of
+// the bit planes "low" and "high".
+fn store_tile(ppu: &mut Ppu, address: u16, low: u8, high: u8) {
+    for row in 0..8 {
+        ppu.internal_memory.store(address + row, low);
+        ppu.internal_memory.store(address + 8 + row, high);
+    }
+}
+
+// Renders the background of "scanline" as if the frame started with no
+// scrolling.
+fn render_background(ppu: &mut Ppu, scanline: u16) -> [u8; SCREEN_WIDTH] {
+    ppu.current_scanline = scanline;
+    ppu.vram_address = ((scanline & 0x07) << 12) | ((scanline >> 3) << 5);
+    let mut line = [0x00; SCREEN_WIDTH];
+    ppu.render_background(&mut line);
+    line
+}
+
+// Places sprite "index" in OAM.
+fn set_sprite(ppu: &mut Ppu, index: usize, y: u8, tile: u8, x: u8) {
+    ppu.oam[index * 4] = y;
+    ppu.oam[index * 4 + 1] = tile;
+    ppu.oam[index * 4 + 2] = 0x00;
+    ppu.oam[index * 4 + 3] = x;
+}
+
+// Renders "scanline" as if the frame started with no scrolling.
+fn render_scanline(ppu: &mut Ppu, scanline: u16) {
+    ppu.current_scanline = scanline;
+    ppu.vram_address = ((scanline & 0x07) << 12) | ((scanline >> 3) << 5);
+    ppu.render_scanline();
+}
+
+#[test]
+fn test_oam_data() {
+    let mut ppu = new_ppu();
+
+    // Writes to OAMDATA increment OAMADDR.
+    ppu.store(0x2003, 0xfe);
+    ppu.store(0x2004, 0x11);
+    ppu.store(0x2004, 0x22);
+    ppu.store(0x2004, 0x33);
+    assert_eq!(ppu.oam[0xfe], 0x11);
+    assert_eq!(ppu.oam[0xff], 0x22);
+    assert_eq!(ppu.oam[0x00], 0x33);
+
+    // Reads from OAMDATA don't.
+    ppu.store(0x2003, 0xff);
+    assert_eq!(ppu.read(0x2004), 0x22);
+    assert_eq!(ppu.read(0x2004), 0x22);
+
+    // Writes to OAMDMA are handed off to the CPU side.
+    assert_eq!(ppu.take_oam_dma(), None);
+    ppu.store(0x4014, 0x02);
+    assert_eq!(ppu.take_oam_dma(), Some(0x02));
+    assert_eq!(ppu.take_oam_dma(), None);
+}
+
+#[test]
+fn test_sprite_evaluation() {
+    let mut ppu = new_ppu();
+    ppu.ppumask = MASK_SPRITES;
+
+    // Move every sprite off screen.
+    for index in 0..64 {
+        set_sprite(&mut ppu, index, 0xff, 0x00, 0x00);
+    }
+
+    // Sprites are drawn one scanline below their Y position.
+    set_sprite(&mut ppu, 3, 20, 0x01, 0x00);
+    assert_eq!(ppu.evaluate_sprites(20).len(), 0);
+    assert_eq!(ppu.evaluate_sprites(21).len(), 1);
+    assert_eq!(ppu.evaluate_sprites(28).len(), 1);
+    assert_eq!(ppu.evaluate_sprites(29).len(), 0);
+
+    // 8x16 sprites cover twice as many scanlines.
+    ppu.ppuctrl = 0x20;
+    assert_eq!(ppu.evaluate_sprites(36).len(), 1);
+    assert_eq!(ppu.evaluate_sprites(37).len(), 0);
+    ppu.ppuctrl = 0x00;
+
+    // Exactly 8 sprites doesn't overflow.
+    for index in 10..17 {
+        set_sprite(&mut ppu, index, 20, 0x01, 0x00);
+    }
+    let sprites = ppu.evaluate_sprites(21);
+    assert_eq!(sprites.len(), 8);
+    assert_eq!(sprites[0].index, 3);
+    assert_eq!(ppu.ppustatus & STATUS_SPRITE_OVERFLOW, 0);
+
+    // A ninth one does.
+    set_sprite(&mut ppu, 17, 20, 0x01, 0x00);
+    assert_eq!(ppu.evaluate_sprites(21).len(), 8);
+    assert_eq!(
+        ppu.ppustatus & STATUS_SPRITE_OVERFLOW,
+        STATUS_SPRITE_OVERFLOW
+    );
+}
+
+#[test]
+fn test_sprite_zero_hit() {
+    let mut ppu = new_ppu();
+    ppu.ppumask = MASK_BACKGROUND
+        | MASK_SPRITES
+        | MASK_BACKGROUND_LEFT
+        | MASK_SPRITES_LEFT;
+
+    // Solid background tile at tile (2, 2).
+    ppu.internal_memory.store(0x2000 + 2 * 32 + 2, 0x01);
+
+    // Sprite 0 overlapping nothing but transparent background.
+    set_sprite(&mut ppu, 0, 15, 0x01, 0x40);
+    for scanline in 0..240 {
+        render_scanline(&mut ppu, scanline);
+    }
+    assert_eq!(ppu.ppustatus & STATUS_SPRITE_ZERO_HIT, 0);
+
+    // Sprite 1 overlapping the background doesn't count.
+    set_sprite(&mut ppu, 1, 15, 0x01, 0x10);
+    render_scanline(&mut ppu, 16);
+    assert_eq!(ppu.ppustatus & STATUS_SPRITE_ZERO_HIT, 0);
+
+    // Sprite 0 overlapping the background does.
+    set_sprite(&mut ppu, 0, 15, 0x01, 0x12);
+    render_scanline(&mut ppu, 16);
+    assert_eq!(
+        ppu.ppustatus & STATUS_SPRITE_ZERO_HIT,
+        STATUS_SPRITE_ZERO_HIT
+    );
+}
+
+#[test]
+fn test_scroll_registers() {
+    let mut ppu = new_ppu();
+
+    // PPUCTRL sets the nametable bits of t.
+    ppu.store(0x2000, 0x03);
+    assert_eq!(ppu.temp_vram_address, 0x0c00);
+
+    // First PPUSCROLL write sets coarse X and fine X.
+    ppu.store(0x2005, 0x7d);
+    assert_eq!(ppu.temp_vram_address, 0x0c0f);
+    assert_eq!(ppu.fine_x, 0x05);
+    assert!(ppu.write_toggle);
+
+    // Second PPUSCROLL write sets coarse Y and fine Y.
+    ppu.store(0x2005, 0x5e);
+    assert_eq!(ppu.temp_vram_address, 0x6d6f);
+    assert!(!ppu.write_toggle);
+
+    // PPUADDR shares the write toggle, and copies t to v on the second write.
+    ppu.store(0x2006, 0x3d);
+    assert_eq!(ppu.temp_vram_address, 0x3d6f);
+    assert_eq!(ppu.vram_address, 0x0000);
+    ppu.store(0x2006, 0xf0);
+    assert_eq!(ppu.temp_vram_address, 0x3df0);
+    assert_eq!(ppu.vram_address, 0x3df0);
+
+    // Reading PPUSTATUS resets the write toggle.
+    ppu.store(0x2006, 0x21);
+    ppu.read(0x2002);
+    ppu.store(0x2006, 0x22);
+    ppu.store(0x2006, 0x08);
+    assert_eq!(ppu.vram_address, 0x2208);
+}
+
+#[test]
+fn test_ppu_data() {
+    let mut ppu = new_ppu();
+
+    // Writes increment v by 1.
+    ppu.store(0x2006, 0x20);
+    ppu.store(0x2006, 0x00);
+    ppu.store(0x2007, 0x11);
+    ppu.store(0x2007, 0x22);
+    assert_eq!(ppu.vram_address, 0x2002);
+
+    // Or by 32, based on PPUCTRL.
+    ppu.store(0x2000, 0x04);
+    ppu.store(0x2007, 0x33);
+    assert_eq!(ppu.vram_address, 0x2022);
+    assert_eq!(ppu.internal_memory.read(0x2002), 0x33);
+    ppu.store(0x2000, 0x00);
+
+    // Reads are delayed by one through the read buffer.
+    ppu.store(0x2006, 0x20);
+    ppu.store(0x2006, 0x00);
+    ppu.read(0x2007);
+    assert_eq!(ppu.read(0x2007), 0x11);
+    assert_eq!(ppu.read(0x2007), 0x22);
+
+    // Reads from the pattern tables are buffered too.
+    ppu.store(0x2006, 0x00);
+    ppu.store(0x2006, 0x10);
+    ppu.read(0x2007);
+    assert_eq!(ppu.read(0x2007), 0xff);
+}
+
+#[test]
+fn test_ppu_status() {
+    let mut ppu = new_ppu();
+    ppu.ppustatus = STATUS_V_BLANK | STATUS_SPRITE_ZERO_HIT;
+
+    // The low bits come from the PPU's data bus.
+    ppu.store(0x2000, 0x1f);
+    assert_eq!(ppu.read(0x2002), 0xdf);
+
+    // Reading clears VBlank, but not the sprite flags.
+    assert_eq!(ppu.read(0x2002) & 0xe0, STATUS_SPRITE_ZERO_HIT);
+
+    // VBlank is set at the start of scanline 241, and cleared on the
+    // pre-render scanline.
+    ppu.current_scanline = 240;
+    ppu.cycle = 0;
+    let (_, v_blank) = ppu.step(341);
+    assert!(v_blank);
+    assert_eq!(ppu.ppustatus & STATUS_V_BLANK, STATUS_V_BLANK);
+    ppu.current_scanline = 260;
+    ppu.step(341);
+    assert_eq!(ppu.ppustatus, 0x00);
+}
+
+#[test]
+fn test_peek() {
+    let mut ppu = new_ppu();
+    ppu.ppustatus = STATUS_V_BLANK;
+    ppu.internal_memory.store(0x2000, 0x12);
+    ppu.store(0x2006, 0x20);
+    ppu.store(0x2006, 0x00);
+
+    // Peeking doesn't clear VBlank, advance v or fill the read buffer.
+    assert_eq!(ppu.peek(0x2002) & 0xe0, STATUS_V_BLANK);
+    assert_eq!(ppu.peek(0x2002) & 0xe0, STATUS_V_BLANK);
+    assert_eq!(ppu.peek(0x2007), 0x00);
+    assert_eq!(ppu.peek(0x2007), 0x00);
+    assert_eq!(ppu.vram_address, 0x2000);
+
+    // Reading does.
+    assert_eq!(ppu.read(0x2002) & 0xe0, STATUS_V_BLANK);
+    assert_eq!(ppu.peek(0x2002) & 0xe0, 0x00);
+    assert_eq!(ppu.read(0x2007), 0x00);
+    assert_eq!(ppu.peek(0x2007), 0x12);
+    assert_eq!(ppu.vram_address, 0x2001);
+}
+
+#[test]
+fn test_io_latch() {
+    let mut ppu = new_ppu();
+
+    // Write-only registers read back the last value written to any register.
+    ppu.store(0x2003, 0xc5);
+    assert_eq!(ppu.read(0x2000), 0xc5);
+    assert_eq!(ppu.read(0x4014), 0xc5);
+
+    // Palette reads fill in the top 2 bits from the latch.
+    ppu.internal_memory.store(0x3f01, 0x21);
+    ppu.store(0x2006, 0x3f);
+    ppu.store(0x2006, 0xc1);
+    assert_eq!(ppu.read(0x2007), 0xe1);
+
+    // The latch decays if it isn't refreshed for long enough.
+    ppu.store(0x2000, 0x5a);
+    for _ in 0..(IO_LATCH_DECAY_FRAMES - 1) * 262 {
+        ppu.step(341);
+    }
+    assert_eq!(ppu.read(0x2001), 0x5a);
+    for _ in 0..IO_LATCH_DECAY_FRAMES * 262 {
+        ppu.step(341);
+    }
+    assert_eq!(ppu.read(0x2001), 0x00);
+}
+
+#[test]
+fn test_background_patterns() {
+    let mut ppu = new_chr_ram_ppu();
+    ppu.ppumask = MASK_BACKGROUND | MASK_BACKGROUND_LEFT;
+
+    // Tile 2 in each pattern table, with its fourth row made of each of the
+    // four colors.
+    ppu.internal_memory.store(0x0023, 0b1010_0000);
+    ppu.internal_memory.store(0x002b, 0b1100_0000);
+    ppu.internal_memory.store(0x1023, 0b0101_0000);
+    ppu.internal_memory.store(0x102b, 0b0011_0000);
+
+    // In the second column of the second row of tiles.
+    ppu.internal_memory.store(0x2000 + 32 + 1, 0x02);
+
+    let line = render_background(&mut ppu, 11);
+    assert_eq!(line[8..16], [3, 2, 1, 0, 0, 0, 0, 0][..]);
+    assert!(line[..8].iter().chain(&line[16..]).all(|&pixel| pixel == 0));
+
+    // Other rows of the tile are blank.
+    assert!(
+        render_background(&mut ppu, 12)
+            .iter()
+            .all(|&pixel| pixel == 0)
+    );
+
+    // PPUCTRL picks the pattern table.
+    ppu.store(0x2000, 0x10);
+    let line = render_background(&mut ppu, 11);
+    assert_eq!(line[8..16], [0, 1, 2, 3, 0, 0, 0, 0][..]);
+
+    // The leftmost 8 pixels can be hidden.
+    ppu.internal_memory.store(0x2000 + 32, 0x02);
+    assert_eq!(render_background(&mut ppu, 11)[..4], [0, 1, 2, 3][..]);
+    ppu.ppumask = MASK_BACKGROUND;
+    assert_eq!(render_background(&mut ppu, 11)[..8], [0; 8][..]);
+}
+
+#[test]
+fn test_background_attributes() {
+    let mut ppu = new_chr_ram_ppu();
+    ppu.ppumask = MASK_BACKGROUND | MASK_BACKGROUND_LEFT;
+    store_tile(&mut ppu, 0x0010, 0xff, 0xff);
+
+    // Solid tiles in the top left 8x4 tiles, covered by two attribute bytes.
+    for row in 0..4 {
+        for column in 0..8 {
+            ppu.internal_memory.store(0x2000 + row * 32 + column, 0x01);
+        }
+    }
+    // Palettes 0, 1, 2 and 3 for the top left, top right, bottom left and
+    // bottom right quadrants, then all palette 2.
+    ppu.internal_memory.store(0x23c0, 0b11_10_01_00);
+    ppu.internal_memory.store(0x23c1, 0b10_10_10_10);
+
+    // Each quadrant is 2x2 tiles.
+    let expected = [
+        (0, [0x03, 0x03, 0x07, 0x07, 0x0b, 0x0b, 0x0b, 0x0b]),
+        (15, [0x03, 0x03, 0x07, 0x07, 0x0b, 0x0b, 0x0b, 0x0b]),
+        (16, [0x0b, 0x0b, 0x0f, 0x0f, 0x0b, 0x0b, 0x0b, 0x0b]),
+        (31, [0x0b, 0x0b, 0x0f, 0x0f, 0x0b, 0x0b, 0x0b, 0x0b]),
+    ];
+    for (scanline, tiles) in expected.iter() {
+        let line = render_background(&mut ppu, *scanline);
+        for (column, palette_index) in tiles.iter().enumerate() {
+            assert!(
+                line[column * 8..column * 8 + 8]
+                    .iter()
+                    .all(|pixel| pixel == palette_index),
+                "Bad palette index on scanline {} in column {}",
+                scanline,
+                column
+            );
+        }
+    }
+}
+
+#[test]
+fn test_fine_x_scroll() {
+    let mut ppu = new_chr_ram_ppu();
+    ppu.ppumask = MASK_BACKGROUND | MASK_BACKGROUND_LEFT;
+    store_tile(&mut ppu, 0x0010, 0xff, 0xff);
+    store_tile(&mut ppu, 0x0020, 0xff, 0x00);
+
+    // Color 3 at the start of the first nametable, and color 1 at the start
+    // of the second.
+    ppu.internal_memory.store(0x2000, 0x01);
+    ppu.internal_memory.store(0x2400, 0x02);
+
+    // Scrolling by 3 pixels shifts the first tile left, and brings 3 pixels
+    // of a 33rd tile, from the next nametable, in on the right.
+    ppu.store(0x2005, 3);
+    ppu.store(0x2005, 0);
+    ppu.current_scanline = 0;
+    ppu.copy_scroll_bits(0x7fff);
+    let mut line = [0x00; SCREEN_WIDTH];
+    ppu.render_background(&mut line);
+    assert_eq!(line[..6], [3, 3, 3, 3, 3, 0][..]);
+    assert!(line[5..253].iter().all(|&pixel| pixel == 0));
+    assert_eq!(line[253..], [1, 1, 1][..]);
+
+    // Without fine X scrolling, the 33rd tile isn't visible.
+    ppu.store(0x2005, 0);
+    ppu.store(0x2005, 0);
+    assert_eq!(
+        render_background(&mut ppu, 0)[..9],
+        [3, 3, 3, 3, 3, 3, 3, 3, 0][..]
+    );
+}
+
+#[test]
+fn test_scrolled_background() {
+    let mut ppu = new_ppu();
+    ppu.ppumask = MASK_BACKGROUND | MASK_BACKGROUND_LEFT;
+
+    // Solid tile at the far right of nametable 0, row 0.
+    ppu.internal_memory.store(0x2000 + 31, 0x01);
+
+    // Scroll right by 250 pixels: the last 6 pixels of the tile are on
+    // screen, followed by the second nametable.
+    ppu.store(0x2005, 250);
+    ppu.store(0x2005, 0);
+    ppu.current_scanline = 0;
+    ppu.copy_scroll_bits(0x7fff);
+    let mut line = [0x00; SCREEN_WIDTH];
+    ppu.render_background(&mut line);
+    assert_eq!(line[0..8], [3, 3, 3, 3, 3, 3, 0, 0][..]);
+}
+
+// Steps "ppu" from the end of scanline 240 into VBlank.
+fn enter_v_blank(ppu: &mut Ppu) {
+    ppu.current_scanline = 240;
+    ppu.cycle = 340;
+    ppu.step(1);
+}
+
+#[test]
+fn test_v_blank_nmi() {
+    let mut ppu = new_ppu();
+
+    // No NMI unless PPUCTRL asks for one.
+    enter_v_blank(&mut ppu);
+    assert!(!ppu.take_nmi());
+
+    // Enabling NMIs during VBlank raises one immediately.
+    ppu.store(0x2000, 0x80);
+    assert!(ppu.take_nmi());
+    assert!(!ppu.take_nmi());
+
+    // Writing PPUCTRL again with NMIs already on doesn't.
+    ppu.store(0x2000, 0x80);
+    assert!(!ppu.take_nmi());
+
+    // Nor does enabling them once VBlank has been acknowledged.
+    ppu.store(0x2000, 0x00);
+    ppu.read(0x2002);
+    ppu.store(0x2000, 0x80);
+    assert!(!ppu.take_nmi());
+
+    // The start of VBlank raises one.
+    enter_v_blank(&mut ppu);
+    assert!(ppu.take_nmi());
+}
+
+#[test]
+fn test_v_blank_nmi_suppression() {
+    let mut ppu = new_ppu();
+    ppu.store(0x2000, 0x80);
+
+    // Reading PPUSTATUS one dot before VBlank stops the flag being set.
+    ppu.current_scanline = 240;
+    ppu.cycle = 340;
+    assert_eq!(ppu.read(0x2002) & STATUS_V_BLANK, 0);
+    ppu.step(1);
+    assert_eq!(ppu.ppustatus & STATUS_V_BLANK, 0);
+    assert!(!ppu.take_nmi());
+
+    // Reading it as VBlank starts returns the flag, but drops the NMI.
+    enter_v_blank(&mut ppu);
+    assert_eq!(ppu.read(0x2002) & STATUS_V_BLANK, STATUS_V_BLANK);
+    assert!(!ppu.take_nmi());
+
+    // Reading it later on leaves the NMI alone.
+    enter_v_blank(&mut ppu);
+    ppu.cycle = 10;
+    ppu.read(0x2002);
+    assert!(ppu.take_nmi());
+}
+
+#[test]
+fn test_palette_ram() {
+    let mut ppu = new_ppu();
+
+    // Palette RAM is mirrored every 32 bytes up to $3FFF.
+    ppu.internal_memory.store(0x3f01, 0x21);
+    assert_eq!(ppu.internal_memory.read(0x3f21), 0x21);
+    assert_eq!(ppu.internal_memory.read(0x3fe1), 0x21);
+
+    // Sprite palette entry 0 mirrors background palette entry 0.
+    for offset in [0x00, 0x04, 0x08, 0x0c] {
+        ppu.internal_memory
+            .store(0x3f10 + u16::from(offset), 0x10 + offset);
+        assert_eq!(
+            ppu.internal_memory.read(0x3f00 + u16::from(offset)),
+            0x10 + offset
+        );
+    }
+
+    // But the other sprite palette entries don't.
+    ppu.internal_memory.store(0x3f11, 0x2a);
+    assert_eq!(ppu.internal_memory.read(0x3f01), 0x21);
+
+    // Only 6 bits are stored.
+    ppu.internal_memory.store(0x3f02, 0xff);
+    assert_eq!(ppu.internal_memory.read(0x3f02), 0x3f);
+
+    // PPUDATA reads from the palette aren't buffered.
+    ppu.store(0x2006, 0x3f);
+    ppu.store(0x2006, 0x01);
+    assert_eq!(ppu.read(0x2007), 0x21);
+}
+
+#[test]
+fn test_palette_color() {
+    let mut ppu = new_ppu();
+    ppu.internal_memory.store(0x3f00, 0x15);
+    assert_eq!(ppu.palette_color(0x00), [228, 0, 88]);
+
+    // Greyscale keeps only the brightness column.
+    ppu.ppumask = MASK_GREYSCALE;
+    assert_eq!(ppu.palette_color(0x00), [188, 188, 188]);
+
+    // Emphasizing red darkens green and blue.
+    ppu.ppumask = MASK_EMPHASIZE_RED;
+    assert_eq!(ppu.palette_color(0x00), [228, 0, 71]);
+
+    // Emphasizing everything darkens everything.
+    ppu.ppumask = 0xe0;
+    assert_eq!(ppu.palette_color(0x00), [186, 0, 71]);
+}
+
+// MMC3 counts scanlines by watching for A12 to rise on the PPU's address bus.
+// With the background at $0000 and sprites at $1000, that happens once on
+// every rendered scanline, the pre-render one included.
+#[test]
+fn test_mmc3_scanline_irq() {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x40];
+    rom.resize(16 + 2 * PRG_ROM_SIZE + CHR_ROM_SIZE, 0x00);
+    let rom = RomFile::new_from_buffer("test".to_string(), &rom).unwrap();
+    let cartridge = Rc::new(RefCell::new(Cartridge::new(&rom).unwrap()));
+    let mut ppu = Ppu::new(cartridge.clone());
+    ppu.ppuctrl = CTRL_SPRITE_TABLE;
+    ppu.ppumask = MASK_BACKGROUND | MASK_SPRITES;
+
+    assert_eq!(ppu.current_scanline, V_BLANK_SCANLINE);
+    for _ in 0..3 {
+        // Acknowledge the last IRQ, and ask for one after 20 scanlines during
+        // VBlank, like a status bar split would.
+        {
+            let mut cartridge = cartridge.borrow_mut();
+            cartridge.store(0xe000, 0x00);
+            cartridge.store(0xc000, 20);
+            cartridge.store(0xc001, 0x00);
+            cartridge.store(0xe001, 0x00);
+        }
+
+        // The pre-render scanline reloads the counter, and each visible one
+        // counts down, so it reaches 0 on scanline 19.
+        let mut irq_scanline = None;
+        while !ppu.step(1).1 {
+            if irq_scanline.is_none() && cartridge.borrow().irq() {
+                irq_scanline = Some(ppu.current_scanline);
+            }
+        }
+        assert_eq!(irq_scanline, Some(19));
+    }
+}
@@ -0,0 +1,117 @@
+// Number of sprites that can be stored in OAM.
+pub const SPRITE_COUNT: usize = 64;
+
+// Size of OAM in bytes. Each sprite takes up 4 bytes.
+pub const OAM_SIZE: usize = SPRITE_COUNT * 4;
+
+// Maximum number of sprites that can be drawn on a single scanline.
+pub const SPRITES_PER_SCANLINE: usize = 8;
+
+// Sprite attributes: palette (4 to 7) of sprite.
+const ATTRIBUTE_PALETTE: u8 = 0b0000_0011;
+// Sprite attributes: priority (0 = in front of background, 1 = behind).
+const ATTRIBUTE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+// Sprite attributes: flip sprite horizontally.
+const ATTRIBUTE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+// Sprite attributes: flip sprite vertically.
+const ATTRIBUTE_FLIP_VERTICAL: u8 = 0b1000_0000;
+
+// A single sprite, as stored in OAM.
+//
+// See http://wiki.nesdev.com/w/index.php/PPU_OAM for more details.
+#[derive(Debug, Copy, Clone)]
+pub struct Sprite {
+    // Index of the sprite in OAM (0 - 63). Sprite 0 is special, since it
+    // is used for sprite 0 hit detection.
+    pub index: u8,
+
+    // Byte 0: Y position of the top of the sprite, minus 1.
+    pub y: u8,
+
+    // Byte 1: Tile index number.
+    pub tile: u8,
+
+    // Byte 2: Attributes (palette, priority, flipping).
+    pub attributes: u8,
+
+    // Byte 3: X position of the left side of the sprite.
+    pub x: u8,
+}
+
+impl Sprite {
+    // Reads sprite number "index" out of OAM.
+    pub fn from_oam(oam: &[u8; OAM_SIZE], index: usize) -> Sprite {
+        let base = index * 4;
+        Sprite {
+            index: index as u8,
+            y: oam[base],
+            tile: oam[base + 1],
+            attributes: oam[base + 2],
+            x: oam[base + 3],
+        }
+    }
+
+    // Returns the row of the sprite that falls on "scanline", or None if the
+    // sprite isn't on that scanline. Sprites are drawn one scanline below
+    // their Y position.
+    pub fn row_on_scanline(y: u8, scanline: u16, height: u16) -> Option<u16> {
+        let top = u16::from(y) + 1;
+        if scanline >= top && scanline < top + height {
+            Some(scanline - top)
+        } else {
+            None
+        }
+    }
+
+    // Gets the address in the pattern tables of the row of this sprite that
+    // falls at "row". For 8x8 sprites, "pattern_table" selects the table.
+    // 8x16 sprites pick their table with bit 0 of the tile index instead.
+    pub fn pattern_address(
+        &self,
+        row: u16,
+        height: u16,
+        pattern_table: u16,
+    ) -> u16 {
+        let row = if self.flip_vertical() {
+            height - 1 - row
+        } else {
+            row
+        };
+
+        if height == 16 {
+            let table = u16::from(self.tile & 0x01) << 12;
+            let tile = u16::from(self.tile & 0xfe) + (row >> 3);
+            table | (tile << 4) | (row & 0x07)
+        } else {
+            pattern_table | (u16::from(self.tile) << 4) | row
+        }
+    }
+
+    // Gets the 2-bit pixel value at column "column" of a sprite row, from the
+    // low and high pattern bytes.
+    pub fn pixel(&self, low: u8, high: u8, column: u8) -> u8 {
+        let bit = if self.flip_horizontal() {
+            column
+        } else {
+            7 - column
+        };
+        ((low >> bit) & 0x01) | (((high >> bit) & 0x01) << 1)
+    }
+
+    // Which of the four sprite palettes to use.
+    pub fn palette(&self) -> u8 {
+        self.attributes & ATTRIBUTE_PALETTE
+    }
+
+    pub fn behind_background(&self) -> bool {
+        self.attributes & ATTRIBUTE_BEHIND_BACKGROUND != 0
+    }
+
+    pub fn flip_horizontal(&self) -> bool {
+        self.attributes & ATTRIBUTE_FLIP_HORIZONTAL != 0
+    }
+
+    pub fn flip_vertical(&self) -> bool {
+        self.attributes & ATTRIBUTE_FLIP_VERTICAL != 0
+    }
+}
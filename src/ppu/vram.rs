@@ -1,12 +1,16 @@
-// TODO: ADD TESTS!!!
-
 // 960 bytes of CHR tile position data, one byte for each 8x8 pixel tile. There
 // are 30 rows and 30 columns, which gives 960 total tiles.
+use crate::cartridge::Cartridge;
 use crate::nes::memory::Memory;
 use crate::rom::MirrorType;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 const TILE_DATA_SIZE: u16 = 960;
 
+// Size of a single nametable, including its attribute data.
+pub const NAMETABLE_SIZE: usize = 0x0400;
+
 // 64 bytes of attribute (palette) data. Each byte controls a 32×32 pixel or 4×4
 // tile area.
 const ATTRIBUTE_DATA_SIZE: u16 = 64;
@@ -26,10 +30,10 @@ impl Nametable {
 }
 
 impl Memory for Nametable {
-    fn fetch(&self, address: u16) -> u8 {
+    fn peek(&self, address: u16) -> u8 {
         match address {
             _ if address < TILE_DATA_SIZE => self.tile_data[address as usize],
-            _ if address < ATTRIBUTE_DATA_SIZE => {
+            _ if address < TILE_DATA_SIZE + ATTRIBUTE_DATA_SIZE => {
                 let attribute_address = (address - TILE_DATA_SIZE) as usize;
                 self.attribute_data[attribute_address]
             }
@@ -47,7 +51,7 @@ impl Memory for Nametable {
                 self.tile_data[address as usize] = value;
                 old_value
             }
-            _ if address < ATTRIBUTE_DATA_SIZE => {
+            _ if address < TILE_DATA_SIZE + ATTRIBUTE_DATA_SIZE => {
                 let attribute_address = (address - TILE_DATA_SIZE) as usize;
                 let old_value = self.attribute_data[attribute_address];
                 self.attribute_data[attribute_address] = value;
@@ -61,6 +65,27 @@ impl Memory for Nametable {
     }
 }
 
+// Where one of the four nametables is mapped to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NametableSource {
+    // One of the two pages of the PPU's internal VRAM (CIRAM).
+    Ciram(usize),
+    // A page of nametable RAM on the cartridge.
+    Cartridge(usize),
+}
+
+// Gets the nametable layout for a standard mirroring type.
+pub fn mirrored_nametables(mirroring: MirrorType) -> [NametableSource; 4] {
+    use NametableSource::{Cartridge, Ciram};
+    match mirroring {
+        MirrorType::Horizontal => [Ciram(0), Ciram(0), Ciram(1), Ciram(1)],
+        MirrorType::Vertical => [Ciram(0), Ciram(1), Ciram(0), Ciram(1)],
+        MirrorType::Both => [Ciram(0), Ciram(1), Cartridge(0), Cartridge(1)],
+        MirrorType::SingleScreenLower => [Ciram(0); 4],
+        MirrorType::SingleScreenUpper => [Ciram(1); 4],
+    }
+}
+
 // PPU internal VRAM, used to store 2 nametables. These nametables are mirrored
 // to make up 4kB of addressable memory. nametables for assigning CHR tiles to
 // each screen position.
@@ -88,119 +113,73 @@ impl Memory for Nametable {
 //
 // Horizontal Mirroring: $2000 equals $2400 and $2800 equals $2C00.
 // Vertical Mirroring: $2000 equals $2800, and $2400 equals $2C00.
+// Single-Screen Mirroring: All four are the same, either nametable A (lower)
+// or nametable B (upper).
+// Four-Screen: $2000 and $2400 are nametables A and B, and $2800 and $2C00
+// are extra RAM on the cartridge.
+//
+// Mappers can also map each of the four nametables anywhere they like at
+// runtime, so the layout is looked up from the cartridge on every access.
 pub struct Vram {
     nametable_a: Nametable,
     nametable_b: Nametable,
-    mirroring: MirrorType,
+    cartridge: Rc<RefCell<Cartridge>>,
 }
 
 impl Vram {
-    pub fn new(mirroring: MirrorType) -> Self {
+    pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> Self {
         Vram {
             nametable_a: Nametable::new(),
             nametable_b: Nametable::new(),
-            mirroring,
+            cartridge,
         }
     }
+
+    // Gets where the nametable containing "address" is mapped, and the offset
+    // of "address" within it.
+    fn locate(&self, address: u16) -> (NametableSource, u16) {
+        if !(0x2000..0x3000).contains(&address) {
+            panic!(
+                "address {:#04x} is not within PPU VRAM addressable range",
+                address
+            );
+        }
+        let slot = usize::from((address >> 10) & 0x03);
+        let source = self.cartridge.borrow().nametables()[slot];
+        (source, address & 0x03ff)
+    }
 }
 
 // This is how VRAM is accessed by the PPU.
 // See http://wiki.nesdev.com/w/index.php/Mirroring#Nametable_Mirroring for more
 // details.
 impl Memory for Vram {
-    fn fetch(&self, address: u16) -> u8 {
-        // This is a pain, but Rust doesn't support exclusive ranges on pattern
-        // matches, so this is what we're left with.
-        match address {
-            // Address too small.
-            _ if address < 0x2000 => panic!(
-                "address {:#04x} is not within PPU VRAM addressable range",
-                address
-            ),
-
-            // Top-left, always Nametable A.
-            _ if address < 0x2400 => self.nametable_a.fetch(address - 0x2000),
-
-            // Top-right, depends on mirroring configuration.
-            _ if address < 0x2800 => match self.mirroring {
-                MirrorType::Horizontal => {
-                    self.nametable_a.fetch(address - 0x2400)
-                }
-                MirrorType::Vertical => {
-                    self.nametable_b.fetch(address - 0x2400)
-                }
-                MirrorType::Both => panic!("MirrorType::Both is unsupported."),
-            },
-
-            // Bottom-left, depends on mirroring configuration.
-            _ if address < 0x2c00 => match self.mirroring {
-                MirrorType::Horizontal => {
-                    self.nametable_b.fetch(address - 0x2c00)
-                }
-                MirrorType::Vertical => {
-                    self.nametable_a.fetch(address - 0x2c00)
-                }
-                MirrorType::Both => panic!("MirrorType::Both is unsupported."),
-            },
-
-            // Bottom-right, always Nametable B.
-            _ if address < 0x3000 => self.nametable_b.fetch(address - 0x2c00),
-
-            // Address too large.
-            _ => panic!(
-                "address {:#04x} is not within PPU VRAM addressable range",
-                address
-            ),
+    fn peek(&self, address: u16) -> u8 {
+        match self.locate(address) {
+            (NametableSource::Ciram(0), offset) => {
+                self.nametable_a.peek(offset)
+            }
+            (NametableSource::Ciram(_), offset) => {
+                self.nametable_b.peek(offset)
+            }
+            (NametableSource::Cartridge(page), offset) => {
+                self.cartridge.borrow().fetch_nametable(page, offset)
+            }
         }
     }
 
     fn store(&mut self, address: u16, value: u8) -> u8 {
-        // This is a pain, but Rust doesn't support exclusive ranges on pattern
-        // matches, so this is what we're left with.
-        match address {
-            // Address too small.
-            _ if address < 0x2000 => panic!(
-                "address {:#04x} is not within PPU VRAM addressable range",
-                address
-            ),
-
-            // Top-left, always Nametable A.
-            _ if address < 0x2400 => {
-                self.nametable_a.store(address - 0x2000, value)
+        match self.locate(address) {
+            (NametableSource::Ciram(0), offset) => {
+                self.nametable_a.store(offset, value)
             }
-
-            // Top-right, depends on mirroring configuration.
-            _ if address < 0x2800 => match self.mirroring {
-                MirrorType::Horizontal => {
-                    self.nametable_a.store(address - 0x2400, value)
-                }
-                MirrorType::Vertical => {
-                    self.nametable_b.store(address - 0x2400, value)
-                }
-                MirrorType::Both => panic!("MirrorType::Both is unsupported."),
-            },
-
-            // Bottom-left, depends on mirroring configuration.
-            _ if address < 0x2c00 => match self.mirroring {
-                MirrorType::Horizontal => {
-                    self.nametable_b.store(address - 0x2c00, value)
-                }
-                MirrorType::Vertical => {
-                    self.nametable_a.store(address - 0x2c00, value)
-                }
-                MirrorType::Both => panic!("MirrorType::Both is unsupported."),
-            },
-
-            // Bottom-right, always Nametable B.
-            _ if address < 0x3000 => {
-                self.nametable_b.store(address - 0x2c00, value)
+            (NametableSource::Ciram(_), offset) => {
+                self.nametable_b.store(offset, value)
             }
-
-            // Address too large.
-            _ => panic!(
-                "address {:#04x} is not within PPU VRAM addressable range",
-                address
-            ),
+            (NametableSource::Cartridge(page), offset) => self
+                .cartridge
+                .borrow_mut()
+                .store_nametable(page, offset, value),
         }
     }
 }
@@ -0,0 +1,135 @@
+use crate::cartridge::nrom::Nrom;
+use crate::cartridge::{Cartridge, Mapper};
+use crate::nes::memory::Memory;
+use crate::ppu::vram::{NametableSource, Vram};
+use crate::rom::{CHR_ROM_SIZE, MirrorType, PRG_ROM_SIZE, RomFile};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Builds an NROM ROM with the given header flags 6.
+fn new_rom(flags_6: u8) -> RomFile {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01, flags_6];
+    rom.resize(16 + PRG_ROM_SIZE + CHR_ROM_SIZE, 0x00);
+    RomFile::new_from_buffer("test".to_string(), &rom).unwrap()
+}
+
+fn new_vram(flags_6: u8) -> Vram {
+    let cartridge = Cartridge::new(&new_rom(flags_6)).unwrap();
+    Vram::new(Rc::new(RefCell::new(cartridge)))
+}
+
+// Writes a distinct value to the start of each of the four nametables, then
+// reads back what each of them sees.
+fn nametable_layout(vram: &mut Vram) -> [u8; 4] {
+    for (index, address) in [0x2000, 0x2400, 0x2800, 0x2c00].iter().enumerate()
+    {
+        vram.store(*address, index as u8 + 1);
+    }
+    [
+        vram.read(0x2000),
+        vram.read(0x2400),
+        vram.read(0x2800),
+        vram.read(0x2c00),
+    ]
+}
+
+#[test]
+fn test_horizontal_mirroring() {
+    let mut vram = new_vram(0x00);
+    assert_eq!(nametable_layout(&mut vram), [2, 2, 4, 4]);
+
+    // Offsets are kept in the bottom-left quadrant.
+    vram.store(0x2bbf, 0x55);
+    assert_eq!(vram.read(0x2fbf), 0x55);
+    assert_eq!(vram.read(0x23bf), 0x00);
+    vram.store(0x2801, 0xaa);
+    assert_eq!(vram.read(0x2c01), 0xaa);
+    assert_eq!(vram.read(0x2001), 0x00);
+}
+
+#[test]
+fn test_vertical_mirroring() {
+    let mut vram = new_vram(0x01);
+    assert_eq!(nametable_layout(&mut vram), [3, 4, 3, 4]);
+
+    vram.store(0x2bbf, 0x55);
+    assert_eq!(vram.read(0x23bf), 0x55);
+    assert_eq!(vram.read(0x27bf), 0x00);
+}
+
+#[test]
+fn test_four_screen() {
+    let mut vram = new_vram(0x08);
+    assert_eq!(nametable_layout(&mut vram), [1, 2, 3, 4]);
+
+    vram.store(0x2fff, 0x55);
+    assert_eq!(vram.read(0x2fff), 0x55);
+    assert_eq!(vram.read(0x2bff), 0x00);
+    assert_eq!(vram.read(0x27ff), 0x00);
+}
+
+// A mapper that maps nametables to cartridge RAM in reverse order, and
+// switches the first nametable between CIRAM pages at runtime.
+struct TestMapper {
+    nrom: Nrom,
+    ciram_page: usize,
+}
+
+impl Mapper for TestMapper {
+    fn peek_prg(&self, address: u16) -> Option<u8> {
+        self.nrom.peek_prg(address)
+    }
+
+    fn store_prg(&mut self, address: u16, value: u8) -> u8 {
+        self.ciram_page = usize::from(value & 0x01);
+        self.nrom.store_prg(address, value)
+    }
+
+    fn fetch_chr(&self, address: u16) -> u8 {
+        self.nrom.fetch_chr(address)
+    }
+
+    fn store_chr(&mut self, address: u16, value: u8) -> u8 {
+        self.nrom.store_chr(address, value)
+    }
+
+    fn mirror_type(&self) -> MirrorType {
+        MirrorType::Horizontal
+    }
+
+    fn nametables(&self) -> [NametableSource; 4] {
+        [
+            NametableSource::Ciram(self.ciram_page),
+            NametableSource::Cartridge(2),
+            NametableSource::Cartridge(1),
+            NametableSource::Cartridge(0),
+        ]
+    }
+
+    fn nametable_ram_pages(&self) -> usize {
+        3
+    }
+}
+
+#[test]
+fn test_mapper_nametables() {
+    let mapper = TestMapper {
+        nrom: Nrom::new(&new_rom(0x00)),
+        ciram_page: 0,
+    };
+    let cartridge = Rc::new(RefCell::new(Cartridge::with_mapper(
+        Box::new(mapper),
+        MirrorType::Horizontal,
+    )));
+    let mut vram = Vram::new(cartridge.clone());
+    assert_eq!(nametable_layout(&mut vram), [1, 2, 3, 4]);
+    assert_eq!(cartridge.borrow().fetch_nametable(2, 0x0000), 2);
+    assert_eq!(cartridge.borrow().fetch_nametable(0, 0x0000), 4);
+
+    // Switching the first nametable to the other CIRAM page.
+    cartridge.borrow_mut().store(0x8000, 0x01);
+    assert_eq!(vram.read(0x2000), 0x00);
+    vram.store(0x2000, 0x55);
+    cartridge.borrow_mut().store(0x8000, 0x00);
+    assert_eq!(vram.read(0x2000), 1);
+}
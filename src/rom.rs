@@ -1,86 +1,335 @@
 #![allow(clippy::upper_case_acronyms)]
 
+use crate::patch::{self, PatchError};
+use crate::unif;
+use crate::utils::crc32::Crc32;
 use crate::utils::io::read_binary;
+use crate::utils::sha1::{DIGEST_SIZE, Sha1};
+use log::warn;
+use std::error;
 use std::fmt;
-use std::io::{Error, ErrorKind, Result};
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
 pub const TRAINER_SIZE: usize = 0x0200;
 pub const PRG_ROM_SIZE: usize = 0x4000;
 pub const CHR_ROM_SIZE: usize = 0x2000;
 pub const PRG_RAM_SIZE: usize = 0x2000;
 
-#[derive(Debug, Copy, Clone)]
+const HEADER_SIZE: usize = 0x10;
+
+// Reasons a ROM file can't be loaded.
+#[derive(Debug)]
+pub enum RomError {
+    // The file couldn't be read.
+    Io(io::Error),
+    // A patch couldn't be applied to the file.
+    Patch {
+        path: PathBuf,
+        error: PatchError,
+    },
+    // The file is too short to hold a header.
+    HeaderTruncated {
+        expected: usize,
+        found: usize,
+    },
+    // The file doesn't start with "NES" followed by an MS-DOS end-of-file.
+    MissingMagic,
+    // The header declares no PRG ROM, which every cartridge needs.
+    NoPrgRom,
+    // The file ends before all of the data declared in the header.
+    TrainerTruncated {
+        expected: usize,
+        found: usize,
+    },
+    PrgRomTruncated {
+        expected: usize,
+        found: usize,
+    },
+    ChrRomTruncated {
+        expected: usize,
+        found: usize,
+    },
+    // A chunk in a UNIF or NSFe file is longer than what's left of the file.
+    ChunkTruncated {
+        id: String,
+        expected: usize,
+        found: usize,
+    },
+    // A UNIF or NSFe file is missing a chunk that it needs.
+    MissingChunk(&'static str),
+    // A UNIF file's board doesn't have a known mapper number, so it can't be
+    // emulated.
+    UnknownBoard(String),
+    // An FDS image doesn't start with the "*NINTENDO-HVC*" disk header.
+    MissingDiskMagic,
+    // An FDS image is shorter than the disk sides it holds.
+    DiskTruncated {
+        expected: usize,
+        found: usize,
+    },
+    // The FDS BIOS isn't the size of the BIOS ROM.
+    BiosSize {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(error) => write!(f, "Unable to read ROM: {}", error),
+            RomError::Patch { path, error } => {
+                write!(f, "Unable to apply patch {}: {}", path.display(), error)
+            }
+            RomError::HeaderTruncated { expected, found } => write!(
+                f,
+                "Header truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            RomError::MissingMagic => {
+                write!(f, "Invalid iNES header: missing \"NES\" declaration")
+            }
+            RomError::NoPrgRom => {
+                write!(f, "Invalid iNES header: no PRG ROM")
+            }
+            RomError::TrainerTruncated { expected, found } => write!(
+                f,
+                "Trainer truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            RomError::PrgRomTruncated { expected, found } => write!(
+                f,
+                "PRG ROM truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            RomError::ChrRomTruncated { expected, found } => write!(
+                f,
+                "CHR ROM truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            RomError::ChunkTruncated {
+                id,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} chunk truncated: expected {} bytes, found {}",
+                id, expected, found
+            ),
+            RomError::MissingChunk(id) => {
+                write!(f, "File has no {} chunk", id)
+            }
+            RomError::UnknownBoard(board) => {
+                write!(
+                    f,
+                    "Unsupported UNIF board \"{}\": no known mapper",
+                    board
+                )
+            }
+            RomError::MissingDiskMagic => write!(
+                f,
+                "Invalid FDS image: missing \"*NINTENDO-HVC*\" declaration"
+            ),
+            RomError::DiskTruncated { expected, found } => write!(
+                f,
+                "Disk image truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            RomError::BiosSize { expected, found } => write!(
+                f,
+                "Wrong FDS BIOS size: expected {} bytes, found {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl error::Error for RomError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RomError::Io(error) => Some(error),
+            RomError::Patch { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(error: io::Error) -> RomError {
+        RomError::Io(error)
+    }
+}
+
+// Problems with a ROM file that don't stop it from loading.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RomWarning {
+    // There are bytes after the end of the data declared in the header.
+    TrailingData { size: usize },
+    // Bytes 11-15 of an iNES header aren't zero, usually because a ripping
+    // tool wrote its name there (like "DiskDude!"). Bytes 7-15 are ignored.
+    DirtyHeader,
+}
+
+impl fmt::Display for RomWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomWarning::TrailingData { size } => {
+                write!(f, "{} bytes of trailing data after ROM", size)
+            }
+            RomWarning::DirtyHeader => write!(
+                f,
+                "Header bytes 11-15 are not zero, ignoring bytes 7-15"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum MirrorType {
     Horizontal,
     Vertical,
     Both,
+    // All four nametables show the first page of VRAM.
+    SingleScreenLower,
+    // All four nametables show the second page of VRAM.
+    SingleScreenUpper,
 }
 
-#[derive(Debug)]
+// The CPU/PPU timing the game was made for.
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TVSystem {
     NTSC,
     PAL,
+    // Works on both NTSC and PAL consoles.
+    Multiple,
+    Dendy,
 }
 
-#[derive(Debug)]
-pub enum Mapper {
-    NROM,
+// Hardware details for Vs. System games, from NES 2.0 byte 13.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VsSystem {
+    // Which PPU the arcade board uses, since most of them have their own
+    // palettes (0 = RP2C03B, 1 = RP2C03G, 2-5 = RP2C04-0001 to -0004, ...).
+    pub ppu_type: u8,
+    // How the board is wired (0 = Vs. Unisystem, 1 = Vs. Unisystem with RBI
+    // Baseball protection, ..., 6 = Vs. Dual System with Raid on Bungeling
+    // Bay protection).
+    pub hardware_type: u8,
 }
 
 pub struct RomFile {
     // From the name of the iNES file.
     pub game_name: String,
 
+    // Whether the header is in NES 2.0 format, rather than plain iNES.
+    pub nes_20: bool,
+
     // Possible 512 bytes of trainer data.
     pub trainer_data: Option<[u8; TRAINER_SIZE]>,
 
-    // PRG ROM in 16 KB units.
+    // PRG ROM in 16 KB units. NES 2.0 headers can give sizes that aren't a
+    // multiple of 16 KB, in which case the last unit is padded with zeroes.
     pub prg_rom_data: Vec<[u8; PRG_ROM_SIZE]>,
 
     // CHR ROM in 8 KB units (0 units means the board uses CHR RAM).
     pub chr_rom_data: Vec<[u8; CHR_ROM_SIZE]>,
 
-    // Size of PRG RAM in 8 KB units.
+    // Size of volatile PRG RAM in bytes.
     pub prg_ram_size: usize,
 
+    // Size of battery-backed PRG RAM in bytes.
+    pub prg_nvram_size: usize,
+
+    // Size of volatile CHR RAM in bytes. Plain iNES headers leave this as 0,
+    // and boards without CHR ROM get a default amount.
+    pub chr_ram_size: usize,
+
+    // Size of battery-backed CHR RAM in bytes.
+    pub chr_nvram_size: usize,
+
+    // Whether the cartridge has a battery, or other non-volatile memory.
+    pub battery: bool,
+
     pub mirror_type: MirrorType,
 
     pub tv_system: TVSystem,
 
     pub vs_cart: bool,
 
+    // Only known for NES 2.0 headers.
+    pub vs_system: Option<VsSystem>,
+
     pub play_choice: bool,
 
-    pub mapper: u8,
+    // 12 bits for NES 2.0 headers, 8 bits for iNES.
+    pub mapper: u16,
+
+    // Picks between variants of a mapper. 0 if unknown.
+    pub submapper: u8,
+
+    // The controller or other input device the game expects, as numbered on
+    // http://wiki.nesdev.com/w/index.php/NES_2.0#Default_Expansion_Device.
+    // 0 if unknown.
+    pub expansion_device: u8,
+
+    // CRC-32 and SHA-1 of the PRG ROM followed by the CHR ROM, which is how
+    // databases like NesCartDB identify dumps.
+    pub crc32: u32,
+    pub sha1: [u8; DIGEST_SIZE],
+
+    // Anything odd noticed while loading the file.
+    pub warnings: Vec<RomWarning>,
 }
 
-// TODO: finish this up.
 impl fmt::Debug for RomFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(
             f,
             "\
 Game name        : {:?}
+Header format    : {}
 Has trainer data : {:?}
 PRG ROM size     : {:?} bytes
 CHR ROM size     : {:?} bytes
 PRG RAM size     : {:?} bytes
+PRG NVRAM size   : {:?} bytes
+CHR RAM size     : {:?} bytes
+CHR NVRAM size   : {:?} bytes
+Battery          : {:?}
 Mirror Type      : {:?}
 TV System        : {:?}
 VS Unisystem     : {:?}
+VS System type   : {:?}
 PlayChoice-10    : {:?}
-Mapper #         : {:?}",
+Mapper #         : {:?}
+Submapper #      : {:?}
+Expansion device : {:?}
+CRC-32           : {:08X}
+SHA-1            : {}",
             self.game_name,
+            if self.nes_20 { "NES 2.0" } else { "iNES" },
             self.trainer_data.is_some(),
             self.prg_rom_data.len() * PRG_ROM_SIZE,
             self.chr_rom_data.len() * CHR_ROM_SIZE,
-            self.prg_ram_size * PRG_RAM_SIZE,
+            self.prg_ram_size,
+            self.prg_nvram_size,
+            self.chr_ram_size,
+            self.chr_nvram_size,
+            self.battery,
             self.mirror_type,
             self.tv_system,
             self.vs_cart,
+            self.vs_system,
             self.play_choice,
-            self.mapper
+            self.mapper,
+            self.submapper,
+            self.expansion_device,
+            self.crc32,
+            self.sha1
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<String>()
         )
     }
 }
@@ -92,8 +341,20 @@ impl fmt::Display for RomFile {
 }
 
 impl RomFile {
-    pub fn new(file_name: &str) -> Result<RomFile> {
-        let bytes = read_binary(file_name)?;
+    // Loads a ROM file, applying each of "patches" to it in order.
+    pub fn new(
+        file_name: &str,
+        patches: &[PathBuf],
+    ) -> Result<RomFile, RomError> {
+        let mut bytes = read_binary(file_name)?;
+        for path in patches {
+            bytes = patch::apply_file(path, &bytes).map_err(|error| {
+                RomError::Patch {
+                    path: path.clone(),
+                    error,
+                }
+            })?;
+        }
         let game_name = Path::new(&file_name)
             .file_stem()
             .unwrap()
@@ -104,28 +365,45 @@ impl RomFile {
         RomFile::new_from_buffer(game_name, &bytes)
     }
 
-    pub fn new_from_buffer(game_name: String, rom: &[u8]) -> Result<RomFile> {
+    pub fn new_from_buffer(
+        game_name: String,
+        rom: &[u8],
+    ) -> Result<RomFile, RomError> {
+        if rom.starts_with(unif::MAGIC) {
+            return unif::parse(game_name, rom);
+        }
+
         // File must have enough space to be a valid header.
-        if rom.len() < 16 {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Invalid iNES header: file less than 16 bytes.",
-            ));
+        if rom.len() < HEADER_SIZE {
+            return Err(RomError::HeaderTruncated {
+                expected: HEADER_SIZE,
+                found: rom.len(),
+            });
         }
 
         // Bytes 0-3: Check "NES" declaration.
         if &rom[0..3] != b"NES" || rom[3] != 0x1a {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Invalid iNES header: missing \"NES\" declaration.",
-            ));
+            return Err(RomError::MissingMagic);
+        }
+
+        // Bytes 11-15 are always 0 in an iNES header. If they aren't, bytes
+        // 7-15 are most likely junk too, and the mapper number would come out
+        // wrong.
+        let mut header = [0x00; HEADER_SIZE];
+        header.copy_from_slice(&rom[..HEADER_SIZE]);
+        let mut warnings = Vec::new();
+        let nes_20 = (header[7] & 0x0c) >> 2 == 0x02;
+        if !nes_20 && header[11..].iter().any(|&byte| byte != 0x00) {
+            header[7..].fill(0x00);
+            warnings.push(RomWarning::DirtyHeader);
         }
 
-        // Byte 4: PRG ROM size.
-        let prg_rom_size = rom[4];
+        // Byte 4: PRG ROM size in 16 KB units (low byte for NES 2.0).
+        let prg_rom_size = header[4];
 
-        // Byte 5: CHR ROM size. 0 implies that the cartridge uses CHR RAM.
-        let chr_rom_size = rom[5];
+        // Byte 5: CHR ROM size in 8 KB units (low byte for NES 2.0). 0
+        // means the cartridge uses CHR RAM.
+        let chr_rom_size = header[5];
 
         // Byte 6 flags:
         // 0: Mirroring (0 = horizontal, 1 = vertical)
@@ -133,31 +411,126 @@ impl RomFile {
         // 2: 512 trainer at $7000 - $71ff
         // 3: 4-screen VRAM (ignore above mirroring bit)
         // 4-7: lower nybble of mapper number
-        let flags_6 = rom[6];
+        let flags_6 = header[6];
         let mirror_type = match flags_6 & 0x09 {
             0x08 | 0x09 => MirrorType::Both,
             0x01 => MirrorType::Vertical,
             _ => MirrorType::Horizontal,
         };
-        #[allow(unused_variables)]
-        let has_prg_ram = flags_6 & 0x02 == 0x02;
+        let battery = flags_6 & 0x02 == 0x02;
         let has_trainer = flags_6 & 0x04 == 0x04;
-        let mapper_lower: u8 = (flags_6 & 0xf0) >> 4;
+        let mapper_lower = u16::from(flags_6 >> 4);
 
         // Byte 7 flags:
         // 0: System is VS Unisystem
         // 1: System is PlayChoice-10 (8KB of Hint Screen data after CHR data)
         // 2-3: If equal to 2, flags 8-15 are in NES 2.0 format
         // 4-7: Upper nybble of mapper number
-        let flags_7 = rom[7];
-        let vs_cart = flags_7 & 0x01 == 0x01;
-        let play_choice = flags_7 & 0x02 == 0x02;
-        #[allow(unused_variables)]
-        let nes_20 = (flags_7 & 0x0c) >> 2 == 0x02;
-        let mapper_upper: u8 = flags_7 & 0xf0;
-
-        // Byte 8: PRG RAM size (0 implies 1 8KB bank).
-        let prg_ram_size = match rom[8] {
+        let flags_7 = header[7];
+        let mapper_upper = u16::from(flags_7 & 0xf0);
+
+        let mut rom_file = if nes_20 {
+            RomFile::new_nes_20(game_name, &header)
+        } else {
+            RomFile::new_ines(game_name, &header)
+        };
+        rom_file.mirror_type = mirror_type;
+        rom_file.battery = battery;
+        rom_file.mapper |= mapper_upper | mapper_lower;
+
+        let (prg_rom_bytes, chr_rom_bytes) = if nes_20 {
+            (
+                RomFile::nes_20_rom_size(
+                    prg_rom_size,
+                    header[9] & 0x0f,
+                    PRG_ROM_SIZE,
+                ),
+                RomFile::nes_20_rom_size(
+                    chr_rom_size,
+                    header[9] >> 4,
+                    CHR_ROM_SIZE,
+                ),
+            )
+        } else {
+            (
+                prg_rom_size as usize * PRG_ROM_SIZE,
+                chr_rom_size as usize * CHR_ROM_SIZE,
+            )
+        };
+
+        if prg_rom_bytes == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        // Copy data from buffer into data object.
+        let mut data = &rom[HEADER_SIZE..];
+
+        // Load trainer data.
+        if has_trainer {
+            let trainer = take(&mut data, TRAINER_SIZE).map_err(|found| {
+                RomError::TrainerTruncated {
+                    expected: TRAINER_SIZE,
+                    found,
+                }
+            })?;
+            let mut trainer_data = [0x00; TRAINER_SIZE];
+            trainer_data.copy_from_slice(trainer);
+            rom_file.trainer_data = Some(trainer_data);
+        }
+
+        // Load PRG ROM data.
+        let prg_rom = take(&mut data, prg_rom_bytes).map_err(|found| {
+            RomError::PrgRomTruncated {
+                expected: prg_rom_bytes,
+                found,
+            }
+        })?;
+
+        // Load CHR ROM data.
+        let chr_rom = take(&mut data, chr_rom_bytes).map_err(|found| {
+            RomError::ChrRomTruncated {
+                expected: chr_rom_bytes,
+                found,
+            }
+        })?;
+        rom_file.set_rom_data(prg_rom, chr_rom);
+
+        // Anything else could be PlayChoice-10 hint screen data, or junk.
+        if !data.is_empty() && !rom_file.play_choice {
+            warnings.push(RomWarning::TrailingData { size: data.len() });
+        }
+
+        for warning in warnings.iter() {
+            warn!("{}: {}", rom_file.game_name, warning);
+        }
+        rom_file.warnings = warnings;
+
+        Ok(rom_file)
+    }
+
+    // Stores the PRG and CHR ROM, and their hashes.
+    pub fn set_rom_data(&mut self, prg_rom: &[u8], chr_rom: &[u8]) {
+        self.prg_rom_data = read_units(prg_rom);
+        self.chr_rom_data = read_units(chr_rom);
+
+        let mut crc = Crc32::new();
+        crc.update(prg_rom);
+        crc.update(chr_rom);
+        self.crc32 = crc.finish();
+
+        let mut sha1 = Sha1::new();
+        sha1.update(prg_rom);
+        sha1.update(chr_rom);
+        self.sha1 = sha1.finish();
+    }
+
+    // Reads bytes 7-15 of a plain iNES header. Flags 6 and the mapper number
+    // are filled in by the caller.
+    fn new_ines(game_name: String, header: &[u8; HEADER_SIZE]) -> RomFile {
+        let flags_7 = header[7];
+
+        // Byte 8: PRG RAM size in 8 KB units (0 implies 1 8KB bank).
+        let prg_ram_size = match header[8] {
             0x00 => 0x01,
             size => size,
         };
@@ -165,52 +538,141 @@ impl RomFile {
         // Byte 9 flags:
         // 0: TV system (0 = NTSC, 1 = PAL),
         // 1-7: Reserved, set to 0.
-        let flags_9 = rom[9];
+        let flags_9 = header[9];
         let tv_system = match flags_9 & 0x01 {
             0x00 => TVSystem::NTSC,
             _ => TVSystem::PAL,
         };
 
-        let mut rom_file = RomFile {
+        RomFile {
             game_name,
+            nes_20: false,
             trainer_data: None,
             prg_rom_data: Vec::new(),
             chr_rom_data: Vec::new(),
-            prg_ram_size: prg_ram_size as usize,
-            mirror_type,
+            prg_ram_size: prg_ram_size as usize * PRG_RAM_SIZE,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            battery: false,
+            mirror_type: MirrorType::Horizontal,
             tv_system,
-            vs_cart,
-            play_choice,
-            mapper: mapper_upper | mapper_lower,
+            vs_cart: flags_7 & 0x01 == 0x01,
+            vs_system: None,
+            play_choice: flags_7 & 0x02 == 0x02,
+            mapper: 0,
+            submapper: 0,
+            expansion_device: 0,
+            crc32: 0,
+            sha1: [0x00; DIGEST_SIZE],
+            warnings: Vec::new(),
+        }
+    }
+
+    // Reads bytes 7-15 of an NES 2.0 header. Flags 6 and the lower 8 bits of
+    // the mapper number are filled in by the caller.
+    //
+    // See http://wiki.nesdev.com/w/index.php/NES_2.0 for more details.
+    fn new_nes_20(game_name: String, header: &[u8; HEADER_SIZE]) -> RomFile {
+        // Byte 7, bits 0-1: Console type (0 = NES, 1 = Vs. System,
+        // 2 = PlayChoice-10, 3 = extended console type in byte 13).
+        let console_type = header[7] & 0x03;
+
+        // Byte 8:
+        // 0-3: Bits 8-11 of mapper number
+        // 4-7: Submapper number
+        let mapper = u16::from(header[8] & 0x0f) << 8;
+        let submapper = header[8] >> 4;
+
+        // Byte 10: PRG RAM (0-3) and PRG NVRAM (4-7) sizes, as shift counts.
+        // Byte 11: CHR RAM (0-3) and CHR NVRAM (4-7) sizes, as shift counts.
+        let ram_size = |shift: u8| match shift {
+            0 => 0,
+            shift => 64 << shift,
         };
 
-        // Copy data from buffer into data object.
-        let mut cursor = 0x10;
+        // Byte 12, bits 0-1: CPU/PPU timing.
+        let tv_system = match header[12] & 0x03 {
+            0x00 => TVSystem::NTSC,
+            0x01 => TVSystem::PAL,
+            0x02 => TVSystem::Multiple,
+            _ => TVSystem::Dendy,
+        };
 
-        // Load trainer data.
-        if has_trainer {
-            let mut trainer_data = [0x00; TRAINER_SIZE];
-            trainer_data.copy_from_slice(&rom[cursor..(cursor + TRAINER_SIZE)]);
-            rom_file.trainer_data = Some(trainer_data);
-            cursor += TRAINER_SIZE;
-        }
+        // Byte 13: Vs. System PPU type (0-3) and hardware type (4-7).
+        let vs_system = if console_type == 0x01 {
+            Some(VsSystem {
+                ppu_type: header[13] & 0x0f,
+                hardware_type: header[13] >> 4,
+            })
+        } else {
+            None
+        };
 
-        // Load PRG ROM data.
-        for _ in 0..prg_rom_size {
-            let mut prg_rom = [0x00; PRG_ROM_SIZE];
-            prg_rom.copy_from_slice(&rom[cursor..(cursor + PRG_ROM_SIZE)]);
-            rom_file.prg_rom_data.push(prg_rom);
-            cursor += PRG_ROM_SIZE;
+        // Byte 15, bits 0-5: Default expansion device.
+        let expansion_device = header[15] & 0x3f;
+
+        RomFile {
+            game_name,
+            nes_20: true,
+            trainer_data: None,
+            prg_rom_data: Vec::new(),
+            chr_rom_data: Vec::new(),
+            prg_ram_size: ram_size(header[10] & 0x0f),
+            prg_nvram_size: ram_size(header[10] >> 4),
+            chr_ram_size: ram_size(header[11] & 0x0f),
+            chr_nvram_size: ram_size(header[11] >> 4),
+            battery: false,
+            mirror_type: MirrorType::Horizontal,
+            tv_system,
+            vs_cart: console_type == 0x01,
+            vs_system,
+            play_choice: console_type == 0x02,
+            mapper,
+            submapper,
+            expansion_device,
+            crc32: 0,
+            sha1: [0x00; DIGEST_SIZE],
+            warnings: Vec::new(),
         }
+    }
 
-        // Load CHR ROM data.
-        for _ in 0..chr_rom_size {
-            let mut chr_rom = [0x00; CHR_ROM_SIZE];
-            chr_rom.copy_from_slice(&rom[cursor..(cursor + CHR_ROM_SIZE)]);
-            rom_file.chr_rom_data.push(chr_rom);
-            cursor += CHR_ROM_SIZE;
+    // Gets the size in bytes of PRG or CHR ROM from an NES 2.0 header. "lsb"
+    // is byte 4 or 5 of the header, "msb" is the matching nybble of byte 9,
+    // and "unit" is the size of a bank.
+    fn nes_20_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+        if msb == 0x0f {
+            // Exponent-multiplier notation: 2^E * (MM * 2 + 1).
+            let exponent = u32::from(lsb >> 2);
+            let multiplier = usize::from(lsb & 0x03) * 2 + 1;
+            1usize
+                .checked_shl(exponent)
+                .and_then(|size| size.checked_mul(multiplier))
+                .unwrap_or(usize::MAX)
+        } else {
+            ((usize::from(msb) << 8) | usize::from(lsb)) * unit
         }
+    }
+}
 
-        Ok(rom_file)
+// Takes the first "size" bytes off the front of "data". If there aren't enough,
+// returns how many there are instead.
+fn take<'a>(data: &mut &'a [u8], size: usize) -> Result<&'a [u8], usize> {
+    if data.len() < size {
+        return Err(data.len());
     }
+    let (taken, rest) = data.split_at(size);
+    *data = rest;
+    Ok(taken)
+}
+
+// Splits ROM data into fixed-size units, padding the last one with zeroes.
+fn read_units<const N: usize>(data: &[u8]) -> Vec<[u8; N]> {
+    data.chunks(N)
+        .map(|chunk| {
+            let mut unit = [0x00; N];
+            unit[..chunk.len()].copy_from_slice(chunk);
+            unit
+        })
+        .collect()
 }
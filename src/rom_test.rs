@@ -0,0 +1,249 @@
+use crate::rom::{
+    CHR_ROM_SIZE, MirrorType, PRG_ROM_SIZE, RomError, RomFile, RomWarning,
+    TVSystem, VsSystem,
+};
+use crate::utils::crc32::Crc32;
+use crate::utils::sha1::Sha1;
+
+// Formats a digest as lowercase hex, the way they're usually written.
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Builds a ROM from a 16 byte header, followed by "data_size" bytes of PRG and
+// CHR data. Each byte of data is its index divided by 1 KB.
+fn new_rom(header: [u8; 16], data_size: usize) -> RomFile {
+    let mut rom = header.to_vec();
+    rom.extend((0..data_size).map(|index| (index / 0x0400) as u8));
+    RomFile::new_from_buffer("test".to_string(), &rom).unwrap()
+}
+
+#[test]
+fn test_ines_header() {
+    let rom = new_rom(
+        [
+            0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x13, 0x41, 0x02, 0x01, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+        2 * PRG_ROM_SIZE + CHR_ROM_SIZE,
+    );
+    assert!(!rom.nes_20);
+    assert_eq!(rom.mapper, 0x41);
+    assert_eq!(rom.submapper, 0);
+    assert_eq!(rom.mirror_type, MirrorType::Vertical);
+    assert!(rom.battery);
+    assert_eq!(rom.prg_rom_data.len(), 2);
+    assert_eq!(rom.prg_rom_data[1][0], 16);
+    assert_eq!(rom.chr_rom_data.len(), 1);
+    assert_eq!(rom.chr_rom_data[0][0], 32);
+    assert_eq!(rom.prg_ram_size, 0x4000);
+    assert_eq!(rom.chr_ram_size, 0);
+    assert_eq!(rom.tv_system, TVSystem::PAL);
+    assert!(rom.vs_cart);
+    assert!(rom.vs_system.is_none());
+    assert!(!rom.play_choice);
+}
+
+#[test]
+fn test_nes_20_header() {
+    let rom = new_rom(
+        [
+            0x4e, 0x45, 0x53, 0x1a, 0x02, 0x00, 0x42, 0x19, 0x31, 0x00, 0x70,
+            0x07, 0x03, 0x25, 0x00, 0x05,
+        ],
+        2 * PRG_ROM_SIZE,
+    );
+    assert!(rom.nes_20);
+    assert_eq!(rom.mapper, 0x114);
+    assert_eq!(rom.submapper, 3);
+    assert!(rom.battery);
+    assert_eq!(rom.prg_rom_data.len(), 2);
+    assert!(rom.chr_rom_data.is_empty());
+    assert_eq!(rom.prg_ram_size, 0);
+    assert_eq!(rom.prg_nvram_size, 0x2000);
+    assert_eq!(rom.chr_ram_size, 0x2000);
+    assert_eq!(rom.chr_nvram_size, 0);
+    assert_eq!(rom.tv_system, TVSystem::Dendy);
+    assert!(rom.vs_cart);
+    assert_eq!(
+        rom.vs_system,
+        Some(VsSystem {
+            ppu_type: 5,
+            hardware_type: 2,
+        })
+    );
+    assert!(!rom.play_choice);
+    assert_eq!(rom.expansion_device, 5);
+}
+
+#[test]
+fn test_nes_20_rom_sizes() {
+    // 12 bit bank counts.
+    let rom = new_rom(
+        [
+            0x4e, 0x45, 0x53, 0x1a, 0x00, 0x00, 0x00, 0x08, 0x00, 0x11, 0x00,
+            0x00, 0x02, 0x00, 0x00, 0x00,
+        ],
+        256 * PRG_ROM_SIZE + 256 * CHR_ROM_SIZE,
+    );
+    assert_eq!(rom.prg_rom_data.len(), 256);
+    assert_eq!(rom.chr_rom_data.len(), 256);
+    assert_eq!(rom.tv_system, TVSystem::Multiple);
+
+    // Exponent-multiplier notation: 2^13 * 3 bytes of PRG ROM, which pads
+    // out the second 16 KB unit.
+    let rom = new_rom(
+        [
+            0x4e, 0x45, 0x53, 0x1a, 0x35, 0x01, 0x00, 0x08, 0x00, 0x0f, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+        0x6000 + CHR_ROM_SIZE,
+    );
+    assert_eq!(rom.prg_rom_data.len(), 2);
+    assert_eq!(rom.prg_rom_data[1][0x1fff], 23);
+    assert_eq!(rom.prg_rom_data[1][0x2000], 0);
+    assert_eq!(rom.chr_rom_data.len(), 1);
+    assert_eq!(rom.chr_rom_data[0][0], 24);
+}
+
+#[test]
+fn test_invalid_header() {
+    let error = RomFile::new_from_buffer("test".to_string(), b"NES\x1a")
+        .err()
+        .unwrap();
+    assert!(matches!(error, RomError::HeaderTruncated { found: 4, .. }));
+    assert_eq!(
+        error.to_string(),
+        "Header truncated: expected 16 bytes, found 4"
+    );
+
+    let error = RomFile::new_from_buffer("test".to_string(), &[0x00; 16])
+        .err()
+        .unwrap();
+    assert!(matches!(error, RomError::MissingMagic));
+
+    // Every cartridge needs some PRG ROM, in either header format.
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x00, 0x01];
+    rom.resize(16 + CHR_ROM_SIZE, 0x00);
+    let error = RomFile::new_from_buffer("test".to_string(), &rom)
+        .err()
+        .unwrap();
+    assert!(matches!(error, RomError::NoPrgRom));
+    assert_eq!(error.to_string(), "Invalid iNES header: no PRG ROM");
+
+    rom[7] = 0x08;
+    let error = RomFile::new_from_buffer("test".to_string(), &rom)
+        .err()
+        .unwrap();
+    assert!(matches!(error, RomError::NoPrgRom));
+}
+
+#[test]
+fn test_truncated_rom() {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x04];
+    rom.resize(16 + 0x0100, 0x00);
+    let error = RomFile::new_from_buffer("test".to_string(), &rom)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Trainer truncated: expected 512 bytes, found 256"
+    );
+
+    rom[6] = 0x00;
+    rom.resize(16 + PRG_ROM_SIZE + 0x1000, 0x00);
+    let error = RomFile::new_from_buffer("test".to_string(), &rom)
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        RomError::PrgRomTruncated {
+            expected: 0x8000,
+            found: 0x5000
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "PRG ROM truncated: expected 32768 bytes, found 20480"
+    );
+
+    rom.resize(16 + 2 * PRG_ROM_SIZE + 0x1000, 0x00);
+    let error = RomFile::new_from_buffer("test".to_string(), &rom)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "CHR ROM truncated: expected 8192 bytes, found 4096"
+    );
+}
+
+#[test]
+fn test_rom_warnings() {
+    let mut header = [0x00; 16];
+    header[..6].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01]);
+    header[6] = 0x10;
+    header[7..].copy_from_slice(b"DiskDude!");
+    let rom = new_rom(header, PRG_ROM_SIZE + CHR_ROM_SIZE + 0x80);
+
+    // The "D" in byte 7 would otherwise make this mapper 65.
+    assert_eq!(rom.mapper, 1);
+    assert_eq!(
+        rom.warnings,
+        vec![
+            RomWarning::DirtyHeader,
+            RomWarning::TrailingData { size: 0x80 }
+        ]
+    );
+}
+
+#[test]
+fn test_crc32() {
+    let mut crc = Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), 0xcbf4_3926);
+
+    let rom = new_rom(
+        [
+            0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+        PRG_ROM_SIZE,
+    );
+    let mut crc = Crc32::new();
+    crc.update(&rom.prg_rom_data[0]);
+    assert_eq!(rom.crc32, crc.finish());
+}
+
+#[test]
+fn test_sha1() {
+    assert_eq!(
+        hex(&Sha1::new().finish()),
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+    );
+
+    let mut sha1 = Sha1::new();
+    sha1.update(b"a");
+    sha1.update(b"bc");
+    assert_eq!(
+        hex(&sha1.finish()),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+
+    // Padding that spills over into another block.
+    let mut sha1 = Sha1::new();
+    sha1.update(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+    assert_eq!(
+        hex(&sha1.finish()),
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+    );
+
+    let mut sha1 = Sha1::new();
+    for _ in 0..1000 {
+        sha1.update(&[b'a'; 1000]);
+    }
+    assert_eq!(
+        hex(&sha1.finish()),
+        "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+    );
+}
@@ -0,0 +1,267 @@
+// UNIF ("Universal NES Image Format") files, which some multicart and pirate
+// dumps only exist as. Instead of a header with a mapper number, they have a
+// list of chunks, one of which names the circuit board.
+//
+// See http://wiki.nesdev.com/w/index.php/UNIF for more details.
+use crate::rom::{MirrorType, PRG_RAM_SIZE, RomError, RomFile, TVSystem};
+use crate::utils::sha1::DIGEST_SIZE;
+
+pub const MAGIC: &[u8] = b"UNIF";
+
+// 4 bytes of magic, a 4 byte revision number, and 24 reserved bytes.
+const HEADER_SIZE: usize = 0x20;
+
+// Each chunk starts with a 4 byte ID and 4 byte length.
+const CHUNK_HEADER_SIZE: usize = 8;
+
+// Mapper and submapper numbers for UNIF board names, without their "NES-",
+// "HVC-", "UNL-" or similar prefix.
+const BOARDS: &[(&str, u16, u8)] = &[
+    ("NROM", 0, 0),
+    ("NROM-128", 0, 0),
+    ("NROM-256", 0, 0),
+    ("RROM", 0, 0),
+    ("RROM-128", 0, 0),
+    ("SAROM", 1, 0),
+    ("SBROM", 1, 0),
+    ("SCROM", 1, 0),
+    ("SEROM", 1, 0),
+    ("SGROM", 1, 0),
+    ("SKROM", 1, 0),
+    ("SL1ROM", 1, 0),
+    ("SLROM", 1, 0),
+    ("SNROM", 1, 0),
+    ("SOROM", 1, 0),
+    ("SUROM", 1, 0),
+    ("SXROM", 1, 0),
+    ("UNROM", 2, 0),
+    ("UOROM", 2, 0),
+    ("CNROM", 3, 0),
+    ("HKROM", 4, 0),
+    ("TBROM", 4, 0),
+    ("TEROM", 4, 0),
+    ("TFROM", 4, 0),
+    ("TGROM", 4, 0),
+    ("TKROM", 4, 0),
+    ("TL1ROM", 4, 0),
+    ("TLROM", 4, 0),
+    ("TR1ROM", 4, 0),
+    ("TSROM", 4, 0),
+    ("TVROM", 4, 0),
+    ("AMROM", 7, 2),
+    ("AN1ROM", 7, 1),
+    ("ANROM", 7, 1),
+    ("AOROM", 7, 0),
+    ("BNROM", 34, 2),
+    ("NINA-001", 34, 1),
+    ("NINA-01", 34, 1),
+    ("GNROM", 66, 0),
+    ("MHROM", 66, 0),
+    // Multicart ("BMC-") and unlicensed ("UNL-") boards. Most of these don't
+    // have a mapper here yet, but knowing the number lets the cartridge say
+    // which one is missing.
+    ("SL1632", 14, 0),
+    ("CC-21", 27, 0),
+    ("SUPERHIK8IN1", 45, 0),
+    ("SUPERVISION16IN1", 53, 0),
+    ("GK-192", 58, 0),
+    ("D1038", 59, 0),
+    ("TEK90", 90, 0),
+    ("H2288", 123, 0),
+    ("22211", 132, 0),
+    ("SA-72008", 133, 0),
+    ("SACHEN-8259D", 137, 0),
+    ("SACHEN-8259B", 138, 0),
+    ("SACHEN-8259C", 139, 0),
+    ("SACHEN-8259A", 141, 0),
+    ("KS7032", 142, 0),
+    ("SA-NROM", 143, 0),
+    ("SA-72007", 145, 0),
+    ("SA-016-1M", 146, 0),
+    ("TC-U01-1.5M", 147, 0),
+    ("SA-0037", 148, 0),
+    ("SA-0036", 149, 0),
+    ("SACHEN-74LS374N", 150, 0),
+    ("FS304", 162, 0),
+    ("FK23C", 176, 0),
+    ("SUPER24IN1SC03", 176, 0),
+    ("8237", 215, 0),
+    ("N625092", 221, 0),
+    ("GHOSTBUSTERS63IN1", 226, 0),
+    ("42IN1RESETSWITCH", 233, 0),
+    ("70IN1", 236, 0),
+    ("603-5052", 238, 0),
+    ("SACHEN-74LS374NA", 243, 0),
+    ("ONEBUS", 256, 0),
+    ("158B", 258, 0),
+    ("F-15", 259, 0),
+    ("HPXX", 260, 0),
+    ("810544-C-A1", 261, 0),
+    ("SHERO", 262, 0),
+    ("KOF97", 263, 0),
+    ("YOKO", 264, 0),
+    ("T-262", 265, 0),
+    ("CITYFIGHT", 266, 0),
+    ("COOLBOY", 268, 0),
+    ("MINDKIDS", 268, 1),
+    ("80013-B", 274, 0),
+    ("GS-2004", 283, 0),
+    ("GS-2013", 283, 0),
+    ("A65AS", 285, 0),
+    ("BS-5", 286, 0),
+    ("411120-C", 287, 0),
+    ("NTD-03", 290, 0),
+    ("DRAGONFIGHTER", 292, 0),
+    ("13IN1JY110", 295, 0),
+    ("TF1201", 298, 0),
+    ("11160", 299, 0),
+    ("190IN1", 300, 0),
+    ("8157", 301, 0),
+    ("SMB2J", 304, 0),
+    ("64IN1NOREPEAT", 314, 0),
+    ("HP898F", 319, 0),
+    ("MALISB", 325, 0),
+    ("EDU2000", 329, 0),
+    ("12-IN-1", 331, 0),
+    ("WS", 332, 0),
+    ("8-IN-1", 333, 0),
+    ("SA-9602B", 513, 0),
+    ("DANCE2000", 518, 0),
+    ("EH8813A", 519, 0),
+    ("DREAMTECH01", 521, 0),
+    ("T-230", 529, 0),
+    ("AX5705", 530, 0),
+];
+
+// Gets the mapper and submapper numbers for a board name.
+fn lookup_board(board: &str) -> Option<(u16, u8)> {
+    let name = match board.split_once('-') {
+        Some((prefix, name)) if prefix.len() == 3 => name,
+        _ => board,
+    };
+    BOARDS
+        .iter()
+        .find(|(known, _, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, mapper, submapper)| (*mapper, *submapper))
+}
+
+// Reads a chunk's data as a NUL-terminated string.
+fn chunk_string(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+// Builds a ROM from the chunks of a UNIF file.
+pub fn parse(game_name: String, file: &[u8]) -> Result<RomFile, RomError> {
+    if file.len() < HEADER_SIZE {
+        return Err(RomError::HeaderTruncated {
+            expected: HEADER_SIZE,
+            found: file.len(),
+        });
+    }
+
+    // PRG and CHR ROM are split into up to 16 chunks each, numbered in hex,
+    // that are put together in order.
+    let mut prg_chunks: [&[u8]; 16] = [&[]; 16];
+    let mut chr_chunks: [&[u8]; 16] = [&[]; 16];
+    let mut board = None;
+    let mut mirror_type = MirrorType::Horizontal;
+    let mut battery = false;
+    let mut tv_system = TVSystem::NTSC;
+
+    let mut data = &file[HEADER_SIZE..];
+    while !data.is_empty() {
+        let id = &data[..data.len().min(4)];
+        let id_string = || String::from_utf8_lossy(id).to_string();
+        if data.len() < CHUNK_HEADER_SIZE {
+            return Err(RomError::ChunkTruncated {
+                id: id_string(),
+                expected: CHUNK_HEADER_SIZE,
+                found: data.len(),
+            });
+        }
+        let length =
+            u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        data = &data[CHUNK_HEADER_SIZE..];
+        if data.len() < length {
+            return Err(RomError::ChunkTruncated {
+                id: id_string(),
+                expected: length,
+                found: data.len(),
+            });
+        }
+        let (chunk, rest) = data.split_at(length);
+        data = rest;
+
+        let number = char::from(id[3]).to_digit(16).map(|digit| digit as usize);
+        match id {
+            b"MAPR" => board = Some(chunk_string(chunk)),
+            // 0 = horizontal, 1 = vertical, 2 = all nametable A, 3 = all
+            // nametable B, 4 = four-screen, 5 = controlled by the mapper.
+            b"MIRR" => {
+                mirror_type = match chunk.first() {
+                    Some(0x01) => MirrorType::Vertical,
+                    Some(0x02) => MirrorType::SingleScreenLower,
+                    Some(0x03) => MirrorType::SingleScreenUpper,
+                    Some(0x04) => MirrorType::Both,
+                    _ => MirrorType::Horizontal,
+                }
+            }
+            b"BATR" => battery = chunk.first().is_some_and(|&byte| byte != 0),
+            // 0 = NTSC, 1 = PAL, 2 = either.
+            b"TVCI" => {
+                tv_system = match chunk.first() {
+                    Some(0x01) => TVSystem::PAL,
+                    Some(0x02) => TVSystem::Multiple,
+                    _ => TVSystem::NTSC,
+                }
+            }
+            [b'P', b'R', b'G', _] => {
+                if let Some(number) = number {
+                    prg_chunks[number] = chunk;
+                }
+            }
+            [b'C', b'H', b'R', _] => {
+                if let Some(number) = number {
+                    chr_chunks[number] = chunk;
+                }
+            }
+            // Other chunks (names, checksums, dumper info, ...) don't affect
+            // emulation.
+            _ => (),
+        }
+    }
+
+    let board = board.ok_or(RomError::MissingChunk("MAPR"))?;
+    let (mapper, submapper) =
+        lookup_board(&board).ok_or(RomError::UnknownBoard(board))?;
+
+    let mut rom_file = RomFile {
+        game_name,
+        nes_20: false,
+        trainer_data: None,
+        prg_rom_data: Vec::new(),
+        chr_rom_data: Vec::new(),
+        prg_ram_size: PRG_RAM_SIZE,
+        prg_nvram_size: 0,
+        chr_ram_size: 0,
+        chr_nvram_size: 0,
+        battery,
+        mirror_type,
+        tv_system,
+        vs_cart: false,
+        vs_system: None,
+        play_choice: false,
+        mapper,
+        submapper,
+        expansion_device: 0,
+        crc32: 0,
+        sha1: [0x00; DIGEST_SIZE],
+        warnings: Vec::new(),
+    };
+    rom_file.set_rom_data(&prg_chunks.concat(), &chr_chunks.concat());
+    Ok(rom_file)
+}
@@ -0,0 +1,90 @@
+use crate::rom::{MirrorType, RomError, RomFile, TVSystem};
+
+// Builds a UNIF file out of (ID, data) chunks.
+fn new_unif(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut file = b"UNIF".to_vec();
+    file.extend_from_slice(&7u32.to_le_bytes());
+    file.resize(0x20, 0x00);
+    for (id, data) in chunks {
+        file.extend_from_slice(*id);
+        file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend_from_slice(data);
+    }
+    file
+}
+
+#[test]
+fn test_unif() {
+    let prg0 = [0x01; 0x4000];
+    let prg1 = [0x02; 0x4000];
+    let chr0 = [0x03; 0x2000];
+    let file = new_unif(&[
+        (b"MAPR", b"NES-TLROM\0"),
+        (b"NAME", b"Test\0"),
+        (b"PRG1", &prg1),
+        (b"PRG0", &prg0),
+        (b"CHR0", &chr0),
+        (b"MIRR", &[0x04]),
+        (b"BATR", &[0x01]),
+        (b"TVCI", &[0x01]),
+    ]);
+    let rom = RomFile::new_from_buffer("test".to_string(), &file).unwrap();
+
+    assert_eq!(rom.mapper, 4);
+    // PRG chunks are put together in number order, not file order.
+    assert_eq!(rom.prg_rom_data.len(), 2);
+    assert_eq!(rom.prg_rom_data[0][0], 0x01);
+    assert_eq!(rom.prg_rom_data[1][0], 0x02);
+    assert_eq!(rom.chr_rom_data.len(), 1);
+    assert_eq!(rom.chr_rom_data[0][0], 0x03);
+    assert_eq!(rom.mirror_type, MirrorType::Both);
+    assert!(rom.battery);
+    assert_eq!(rom.tv_system, TVSystem::PAL);
+}
+
+#[test]
+fn test_unif_boards() {
+    let prg0 = [0x00; 0x8000];
+    let board = |name: &[u8]| {
+        let file = new_unif(&[(b"MAPR", name), (b"PRG0", &prg0)]);
+        RomFile::new_from_buffer("test".to_string(), &file)
+            .map(|rom| (rom.mapper, rom.submapper))
+    };
+
+    assert_eq!(board(b"NES-NROM-256\0").unwrap(), (0, 0));
+    assert_eq!(board(b"HVC-SNROM").unwrap(), (1, 0));
+    assert_eq!(board(b"NES-UOROM\0").unwrap(), (2, 0));
+    assert_eq!(board(b"NES-AMROM\0").unwrap(), (7, 2));
+    assert_eq!(board(b"AVE-NINA-01\0").unwrap(), (34, 1));
+    assert_eq!(board(b"UNL-SA-NROM\0").unwrap(), (143, 0));
+    assert_eq!(board(b"UNL-Sachen-8259A\0").unwrap(), (141, 0));
+    assert_eq!(board(b"BMC-Ghostbusters63in1\0").unwrap(), (226, 0));
+    assert_eq!(board(b"BMC-MINDKIDS\0").unwrap(), (268, 1));
+
+    let error = board(b"UNL-SOMETHING\0").err().unwrap();
+    assert!(matches!(error, RomError::UnknownBoard(_)));
+    assert_eq!(
+        error.to_string(),
+        "Unsupported UNIF board \"UNL-SOMETHING\": no known mapper"
+    );
+}
+
+#[test]
+fn test_invalid_unif() {
+    let file = new_unif(&[(b"PRG0", &[0x00; 0x4000])]);
+    let error = RomFile::new_from_buffer("test".to_string(), &file)
+        .err()
+        .unwrap();
+    assert!(matches!(error, RomError::MissingChunk("MAPR")));
+
+    let mut file =
+        new_unif(&[(b"MAPR", b"NES-NROM\0"), (b"PRG0", &[0x00; 0x10])]);
+    file.truncate(file.len() - 4);
+    let error = RomFile::new_from_buffer("test".to_string(), &file)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "PRG0 chunk truncated: expected 16 bytes, found 12"
+    );
+}
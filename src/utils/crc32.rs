@@ -0,0 +1,54 @@
+// CRC-32 as used by zip, PNG, and ROM databases and patch formats (reflected
+// polynomial 0xedb88320).
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 == 1 {
+                (value >> 1) ^ POLYNOMIAL
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[index] = value;
+        index += 1;
+    }
+    table
+}
+
+// Computes a CRC-32 over data that arrives in several pieces.
+pub struct Crc32 {
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { value: 0xffff_ffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            let index = (self.value ^ u32::from(*byte)) & 0xff;
+            self.value = (self.value >> 8) ^ TABLE[index as usize];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.value
+    }
+}
+
+// Computes the CRC-32 of "data".
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
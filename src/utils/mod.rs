@@ -1,3 +1,5 @@
 pub mod arithmetic;
+pub mod crc32;
 pub mod io;
 pub mod paging;
+pub mod sha1;
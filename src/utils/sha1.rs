@@ -0,0 +1,103 @@
+// SHA-1, as used by ROM databases to tell apart dumps whose CRC-32s collide.
+pub const DIGEST_SIZE: usize = 20;
+
+const BLOCK_SIZE: usize = 64;
+
+// Computes a SHA-1 digest over data that arrives in several pieces.
+pub struct Sha1 {
+    state: [u32; 5],
+    // Data that doesn't fill a whole block yet.
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+    // Total length of the data so far, in bytes.
+    length: u64,
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: [
+                0x6745_2301,
+                0xefcd_ab89,
+                0x98ba_dcfe,
+                0x1032_5476,
+                0xc3d2_e1f0,
+            ],
+            block: [0x00; BLOCK_SIZE],
+            block_len: 0,
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let size = data.len().min(BLOCK_SIZE - self.block_len);
+            self.block[self.block_len..self.block_len + size]
+                .copy_from_slice(&data[..size]);
+            self.block_len += size;
+            data = &data[size..];
+            if self.block_len == BLOCK_SIZE {
+                self.process_block();
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; DIGEST_SIZE] {
+        // Pad with a 1 bit, then 0s up to the last 8 bytes of a block, which
+        // hold the length in bits.
+        let bit_length = self.length * 8;
+        self.update(&[0x80]);
+        while self.block_len != BLOCK_SIZE - 8 {
+            self.update(&[0x00]);
+        }
+        self.update(&bit_length.to_be_bytes());
+
+        let mut digest = [0x00; DIGEST_SIZE];
+        for (bytes, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn process_block(&mut self) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(self.block.chunks(4)) {
+            *word =
+                u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3]
+                ^ words[index - 8]
+                ^ words[index - 14]
+                ^ words[index - 16])
+                .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+        self.block_len = 0;
+    }
+}